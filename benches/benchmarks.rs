@@ -0,0 +1,160 @@
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
+use encrypted_box::batch::{Batch, Format};
+use encrypted_box::encrypted_box::EncryptedBox;
+use encrypted_box::encrypted_box_builder::EncryptedBoxBuilder;
+use encrypted_box::encryption_scheme::{DefaultScheme, EncryptionScheme};
+use encrypted_box::kdf::{Kdf, KdfParams};
+use encrypted_box::openssl_aes::defs::OpenSslVariants;
+use encrypted_box::subkeys::MASTER_KEY_LEN;
+use std::fmt::Display;
+
+const PASSWORD: &str = "password";
+
+/// payload sizes from a single block to a large file
+const SIZES: &[usize] = &[16, 1024, 64 * 1024, 1024 * 1024, 64 * 1024 * 1024];
+
+/// a box to seal payloads with, keyed with a cheap KDF
+fn sealer<T>(variant: &OpenSslVariants) -> EncryptedBox<T>
+where
+    T: EncryptionScheme<Variant = OpenSslVariants> + Clone,
+    T::Error: Display,
+{
+    let scheme = T::new(variant);
+    let params = KdfParams::new(Kdf::Pbkdf2Sha256 { iterations: 1 });
+    let key = params.derive(PASSWORD, MASTER_KEY_LEN);
+    EncryptedBox::new(vec![], key, params, scheme)
+}
+
+fn human(size: usize) -> String {
+    match size {
+        s if s >= 1024 * 1024 => format!("{}MiB", s / (1024 * 1024)),
+        s if s >= 1024 => format!("{}KiB", s / 1024),
+        s => format!("{}B", s),
+    }
+}
+
+/// every scheme of every enabled backend across payload sizes
+fn schemes(c: &mut Criterion) {
+    #[cfg(feature = "openssl")]
+    backend_schemes::<encrypted_box::openssl_aes::wrapper::OpensslAesWrapper>(c, "openssl");
+    #[cfg(feature = "rustcrypto")]
+    backend_schemes::<encrypted_box::rustcrypto_aes::wrapper::RustCryptoAesWrapper>(
+        c,
+        "rustcrypto",
+    );
+}
+
+fn backend_schemes<T>(c: &mut Criterion, backend: &str)
+where
+    T: EncryptionScheme<Variant = OpenSslVariants> + Clone,
+    T::Error: Display,
+{
+    for variant in OpenSslVariants::iterator() {
+        let mut group = c.benchmark_group(format!("encrypt/{}/{}", backend, variant));
+        let eb = sealer::<T>(variant);
+        for &size in SIZES {
+            if size >= 1024 * 1024 {
+                group.sample_size(10);
+            }
+            let fields = vec![vec![0u8; size]];
+            group.throughput(Throughput::Bytes(size as u64));
+            group.bench_with_input(BenchmarkId::from_parameter(human(size)), &fields, |b, f| {
+                b.iter(|| eb.encrypt_fields(black_box(f)).unwrap())
+            });
+        }
+        group.finish();
+    }
+}
+
+/// what the builder costs on top of the encryption: add_fields and build
+/// (which clones fields, key and cipher) or into_box (which moves them),
+/// for many small or a few large fields
+fn builder(c: &mut Criterion) {
+    let mut group = c.benchmark_group("builder");
+    let variant = OpenSslVariants::Aes256Gcm;
+    for &(count, size) in &[(1, 16), (1000, 16), (1, 1024 * 1024), (16, 1024 * 1024)] {
+        let fields: Vec<String> = (0..count).map(|_| "x".repeat(size)).collect();
+        group.throughput(Throughput::Bytes((count * size) as u64));
+        let id = format!("{}x{}", count, human(size));
+        let keyed = || {
+            let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variant));
+            ebb.set_kdf(Kdf::Pbkdf2Sha256 { iterations: 1 })
+                .set_password(String::from(PASSWORD));
+            ebb
+        };
+        group.bench_with_input(BenchmarkId::new("build", &id), &fields, |b, fields| {
+            b.iter_batched(
+                keyed,
+                |mut ebb| ebb.add_fields(&fields[..]).build().unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+        group.bench_with_input(BenchmarkId::new("into_box", &id), &fields, |b, fields| {
+            b.iter_batched(
+                keyed,
+                |mut ebb| {
+                    ebb.add_fields(&fields[..]);
+                    ebb.into_box().unwrap()
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// the KDF across its parameters
+fn kdf(c: &mut Criterion) {
+    let mut group = c.benchmark_group("kdf");
+    group.sample_size(10);
+    group.bench_function("sha512", |b| {
+        b.iter(|| Kdf::Sha512.derive(black_box(PASSWORD), &[0u8; 16], 32))
+    });
+    for &iterations in &[1_000, 10_000, 100_000, 1_000_000] {
+        let kdf = Kdf::Pbkdf2Sha256 { iterations };
+        group.bench_with_input(
+            BenchmarkId::new("pbkdf2-sha256", iterations),
+            &kdf,
+            |b, kdf| b.iter(|| kdf.derive(black_box(PASSWORD), &[0u8; 16], 32)),
+        );
+    }
+    group.finish();
+}
+
+/// records streamed through batch mode, per cell and per row
+fn streaming(c: &mut Criterion) {
+    const RECORDS: usize = 10_000;
+    let mut input = String::from("id,email,ssn\n");
+    for i in 0..RECORDS {
+        input.push_str(&format!("{},user{}@example.com,{:09}\n", i, i, i));
+    }
+    let eb = sealer::<DefaultScheme>(&OpenSslVariants::Aes256Gcm);
+
+    let mut group = c.benchmark_group("batch");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(RECORDS as u64));
+    for &per_row in &[false, true] {
+        let name = if per_row {
+            "csv/per-row"
+        } else {
+            "csv/per-cell"
+        };
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let mut batch = Batch::new(Format::Csv);
+                batch.add_columns(&["email", "ssn"]);
+                if per_row {
+                    batch.per_row("encrypted_box");
+                }
+                let mut output = Vec::with_capacity(4 * input.len());
+                batch.encrypt(&eb, input.as_bytes(), &mut output).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, schemes, builder, kdf, streaming);
+criterion_main!(benches);
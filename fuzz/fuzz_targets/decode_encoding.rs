@@ -0,0 +1,15 @@
+// decoding any bytes, in any encoding or with the encoding detected,
+// never panics
+#![no_main]
+use encrypted_box::encoding::{self, Encoding};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    for encoding in Encoding::iterator() {
+        let _ = encoding::decode(data, *encoding);
+    }
+    let _ = encoding::decode_auto(data);
+    if let Ok(text) = std::str::from_utf8(data) {
+        let _ = encoding::dearmor(text);
+    }
+});
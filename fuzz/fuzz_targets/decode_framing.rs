@@ -0,0 +1,11 @@
+// decoding any bytes as framed fields never panics, and whatever decodes
+// encodes back to the same bytes
+#![no_main]
+use encrypted_box::framing;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(fields) = framing::decode(data) {
+        assert_eq!(framing::encode(&fields), data);
+    }
+});
@@ -0,0 +1,32 @@
+// opening any bytes as an encrypted box never panics, with either backend
+#![no_main]
+use encrypted_box::encrypted_box::EncryptedBox;
+use encrypted_box::encryption_scheme::SecurityLevel;
+use encrypted_box::kdf::KeyCache;
+use encrypted_box::openssl_aes::wrapper::OpensslAesWrapper;
+use encrypted_box::policy::DecryptPolicy;
+use encrypted_box::registry::SchemeRegistry;
+use encrypted_box::rustcrypto_aes::wrapper::RustCryptoAesWrapper;
+use libfuzzer_sys::fuzz_target;
+
+const PASSWORD: &str = "password";
+
+fuzz_target!(|data: &[u8]| {
+    // accept every scheme and KDF, but keep key derivation cheap
+    let mut policy = DecryptPolicy::new();
+    policy
+        .min_security_level(SecurityLevel::Insecure)
+        .allow_kdf("sha512")
+        .min_kdf_cost(1)
+        .max_kdf_cost(16);
+
+    let mut keys = KeyCache::new(String::from(PASSWORD));
+    let _ = EncryptedBox::<OpensslAesWrapper>::decrypt_with_keys(data, &policy, &mut keys);
+    let _ = EncryptedBox::<RustCryptoAesWrapper>::decrypt_with_keys(data, &policy, &mut keys);
+    let _ = EncryptedBox::decrypt_with_registry(
+        data,
+        &policy,
+        &mut keys,
+        &SchemeRegistry::default(),
+    );
+});
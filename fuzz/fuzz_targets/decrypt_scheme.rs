@@ -0,0 +1,27 @@
+// decrypting any ciphertext with any scheme, of either backend, fails
+// or succeeds but never panics. The first byte picks the scheme
+#![no_main]
+use encrypted_box::encryption_scheme::EncryptionScheme;
+use encrypted_box::openssl_aes::defs::OpenSslVariants;
+use encrypted_box::openssl_aes::wrapper::OpensslAesWrapper;
+use encrypted_box::rustcrypto_aes::wrapper::RustCryptoAesWrapper;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let (selector, ctext) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let variants: Vec<_> = OpenSslVariants::iterator().collect();
+    let variant = variants[*selector as usize % variants.len()];
+    let key = vec![0x42; variant.key_length()];
+
+    let openssl = OpensslAesWrapper::new(variant).decrypt(&key, ctext);
+    let rustcrypto = RustCryptoAesWrapper::new(variant).decrypt(&key, ctext);
+    // the backends must agree on what they accept
+    match (openssl, rustcrypto) {
+        (Ok(a), Ok(b)) => assert_eq!(a, b, "{}", variant),
+        (Err(_), Err(_)) => {}
+        (a, b) => panic!("{}: openssl {:?}, rustcrypto {:?}", variant, a.is_ok(), b.is_ok()),
+    }
+});
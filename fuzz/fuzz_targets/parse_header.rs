@@ -0,0 +1,13 @@
+// parsing any bytes as a header never panics, and whatever parses
+// serializes back to the bytes it was parsed from
+#![no_main]
+use encrypted_box::header::Header;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((header, rest)) = Header::parse(data) {
+        let bytes = header.to_bytes().expect("a parsed header serializes");
+        assert_eq!(bytes.len(), header.encoded_len());
+        assert_eq!(&bytes[..], &data[..data.len() - rest.len()]);
+    }
+});
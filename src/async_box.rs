@@ -0,0 +1,288 @@
+use crate::encrypted_box::{EncryptedBox, Fields};
+use crate::encrypted_box_builder::EncryptedBoxBuilder;
+use crate::encryption_scheme::EncryptionScheme;
+use crate::errors::{Error, Result};
+use crate::kdf::Kdf;
+use crate::policy::DecryptPolicy;
+use exitfailure::ExitFailure;
+use std::fmt::Display;
+use std::panic;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::task;
+
+// Async counterparts of the builder and of sealing and opening boxes, for
+// tokio services (behind the `async` feature).
+//
+// Key derivation is slow by design and encrypting a large box takes a while
+// too, so both run on tokio's blocking pool (`spawn_blocking`) rather than
+// stalling the executor.
+//
+// A box authenticates all of its fields at once, so the streaming functions
+// read the whole payload before sealing (or opening) it and then write the
+// result out. Wrap untrusted readers in `AsyncReadExt::take` to bound that.
+
+/// An async builder for an encrypted box (see `EncryptedBoxBuilder`).
+/// The key is derived by `build`, on the blocking pool
+pub struct AsyncEncryptedBoxBuilder<T> {
+    builder: EncryptedBoxBuilder<T>,
+    password: Option<String>,
+}
+
+impl<T> AsyncEncryptedBoxBuilder<T>
+where
+    T: EncryptionScheme + Clone + Send + 'static,
+    T::Error: Display,
+{
+    /// create a new builder with a given cipher
+    pub fn new(cipher: T) -> AsyncEncryptedBoxBuilder<T> {
+        AsyncEncryptedBoxBuilder {
+            builder: EncryptedBoxBuilder::new(cipher),
+            password: None,
+        }
+    }
+
+    /// add a field to the encryption data
+    pub fn add_field<F>(&mut self, field: F) -> &mut AsyncEncryptedBoxBuilder<T>
+    where
+        F: ToString,
+    {
+        self.builder.add_field(field);
+        self
+    }
+
+    /// add multiple fields at once
+    pub fn add_fields<F>(&mut self, fields: &[F]) -> &mut AsyncEncryptedBoxBuilder<T>
+    where
+        F: ToString + Display,
+    {
+        self.builder.add_fields(fields);
+        self
+    }
+
+    /// set a password, the key is derived of it by `build`
+    pub fn set_password(&mut self, password: String) -> &mut AsyncEncryptedBoxBuilder<T> {
+        self.password = Some(password);
+        self
+    }
+
+    /// set the key derivation function (PBKDF2-HMAC-SHA256 by default)
+    pub fn set_kdf(&mut self, kdf: Kdf) -> &mut AsyncEncryptedBoxBuilder<T> {
+        self.builder.set_kdf(kdf);
+        self
+    }
+
+    /// allow building boxes with insecure or unauthenticated schemes
+    /// (see `EncryptedBoxBuilder::allow_insecure`)
+    pub fn allow_insecure(&mut self) -> &mut AsyncEncryptedBoxBuilder<T> {
+        self.builder.allow_insecure();
+        self
+    }
+
+    /// derive the key and generate a new EncryptedBox, off the executor
+    pub async fn build(self) -> std::result::Result<EncryptedBox<T>, ExitFailure> {
+        let AsyncEncryptedBoxBuilder {
+            mut builder,
+            password,
+        } = self;
+        run_blocking(move || {
+            if let Some(password) = password {
+                builder.set_password(password);
+            }
+            builder.into_box()
+        })
+        .await?
+    }
+}
+
+/// encrypt fields with the key of eb (see `EncryptedBox::encrypt_fields`),
+/// off the executor
+pub async fn encrypt_fields<T>(eb: Arc<EncryptedBox<T>>, fields: Fields) -> Result<Vec<u8>>
+where
+    T: EncryptionScheme + Clone + Send + Sync + 'static,
+    T::Error: Display,
+{
+    run_blocking(move || eb.encrypt_fields(&fields)).await?
+}
+
+/// read everything from reader and write a box holding it as its only field
+/// to writer, e.g. an HTTP response body
+pub async fn encrypt_stream<T, R, W>(
+    eb: Arc<EncryptedBox<T>>,
+    mut reader: R,
+    mut writer: W,
+) -> Result<()>
+where
+    T: EncryptionScheme + Clone + Send + Sync + 'static,
+    T::Error: Display,
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut payload = Vec::new();
+    reader.read_to_end(&mut payload).await.map_err(Error::io)?;
+    let sealed = encrypt_fields(eb, vec![payload]).await?;
+    writer.write_all(&sealed).await.map_err(Error::io)?;
+    writer.flush().await.map_err(Error::io)
+}
+
+/// decrypt ciphertext (see `EncryptedBox::decrypt`), off the executor
+pub async fn decrypt<T>(
+    password: String,
+    ciphertext: Vec<u8>,
+    policy: DecryptPolicy,
+) -> Result<EncryptedBox<T>>
+where
+    T: EncryptionScheme + Clone + Send + 'static,
+    T::Error: Display,
+    T::Variant: FromStr,
+{
+    run_blocking(move || EncryptedBox::decrypt(password, &ciphertext, &policy)).await?
+}
+
+/// read a box from reader and write its only field to writer,
+/// the counterpart of `encrypt_stream`
+pub async fn decrypt_stream<T, R, W>(
+    password: String,
+    policy: DecryptPolicy,
+    mut reader: R,
+    mut writer: W,
+) -> Result<()>
+where
+    T: EncryptionScheme + Clone + Send + 'static,
+    T::Error: Display,
+    T::Variant: FromStr,
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut ciphertext = Vec::new();
+    reader
+        .read_to_end(&mut ciphertext)
+        .await
+        .map_err(Error::io)?;
+    let eb = decrypt::<T>(password, ciphertext, policy).await?;
+    match eb.fields() {
+        [payload] => {
+            writer.write_all(payload).await.map_err(Error::io)?;
+            writer.flush().await.map_err(Error::io)
+        }
+        fields => Err(Error::malformed(format!(
+            "expected a box of a single field, found {} fields",
+            fields.len()
+        ))),
+    }
+}
+
+/// run f on the blocking pool, passing on its panics
+async fn run_blocking<F, R>(f: F) -> Result<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    match task::spawn_blocking(f).await {
+        Ok(result) => Ok(result),
+        Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(Error::io(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption_scheme::DefaultScheme;
+    use crate::errors::ErrorKind;
+    use crate::openssl_aes::defs::OpenSslVariants as variants;
+
+    const PASSWORD: &str = "password";
+    const TEST_KDF: Kdf = Kdf::Pbkdf2Sha256 { iterations: 1000 };
+
+    /// the default policy, accepting TEST_KDF
+    fn policy() -> DecryptPolicy {
+        let mut policy = DecryptPolicy::default();
+        policy.min_kdf_cost(1000);
+        policy
+    }
+
+    async fn sealer() -> Arc<EncryptedBox<DefaultScheme>> {
+        let mut ebb = AsyncEncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes256Gcm));
+        ebb.set_kdf(TEST_KDF).set_password(String::from(PASSWORD));
+        Arc::new(ebb.build().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn build_encrypt_decrypt() -> Result<()> {
+        let mut ebb =
+            AsyncEncryptedBoxBuilder::new(DefaultScheme::new(&variants::ChaCha20Poly1305));
+        ebb.set_kdf(TEST_KDF)
+            .set_password(String::from(PASSWORD))
+            .add_fields(&["a", "b"]);
+        let eb = ebb.build().await.unwrap();
+        let sealed = eb.encrypt()?;
+
+        let dec_eb: EncryptedBox<DefaultScheme> =
+            decrypt(String::from(PASSWORD), sealed, policy()).await?;
+        assert_eq!(dec_eb.fields(), eb.fields());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn build_without_password_fails() {
+        let ebb = AsyncEncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes256Gcm));
+        assert!(ebb.build().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn build_refuses_insecure_schemes_by_default() {
+        let mut ebb = AsyncEncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes128Ecb));
+        ebb.set_kdf(TEST_KDF).set_password(String::from(PASSWORD));
+        assert!(ebb.build().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn stream_round_trip() -> Result<()> {
+        let payload = vec![7u8; 100_000];
+        let mut sealed = Vec::new();
+        encrypt_stream(sealer().await, &payload[..], &mut sealed).await?;
+        assert_eq!(&sealed[..4], b"EBOX");
+
+        let mut opened = Vec::new();
+        decrypt_stream::<DefaultScheme, _, _>(
+            String::from(PASSWORD),
+            policy(),
+            &sealed[..],
+            &mut opened,
+        )
+        .await?;
+        assert_eq!(opened, payload);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn decrypt_stream_wants_a_single_field() -> Result<()> {
+        let fields = vec![b"a".to_vec(), b"b".to_vec()];
+        let sealed = encrypt_fields(sealer().await, fields).await?;
+        let mut opened = Vec::new();
+        let result = decrypt_stream::<DefaultScheme, _, _>(
+            String::from(PASSWORD),
+            policy(),
+            &sealed[..],
+            &mut opened,
+        )
+        .await;
+        match result {
+            Err(e) => assert!(matches!(e.kind(), ErrorKind::MalformedBox(_))),
+            Ok(_) => panic!("opened a box of two fields"),
+        }
+        assert!(opened.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn decrypt_with_wrong_password_fails() -> Result<()> {
+        let sealed = encrypt_fields(sealer().await, vec![b"field".to_vec()]).await?;
+        let dec =
+            decrypt::<DefaultScheme>(String::from("not the password"), sealed, policy()).await;
+        assert!(dec.is_err());
+        Ok(())
+    }
+}
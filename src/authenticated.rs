@@ -0,0 +1,373 @@
+use crate::encryption_scheme::{EncryptionScheme, SecurityLevel};
+use crate::errors::{Error, Result};
+use crate::subkeys::{MasterKey, Purpose};
+use hmac::crypto_mac::generic_array::typenum::Unsigned;
+use hmac::crypto_mac::generic_array::GenericArray;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::{Sha256, Sha512};
+use std::fmt::Display;
+use std::marker::PhantomData;
+
+// `Authenticated` turns any scheme into an authenticated one by encrypt-then-MAC:
+//
+//   nonce      16 random bytes per message
+//   enc key    the content encryption subkey of the box key for the nonce
+//              (see subkeys.rs)
+//   mac key    the MAC subkey of the box key
+//   output     nonce || ciphertext || MAC(mac key, aad || nonce || ciphertext || aad bits as u64)
+//
+// where ciphertext is the scheme's, and aad the associated data (the header
+// of the box, see encryption_scheme.rs). Many schemes encrypt with a fixed IV
+// (e.g. CBC and CTR, see openssl_aes/defs.rs), which under one key would
+// repeat a CTR keystream or make CBC deterministic; with a key per nonce,
+// no two messages share key and IV.
+// The subkeys depend on the identifier (e.g. "aes-256-cbc+hmac-sha256"), so
+// they differ between every scheme and MAC. The tag is checked in constant
+// time before anything is decrypted, and every failure after that is the same
+// opaque error, so the scheme's errors (e.g. bad padding) cannot serve as an
+// oracle. The built-in encrypt-then-MAC variants (see openssl_aes/etm.rs) are
+// the same idea with a fixed format, for boxes any backend opens.
+
+/// the length of the box key, which the subkeys are derived from
+pub const KEY_LEN: usize = 32;
+
+/// the length of the random nonce prepended to every ciphertext
+pub const NONCE_LEN: usize = 16;
+
+/// A MAC with a name, which becomes part of the identifier of the schemes it
+/// authenticates
+pub trait NamedMac: Mac {
+    const NAME: &'static str;
+}
+
+impl NamedMac for Hmac<Sha256> {
+    const NAME: &'static str = "hmac-sha256";
+}
+
+impl NamedMac for Hmac<Sha512> {
+    const NAME: &'static str = "hmac-sha512";
+}
+
+/// Scheme S with its ciphertexts authenticated by MAC M, e.g.
+/// `Authenticated<OpensslAesWrapper, Hmac<Sha256>>`
+pub struct Authenticated<S, M> {
+    scheme: S,
+    identifier: String,
+    mac: PhantomData<fn() -> M>,
+}
+
+impl<S, M> Authenticated<S, M>
+where
+    S: EncryptionScheme,
+    S::Error: Display,
+    M: NamedMac,
+{
+    /// authenticate the ciphertexts of scheme
+    pub fn wrap(scheme: S) -> Authenticated<S, M> {
+        let identifier = format!("{}+{}", scheme.identifier(), M::NAME);
+        Authenticated {
+            scheme,
+            identifier,
+            mac: PhantomData,
+        }
+    }
+
+    /// the scheme encrypting the messages
+    pub fn inner(&self) -> &S {
+        &self.scheme
+    }
+
+    fn check_key_len(&self, key_len: usize) -> Result<()> {
+        if key_len != KEY_LEN {
+            return Err(Error::scheme(format!(
+                "key length mismatch: expected {} but got {}",
+                KEY_LEN, key_len
+            )));
+        }
+        Ok(())
+    }
+
+    /// derive a subkey of len bytes for purpose from the box key
+    fn subkey(&self, key: &[u8], purpose: Purpose, len: usize) -> Vec<u8> {
+        MasterKey::new(key).subkey(purpose, &self.identifier, len)
+    }
+
+    /// the key the scheme encrypts the message sealed with nonce under
+    fn content_key(&self, key: &[u8], nonce: &[u8]) -> Vec<u8> {
+        MasterKey::new(key).subkey(
+            Purpose::ContentEncryption,
+            &format!("{} {}", self.identifier, hex::encode(nonce)),
+            self.scheme.get_key_length(),
+        )
+    }
+
+    // sealed is the nonce and the scheme's ciphertext
+    fn mac(&self, key: &[u8], aad: &[u8], sealed: &[u8]) -> M {
+        let mac_key = self.subkey(key, Purpose::Mac, M::KeySize::to_usize());
+        let mut mac = M::new(GenericArray::from_slice(&mac_key));
+        mac.input(aad);
+        mac.input(sealed);
+        mac.input(&(aad.len() as u64 * 8).to_be_bytes());
+        mac
+    }
+}
+
+impl<S: Clone, M> Clone for Authenticated<S, M> {
+    fn clone(&self) -> Authenticated<S, M> {
+        Authenticated {
+            scheme: self.scheme.clone(),
+            identifier: self.identifier.clone(),
+            mac: PhantomData,
+        }
+    }
+}
+
+/// See encryption_scheme.rs
+impl<S, M> EncryptionScheme for Authenticated<S, M>
+where
+    S: EncryptionScheme,
+    S::Error: Display,
+    M: NamedMac,
+{
+    type Error = Error;
+
+    /// variant type is the variant of the scheme
+    type Variant = S::Variant;
+
+    fn new(v: &S::Variant) -> Authenticated<S, M> {
+        Authenticated::wrap(S::new(v))
+    }
+
+    fn identifier(&self) -> &str {
+        &self.identifier
+    }
+
+    fn get_key_length(&self) -> usize {
+        KEY_LEN
+    }
+
+    // a MAC does not hide the equal blocks of ECB, which cannot be told apart
+    // from the other insecure schemes
+    fn security_level(&self) -> SecurityLevel {
+        match self.scheme.security_level() {
+            SecurityLevel::Insecure => SecurityLevel::Insecure,
+            _ => SecurityLevel::Authenticated,
+        }
+    }
+
+    fn encrypt(&self, key: &[u8], msg: &[u8]) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        self.encrypt_with_aad_into(key, &[], msg, &mut out)?;
+        Ok(out)
+    }
+
+    fn decrypt(&self, key: &[u8], ctext: &[u8]) -> Result<Vec<u8>> {
+        self.decrypt_with_aad(key, &[], ctext)
+    }
+
+    fn encrypt_with_aad_into(
+        &self,
+        key: &[u8],
+        aad: &[u8],
+        msg: &[u8],
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
+        self.check_key_len(key.len())?;
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::rngs::OsRng.fill_bytes(&mut nonce);
+        let enc_key = self.content_key(key, &nonce);
+        let enc = self.scheme.encrypt(&enc_key, msg).map_err(Error::scheme)?;
+        let mut sealed = Vec::with_capacity(NONCE_LEN + enc.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend(enc);
+        let tag = self.mac(key, aad, &sealed).result().code();
+        out.reserve(sealed.len() + tag.len());
+        out.extend(sealed);
+        out.extend_from_slice(&tag);
+        Ok(())
+    }
+
+    fn decrypt_with_aad(&self, key: &[u8], aad: &[u8], ctext: &[u8]) -> Result<Vec<u8>> {
+        self.check_key_len(key.len())?;
+        let tag_len = M::OutputSize::to_usize();
+        if ctext.len() < NONCE_LEN + tag_len {
+            return Err(failed());
+        }
+        let (sealed, tag) = ctext.split_at(ctext.len() - tag_len);
+        // verify compares in constant time
+        self.mac(key, aad, sealed)
+            .verify(tag)
+            .map_err(|_| failed())?;
+        let (nonce, enc) = sealed.split_at(NONCE_LEN);
+        let enc_key = self.content_key(key, nonce);
+        self.scheme.decrypt(&enc_key, enc).map_err(|_| failed())
+    }
+}
+
+fn failed() -> Error {
+    Error::scheme("decryption failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encrypted_box::EncryptedBox;
+    use crate::encrypted_box_builder::EncryptedBoxBuilder;
+    use crate::encryption_scheme::DefaultScheme;
+    use crate::errors::ErrorKind;
+    use crate::kdf::{Kdf, KeyCache};
+    use crate::openssl_aes::defs::OpenSslVariants as variants;
+    use crate::policy::DecryptPolicy;
+    use crate::registry::SchemeRegistry;
+    use exitfailure::ExitFailure;
+
+    type HmacSha256<S> = Authenticated<S, Hmac<Sha256>>;
+
+    const KEY: [u8; KEY_LEN] = [7; KEY_LEN];
+    const MSG: &[u8] = b"This is a message that spans more than two AES blocks";
+
+    #[test]
+    fn round_trip_all_variants() -> Result<()> {
+        for variant in variants::iterator() {
+            let scheme = HmacSha256::<DefaultScheme>::new(variant);
+            let enc = scheme.encrypt(&KEY, MSG)?;
+            assert_eq!(scheme.decrypt(&KEY, &enc)?, MSG, "{}", variant);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn identifier_names_scheme_and_mac() {
+        let scheme = HmacSha256::<DefaultScheme>::new(&variants::Aes256Cbc);
+        assert_eq!(scheme.identifier(), "aes-256-cbc+hmac-sha256");
+        let scheme =
+            Authenticated::<_, Hmac<Sha512>>::wrap(DefaultScheme::new(&variants::Aes256Cbc));
+        assert_eq!(scheme.identifier(), "aes-256-cbc+hmac-sha512");
+    }
+
+    #[test]
+    fn security_level() {
+        for variant in variants::iterator() {
+            let expected = if variant.security_level() == SecurityLevel::Insecure {
+                SecurityLevel::Insecure
+            } else {
+                SecurityLevel::Authenticated
+            };
+            let scheme = HmacSha256::<DefaultScheme>::new(variant);
+            assert_eq!(scheme.security_level(), expected, "{}", variant);
+        }
+    }
+
+    #[test]
+    fn detects_tampering() -> Result<()> {
+        let scheme = HmacSha256::<DefaultScheme>::new(&variants::Aes128Ctr);
+        let enc = scheme.encrypt(&KEY, MSG)?;
+        assert_eq!(enc.len(), NONCE_LEN + MSG.len() + 32);
+        for i in 0..enc.len() {
+            let mut forged = enc.clone();
+            forged[i] ^= 1;
+            assert!(scheme.decrypt(&KEY, &forged).is_err());
+        }
+        assert!(scheme.decrypt(&KEY, &enc[..NONCE_LEN + 31]).is_err());
+        let mut other_key = KEY;
+        other_key[0] ^= 1;
+        assert!(scheme.decrypt(&other_key, &enc).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn authenticates_aad() -> Result<()> {
+        let scheme = HmacSha256::<DefaultScheme>::new(&variants::Aes128Ctr);
+        let mut enc = Vec::new();
+        scheme.encrypt_with_aad_into(&KEY, b"header", MSG, &mut enc)?;
+        assert_eq!(scheme.decrypt_with_aad(&KEY, b"header", &enc)?, MSG);
+        assert!(scheme.decrypt_with_aad(&KEY, b"headex", &enc).is_err());
+        assert!(scheme.decrypt(&KEY, &enc).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn equal_messages_encrypt_differently() -> Result<()> {
+        // the inner schemes encrypt with a fixed IV
+        for variant in &[variants::Aes256Cbc, variants::Aes256Ctr] {
+            let scheme = HmacSha256::<DefaultScheme>::new(variant);
+            assert!(!scheme.is_deterministic());
+            let first = scheme.encrypt(&KEY, MSG)?;
+            let second = scheme.encrypt(&KEY, MSG)?;
+            assert_ne!(first[NONCE_LEN..], second[NONCE_LEN..], "{}", variant);
+            assert_eq!(scheme.decrypt(&KEY, &second)?, MSG);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn subkeys_are_independent() {
+        let cbc = HmacSha256::<DefaultScheme>::new(&variants::Aes256Cbc);
+        let ctr = HmacSha256::<DefaultScheme>::new(&variants::Aes256Ctr);
+        let enc_key = cbc.subkey(&KEY, Purpose::ContentEncryption, 32);
+        let mac_key = cbc.subkey(&KEY, Purpose::Mac, 32);
+        assert_ne!(enc_key, mac_key);
+        assert_ne!(&enc_key[..], &KEY[..]);
+        assert_ne!(enc_key, ctr.subkey(&KEY, Purpose::ContentEncryption, 32));
+    }
+
+    #[test]
+    fn failures_are_opaque() -> Result<()> {
+        let scheme = HmacSha256::<DefaultScheme>::new(&variants::Aes128Cbc);
+        // a valid tag over a nonce and a block without valid padding
+        let garbage = vec![0x55u8; NONCE_LEN + 16];
+        let mut bad_padding = garbage.clone();
+        bad_padding.extend_from_slice(&scheme.mac(&KEY, &[], &garbage).result().code());
+        let (nonce, enc) = garbage.split_at(NONCE_LEN);
+        let enc_key = scheme.content_key(&KEY, nonce);
+        assert!(scheme.inner().decrypt(&enc_key, enc).is_err());
+
+        let mut bad_tag = scheme.encrypt(&KEY, MSG)?;
+        bad_tag[0] ^= 1;
+        for ctext in &[bad_padding, bad_tag, vec![0u8; 3]] {
+            let error = scheme.decrypt(&KEY, ctext).unwrap_err();
+            assert_eq!(error.kind(), failed().kind());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn key_length_mismatch() {
+        let scheme = HmacSha256::<DefaultScheme>::new(&variants::Aes128Ctr);
+        assert!(scheme.encrypt(&KEY[..16], MSG).is_err());
+        match scheme.decrypt(&KEY[..16], MSG).unwrap_err().kind() {
+            ErrorKind::SchemeError(msg) => assert!(msg.contains("key length")),
+            kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn retrofits_unauthenticated_boxes() -> std::result::Result<(), ExitFailure> {
+        let scheme = HmacSha256::<DefaultScheme>::new(&variants::Aes256Cbc);
+        let mut ebb = EncryptedBoxBuilder::new(scheme.clone());
+        let built = ebb
+            .set_kdf(Kdf::Pbkdf2Sha256 { iterations: 1000 })
+            .set_password(String::from("password"))
+            .add_fields(&["a", "b"])
+            .build()?;
+        let sealed = built.encrypt()?;
+        // sealing the same fields under the same key again differs, despite
+        // the fixed IV of CBC
+        assert_ne!(sealed, built.encrypt()?);
+
+        // accepted by the default policy, as it is authenticated
+        let mut policy = DecryptPolicy::default();
+        policy.min_kdf_cost(1000);
+        let mut registry = SchemeRegistry::default();
+        registry.register_scheme(scheme);
+        let eb = EncryptedBox::decrypt_with_registry(
+            &sealed,
+            &policy,
+            &mut KeyCache::new(String::from("password")),
+            &registry,
+        )?;
+        assert_eq!(eb.fields(), &[b"a".to_vec(), b"b".to_vec()][..]);
+        Ok(())
+    }
+}
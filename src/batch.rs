@@ -0,0 +1,835 @@
+use crate::encoding::{self, Encoding};
+use crate::encrypted_box::{EncryptedBox, Fields};
+use crate::encryption_scheme::EncryptionScheme;
+use crate::errors::{Error, Result};
+use crate::kdf::KeyCache;
+use crate::policy::DecryptPolicy;
+use crate::registry::SchemeRegistry;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+// Batch mode encrypts (or decrypts) selected columns of every record of a
+// CSV file (with a header row) or of a JSON Lines file (one object per line).
+//
+// Per cell, every selected value is replaced by a box holding that value.
+// Per row, the selected values of a record are sealed into a single box,
+// stored in an extra column, with the fields of the box alternating between
+// column name and value. CSV keeps the (emptied) selected columns, so
+// decryption can put the values back where they were.
+//
+// Records are read in chunks of CHUNK_SIZE, and the boxes of a chunk are
+// sealed in parallel, keeping the order of the records.
+//
+// JSON values are encrypted as JSON text, so numbers, objects etc. survive
+// the round trip. Records that fail are left out of the output and listed
+// in the `BatchReport` instead of aborting the whole run.
+
+/// the number of records read (and sealed in parallel) at a time
+const CHUNK_SIZE: usize = 1024;
+
+/// The record formats batch mode reads and writes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// comma separated values, the first row naming the columns
+    Csv,
+    /// JSON Lines, one JSON object per line
+    Jsonl,
+}
+
+impl Format {
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Jsonl => "jsonl",
+        }
+    }
+
+    /// guess the format from a file extension (.csv, .jsonl or .ndjson)
+    pub fn from_path(path: &Path) -> Option<Format> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match &extension[..] {
+            "csv" => Some(Format::Csv),
+            "jsonl" | "ndjson" => Some(Format::Jsonl),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Format> {
+        match &s.to_ascii_lowercase()[..] {
+            "csv" => Ok(Format::Csv),
+            "jsonl" | "ndjson" => Ok(Format::Jsonl),
+            _ => Err(Error::batch(format!("unknown format '{}'", s))),
+        }
+    }
+}
+
+/// A record that could not be encrypted (or decrypted)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordError {
+    /// the number of the record, starting at 1 (not counting a CSV header)
+    pub record: usize,
+    pub message: String,
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "record {}: {}", self.record, self.message)
+    }
+}
+
+/// The outcome of a batch run
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BatchReport {
+    /// the number of records read
+    pub records: usize,
+    /// the records left out of the output
+    pub errors: Vec<RecordError>,
+}
+
+/// Encrypts or decrypts the selected columns of many records.
+///
+/// Encryption seals every record with the key of a single `EncryptedBox`
+/// (see `EncryptedBox::encrypt_fields`), and decryption derives keys through
+/// a `KeyCache`, so the KDF runs once per run rather than once per record.
+pub struct Batch<'a> {
+    format: Format,
+    columns: Vec<String>,
+    row_column: Option<String>,
+    encoding: Option<Encoding>,
+    jobs: Option<usize>,
+    progress: Option<Box<dyn FnMut(usize) + 'a>>,
+}
+
+impl<'a> Batch<'a> {
+    pub fn new(format: Format) -> Batch<'a> {
+        Batch {
+            format,
+            columns: Vec::new(),
+            row_column: None,
+            encoding: None,
+            jobs: None,
+            progress: None,
+        }
+    }
+
+    /// select a column (CSV) or key (JSON Lines) to encrypt
+    pub fn add_column(&mut self, column: &str) -> &mut Batch<'a> {
+        self.columns.push(column.to_string());
+        self
+    }
+
+    /// select several columns (see `add_column`)
+    pub fn add_columns<S: AsRef<str>>(&mut self, columns: &[S]) -> &mut Batch<'a> {
+        for column in columns {
+            self.add_column(column.as_ref());
+        }
+        self
+    }
+
+    /// seal the selected columns of a record into one box stored in column,
+    /// instead of one box per cell
+    pub fn per_row(&mut self, column: &str) -> &mut Batch<'a> {
+        self.row_column = Some(column.to_string());
+        self
+    }
+
+    /// the encoding of the boxes. Encryption defaults to base64,
+    /// decryption detects the encoding unless it is set
+    pub fn set_encoding(&mut self, encoding: Encoding) -> &mut Batch<'a> {
+        self.encoding = Some(encoding);
+        self
+    }
+
+    /// call progress with the number of records read so far after every record
+    pub fn on_progress<F>(&mut self, progress: F) -> &mut Batch<'a>
+    where
+        F: FnMut(usize) + 'a,
+    {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// encrypt on a thread pool of jobs threads, rather than on rayon's
+    /// global pool (one thread per core)
+    pub fn set_jobs(&mut self, jobs: usize) -> &mut Batch<'a> {
+        self.jobs = Some(jobs);
+        self
+    }
+
+    /// encrypt the selected columns of every record of input with the key,
+    /// KDF parameters and scheme of eb (its own fields are ignored).
+    /// Records are sealed in parallel (see `EncryptedBox::encrypt_many`)
+    pub fn encrypt<T, R, W>(
+        &mut self,
+        eb: &EncryptedBox<T>,
+        input: R,
+        output: W,
+    ) -> Result<BatchReport>
+    where
+        T: EncryptionScheme + Clone + Sync,
+        T::Error: fmt::Display,
+        R: Read,
+        W: Write,
+    {
+        let encoding = self.encoding.unwrap_or(Encoding::Base64);
+        if !encoding.is_text() {
+            return Err(Error::batch(format!(
+                "boxes cannot be stored in {} encoding",
+                encoding
+            )));
+        }
+        if self.row_column.is_none() && self.columns.is_empty() {
+            return Err(Error::batch("no columns selected"));
+        }
+        let pool = match self.jobs {
+            Some(jobs) => Some(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(jobs)
+                    .build()
+                    .map_err(Error::batch)?,
+            ),
+            None => None,
+        };
+        let columns = &self.columns;
+        let row_column = &self.row_column;
+        let mut seal = |records: &mut [&mut dyn Record]| -> Vec<Result<()>> {
+            // gather the fields of every box of the chunk, to seal them all at once
+            let mut results = Vec::with_capacity(records.len());
+            let mut targets = Vec::new();
+            let mut items = Vec::new();
+            for (index, record) in records.iter_mut().enumerate() {
+                match gather(*record, columns, row_column) {
+                    Ok(boxes) => {
+                        for (column, fields) in boxes {
+                            targets.push((index, column));
+                            items.push(fields);
+                        }
+                        results.push(Ok(()));
+                    }
+                    Err(e) => results.push(Err(e)),
+                }
+            }
+            let sealed = match &pool {
+                Some(pool) => pool.install(|| eb.encrypt_many(items)),
+                None => eb.encrypt_many(items),
+            };
+            for ((index, column), boxed) in targets.into_iter().zip(sealed) {
+                if results[index].is_err() {
+                    continue;
+                }
+                let stored = boxed
+                    .and_then(|boxed| {
+                        String::from_utf8(encoding::encode(&boxed[..], encoding))
+                            .map_err(|_| Error::encoding("not a text encoding"))
+                    })
+                    .and_then(|text| records[index].set_text(column, text));
+                if let Err(e) = stored {
+                    results[index] = Err(e);
+                }
+            }
+            results
+        };
+        let headers = Headers {
+            required: self.columns.clone(),
+            add: self.row_column.clone(),
+            drop: None,
+        };
+        run(
+            self.format,
+            input,
+            output,
+            headers,
+            &mut self.progress,
+            &mut seal,
+        )
+    }
+
+    /// decrypt the selected columns (or the per-row box) of every record of
+    /// input, accepting only boxes of the schemes of registry that policy allows
+    pub fn decrypt<R, W>(
+        &mut self,
+        keys: &mut KeyCache,
+        policy: &DecryptPolicy,
+        registry: &SchemeRegistry,
+        input: R,
+        output: W,
+    ) -> Result<BatchReport>
+    where
+        R: Read,
+        W: Write,
+    {
+        if self.row_column.is_none() && self.columns.is_empty() {
+            return Err(Error::batch("no columns selected"));
+        }
+        let encoding = self.encoding;
+        let row_column = &self.row_column;
+        let columns = &self.columns;
+        let mut open_record = |record: &mut dyn Record| -> Result<()> {
+            let mut open = |text: String| -> Result<Fields> {
+                let boxed = match encoding {
+                    Some(encoding) => encoding::decode(text.as_bytes(), encoding)?,
+                    None => encoding::decode_auto(text.as_bytes())?,
+                };
+                let eb = EncryptedBox::decrypt_with_registry(&boxed[..], policy, keys, registry)?;
+                Ok(eb.fields().to_vec())
+            };
+            match row_column {
+                None => {
+                    for column in columns {
+                        let fields = open(record.text(column)?)?;
+                        if fields.len() != 1 {
+                            return Err(Error::batch(format!(
+                                "column '{}' holds {} fields instead of one",
+                                column,
+                                fields.len()
+                            )));
+                        }
+                        record.set_value(column, &fields[0][..])?;
+                    }
+                }
+                Some(row_column) => {
+                    let fields = open(record.text(row_column)?)?;
+                    if fields.len() % 2 != 0 {
+                        return Err(Error::batch("row box does not hold name/value pairs"));
+                    }
+                    record.remove(row_column);
+                    for pair in fields.chunks(2) {
+                        let column = std::str::from_utf8(&pair[0][..])
+                            .map_err(|_| Error::batch("column name is not valid UTF-8"))?;
+                        record.set_value(column, &pair[1][..])?;
+                    }
+                }
+            }
+            Ok(())
+        };
+        let mut open = |records: &mut [&mut dyn Record]| -> Vec<Result<()>> {
+            records
+                .iter_mut()
+                .map(|record| open_record(*record))
+                .collect()
+        };
+        let headers = match &self.row_column {
+            None => Headers {
+                required: self.columns.clone(),
+                add: None,
+                drop: None,
+            },
+            Some(row_column) => Headers {
+                required: vec![row_column.clone()],
+                add: None,
+                drop: Some(row_column.clone()),
+            },
+        };
+        run(
+            self.format,
+            input,
+            output,
+            headers,
+            &mut self.progress,
+            &mut open,
+        )
+    }
+}
+
+/// the boxes a record is sealed into, as the column each box is stored in
+/// with the fields of the box. Per row, the selected columns are removed
+fn gather<'c>(
+    record: &mut dyn Record,
+    columns: &'c [String],
+    row_column: &'c Option<String>,
+) -> Result<Vec<(&'c str, Fields)>> {
+    match row_column {
+        None => columns
+            .iter()
+            .map(|column| Ok((&column[..], vec![record.value(column)?])))
+            .collect(),
+        Some(row_column) => {
+            let mut fields = Vec::with_capacity(2 * columns.len());
+            for column in columns {
+                fields.push(column.as_bytes().to_vec());
+                fields.push(record.value(column)?);
+            }
+            for column in columns {
+                record.remove(column);
+            }
+            Ok(vec![(&row_column[..], fields)])
+        }
+    }
+}
+
+/// what a run does to the columns of a CSV file
+struct Headers {
+    /// columns that must exist
+    required: Vec<String>,
+    /// column appended to every record
+    add: Option<String>,
+    /// column removed from every record
+    drop: Option<String>,
+}
+
+type Progress<'a> = Option<Box<dyn FnMut(usize) + 'a>>;
+
+/// transforms a chunk of records, returning the outcome of every record
+type Transform<'t> = dyn FnMut(&mut [&mut dyn Record]) -> Vec<Result<()>> + 't;
+
+fn run<R: Read, W: Write>(
+    format: Format,
+    input: R,
+    output: W,
+    headers: Headers,
+    progress: &mut Progress,
+    transform: &mut Transform,
+) -> Result<BatchReport> {
+    match format {
+        Format::Csv => run_csv(input, output, headers, progress, transform),
+        Format::Jsonl => run_jsonl(input, output, progress, transform),
+    }
+}
+
+/// transform the records of chunk that were read, failing those the transform fails
+fn transform_chunk<Rec: Record>(
+    mut chunk: Vec<Result<Rec>>,
+    transform: &mut Transform,
+) -> Vec<Result<Rec>> {
+    let results = {
+        let mut records: Vec<&mut dyn Record> = chunk
+            .iter_mut()
+            .filter_map(|record| record.as_mut().ok())
+            .map(|record| record as &mut dyn Record)
+            .collect();
+        transform(&mut records[..])
+    };
+    let mut results = results.into_iter();
+    chunk
+        .into_iter()
+        .map(|record| {
+            record.and_then(|record| {
+                let result = results.next().expect("one result per record");
+                result.map(|_| record)
+            })
+        })
+        .collect()
+}
+
+fn run_csv<R: Read, W: Write>(
+    input: R,
+    output: W,
+    headers: Headers,
+    progress: &mut Progress,
+    transform: &mut Transform,
+) -> Result<BatchReport> {
+    let mut reader = csv::Reader::from_reader(input);
+    let mut writer = csv::Writer::from_writer(output);
+    let mut names: Vec<String> = reader
+        .headers()
+        .map_err(Error::batch)?
+        .iter()
+        .map(String::from)
+        .collect();
+    for column in &headers.required {
+        if !names.contains(column) {
+            return Err(Error::batch(format!("no column '{}'", column)));
+        }
+    }
+    if let Some(column) = &headers.add {
+        if names.contains(column) {
+            return Err(Error::batch(format!("column '{}' already exists", column)));
+        }
+        names.push(column.clone());
+    }
+    let drop = headers
+        .drop
+        .as_ref()
+        .and_then(|column| names.iter().position(|n| n == column));
+    let mut out_names = names.clone();
+    if let Some(index) = drop {
+        out_names.remove(index);
+    }
+    writer.write_record(&out_names).map_err(Error::batch)?;
+
+    let mut report = BatchReport::default();
+    let mut rows = reader.records();
+    loop {
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+        for result in rows.by_ref().take(CHUNK_SIZE) {
+            if let Err(e) = &result {
+                if e.is_io_error() {
+                    return Err(Error::batch(e));
+                }
+            }
+            chunk.push(result.map_err(Error::batch).map(|row| {
+                let mut cells: Vec<String> = row.iter().map(String::from).collect();
+                if headers.add.is_some() {
+                    cells.push(String::new());
+                }
+                CsvRecord {
+                    names: &names,
+                    cells,
+                }
+            }));
+        }
+        if chunk.is_empty() {
+            break;
+        }
+        for record in transform_chunk(chunk, transform) {
+            report.records += 1;
+            match record {
+                Ok(mut record) => {
+                    if let Some(index) = drop {
+                        record.cells.remove(index);
+                    }
+                    writer.write_record(&record.cells).map_err(Error::batch)?;
+                }
+                Err(e) => report.errors.push(RecordError {
+                    record: report.records,
+                    message: e.to_string(),
+                }),
+            }
+            if let Some(progress) = progress {
+                progress(report.records);
+            }
+        }
+    }
+    writer.flush().map_err(Error::batch)?;
+    Ok(report)
+}
+
+fn run_jsonl<R: Read, W: Write>(
+    input: R,
+    mut output: W,
+    progress: &mut Progress,
+    transform: &mut Transform,
+) -> Result<BatchReport> {
+    let mut report = BatchReport::default();
+    let mut lines = BufReader::new(input).lines();
+    loop {
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+        while chunk.len() < CHUNK_SIZE {
+            let line = match lines.next() {
+                Some(line) => line.map_err(Error::batch)?,
+                None => break,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            chunk.push(
+                serde_json::from_str::<Map<String, Value>>(&line)
+                    .map(|map| JsonRecord { map })
+                    .map_err(|e| Error::batch(format!("not a JSON object: {}", e))),
+            );
+        }
+        if chunk.is_empty() {
+            break;
+        }
+        for record in transform_chunk(chunk, transform) {
+            report.records += 1;
+            let line =
+                record.and_then(|record| serde_json::to_string(&record.map).map_err(Error::batch));
+            match line {
+                Ok(line) => writeln!(output, "{}", line).map_err(Error::batch)?,
+                Err(e) => report.errors.push(RecordError {
+                    record: report.records,
+                    message: e.to_string(),
+                }),
+            }
+            if let Some(progress) = progress {
+                progress(report.records);
+            }
+        }
+    }
+    output.flush().map_err(Error::batch)?;
+    Ok(report)
+}
+
+/// a record, as far as batch mode is concerned
+trait Record {
+    /// the (plaintext) value of column
+    fn value(&self, column: &str) -> Result<Vec<u8>>;
+    /// set column to a decrypted value
+    fn set_value(&mut self, column: &str, value: &[u8]) -> Result<()>;
+    /// the (encoded) box stored in column
+    fn text(&self, column: &str) -> Result<String>;
+    /// store an (encoded) box in column
+    fn set_text(&mut self, column: &str, text: String) -> Result<()>;
+    /// remove the value of column
+    fn remove(&mut self, column: &str);
+}
+
+struct CsvRecord<'n> {
+    names: &'n [String],
+    cells: Vec<String>,
+}
+
+impl<'n> CsvRecord<'n> {
+    fn cell(&mut self, column: &str) -> Result<&mut String> {
+        let index = self
+            .names
+            .iter()
+            .position(|n| n == column)
+            .ok_or_else(|| Error::batch(format!("no column '{}'", column)))?;
+        Ok(&mut self.cells[index])
+    }
+}
+
+impl<'n> Record for CsvRecord<'n> {
+    fn value(&self, column: &str) -> Result<Vec<u8>> {
+        self.text(column).map(String::into_bytes)
+    }
+
+    fn set_value(&mut self, column: &str, value: &[u8]) -> Result<()> {
+        let value = String::from_utf8(value.to_vec())
+            .map_err(|_| Error::batch(format!("column '{}' is not valid UTF-8", column)))?;
+        self.set_text(column, value)
+    }
+
+    fn text(&self, column: &str) -> Result<String> {
+        self.names
+            .iter()
+            .position(|n| n == column)
+            .map(|index| self.cells[index].clone())
+            .ok_or_else(|| Error::batch(format!("no column '{}'", column)))
+    }
+
+    fn set_text(&mut self, column: &str, text: String) -> Result<()> {
+        *self.cell(column)? = text;
+        Ok(())
+    }
+
+    /// CSV cells are emptied rather than removed, so every row keeps its columns
+    fn remove(&mut self, column: &str) {
+        if let Ok(cell) = self.cell(column) {
+            cell.clear();
+        }
+    }
+}
+
+struct JsonRecord {
+    map: Map<String, Value>,
+}
+
+impl Record for JsonRecord {
+    fn value(&self, column: &str) -> Result<Vec<u8>> {
+        let value = self
+            .map
+            .get(column)
+            .ok_or_else(|| Error::batch(format!("no key '{}'", column)))?;
+        serde_json::to_vec(value).map_err(Error::batch)
+    }
+
+    fn set_value(&mut self, column: &str, value: &[u8]) -> Result<()> {
+        let value = serde_json::from_slice(value)
+            .map_err(|e| Error::batch(format!("key '{}' is not valid JSON: {}", column, e)))?;
+        self.map.insert(column.to_string(), value);
+        Ok(())
+    }
+
+    fn text(&self, column: &str) -> Result<String> {
+        match self.map.get(column) {
+            Some(Value::String(text)) => Ok(text.clone()),
+            Some(_) => Err(Error::batch(format!("key '{}' is not a string", column))),
+            None => Err(Error::batch(format!("no key '{}'", column))),
+        }
+    }
+
+    fn set_text(&mut self, column: &str, text: String) -> Result<()> {
+        self.map.insert(column.to_string(), Value::String(text));
+        Ok(())
+    }
+
+    fn remove(&mut self, column: &str) {
+        self.map.remove(column);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption_scheme::DefaultScheme;
+    use crate::encryption_scheme::EncryptionScheme;
+    use crate::kdf::{Kdf, KdfParams};
+    use crate::openssl_aes::defs::OpenSslVariants as aes_variant;
+    use crate::subkeys::MASTER_KEY_LEN;
+
+    const PASSWORD: &str = "password";
+
+    const CSV: &str = "id,email,age\n1,a@example.com,30\n2,\"b,c@example.com\",41\n";
+    const JSONL: &str = "{\"age\":30,\"email\":\"a@example.com\",\"id\":1}\n\
+                         {\"age\":{\"years\":41},\"email\":\"b@example.com\",\"id\":2}\n";
+
+    fn sealer() -> EncryptedBox<DefaultScheme> {
+        let scheme = DefaultScheme::new(&aes_variant::Aes256Gcm);
+        let params = KdfParams::new(Kdf::default());
+        let key = params.derive(PASSWORD, MASTER_KEY_LEN);
+        EncryptedBox::new(vec![], key, params, scheme)
+    }
+
+    fn encrypt(batch: &mut Batch, input: &str) -> Result<(String, BatchReport)> {
+        let mut output = Vec::new();
+        let report = batch.encrypt(&sealer(), input.as_bytes(), &mut output)?;
+        Ok((String::from_utf8(output).unwrap(), report))
+    }
+
+    fn decrypt(batch: &mut Batch, input: &str) -> Result<(String, BatchReport)> {
+        let mut output = Vec::new();
+        let mut keys = KeyCache::new(String::from(PASSWORD));
+        let report = batch.decrypt(
+            &mut keys,
+            &DecryptPolicy::default(),
+            &SchemeRegistry::default(),
+            input.as_bytes(),
+            &mut output,
+        )?;
+        Ok((String::from_utf8(output).unwrap(), report))
+    }
+
+    fn round_trip(batch: &mut Batch, input: &str) -> Result<String> {
+        let (encrypted, report) = encrypt(batch, input)?;
+        assert!(report.errors.is_empty());
+        assert!(!encrypted.contains("example.com"));
+        let (decrypted, report) = decrypt(batch, &encrypted)?;
+        assert!(report.errors.is_empty());
+        assert_eq!(report.records, 2);
+        Ok(decrypted)
+    }
+
+    #[test]
+    fn csv_per_cell() -> Result<()> {
+        let mut batch = Batch::new(Format::Csv);
+        batch.add_columns(&["email", "age"]);
+        let (encrypted, _) = encrypt(&mut batch, CSV)?;
+        assert!(encrypted.starts_with("id,email,age\n1,RUJPWA"));
+        assert_eq!(round_trip(&mut batch, CSV)?, CSV);
+        Ok(())
+    }
+
+    #[test]
+    fn csv_per_row() -> Result<()> {
+        let mut batch = Batch::new(Format::Csv);
+        batch.add_column("email").per_row("sealed");
+        let (encrypted, _) = encrypt(&mut batch, CSV)?;
+        assert!(encrypted.starts_with("id,email,age,sealed\n1,,30,RUJPWA"));
+        assert_eq!(round_trip(&mut batch, CSV)?, CSV);
+        Ok(())
+    }
+
+    #[test]
+    fn jsonl_per_cell() -> Result<()> {
+        let mut batch = Batch::new(Format::Jsonl);
+        batch
+            .add_columns(&["email", "age"])
+            .set_encoding(Encoding::Hex);
+        assert_eq!(round_trip(&mut batch, JSONL)?, JSONL);
+        Ok(())
+    }
+
+    #[test]
+    fn jsonl_per_row() -> Result<()> {
+        let mut batch = Batch::new(Format::Jsonl);
+        batch.add_columns(&["email", "age"]).per_row("sealed");
+        let (encrypted, _) = encrypt(&mut batch, JSONL)?;
+        assert!(encrypted.starts_with("{\"id\":1,\"sealed\":\"RUJPWA"));
+        assert_eq!(round_trip(&mut batch, JSONL)?, JSONL);
+        Ok(())
+    }
+
+    #[test]
+    fn bad_records_are_reported() -> Result<()> {
+        let mut batch = Batch::new(Format::Jsonl);
+        batch.add_column("email");
+        let input = "{\"email\":\"a\"}\nnot json\n{\"name\":\"b\"}\n{\"email\":\"c\"}\n";
+        let (encrypted, report) = encrypt(&mut batch, input)?;
+        assert_eq!(report.records, 4);
+        let failed: Vec<usize> = report.errors.iter().map(|e| e.record).collect();
+        assert_eq!(failed, vec![2, 3]);
+        assert_eq!(encrypted.lines().count(), 2);
+
+        // a tampered box fails only its own record
+        let tampered = encrypted.replacen("RUJPWA", "RUJPWB", 1);
+        let (_, report) = decrypt(&mut batch, &tampered)?;
+        assert_eq!(report.records, 2);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].record, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn csv_rows_of_wrong_length_are_reported() -> Result<()> {
+        let mut batch = Batch::new(Format::Csv);
+        batch.add_column("email");
+        let (_, report) = encrypt(&mut batch, "id,email\n1,a\n2\n3,c\n")?;
+        assert_eq!(report.records, 3);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].record, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn many_chunks_keep_order() -> Result<()> {
+        let mut input = String::from("id,email\n");
+        for i in 0..(2 * CHUNK_SIZE + 3) {
+            input.push_str(&format!("{},{}@example.com\n", i, i));
+        }
+        // and a bad record at the very end
+        input.push_str("bad\n");
+        let mut batch = Batch::new(Format::Csv);
+        batch.add_column("email").set_jobs(3);
+        let (encrypted, report) = encrypt(&mut batch, &input)?;
+        assert_eq!(report.records, 2 * CHUNK_SIZE + 4);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].record, 2 * CHUNK_SIZE + 4);
+
+        let (decrypted, _) = decrypt(&mut batch, &encrypted)?;
+        assert_eq!(decrypted, input.trim_end_matches("bad\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn missing_csv_column_aborts() {
+        let mut batch = Batch::new(Format::Csv);
+        batch.add_column("ssn");
+        assert!(encrypt(&mut batch, CSV).is_err());
+    }
+
+    #[test]
+    fn binary_encoding_is_refused() {
+        let mut batch = Batch::new(Format::Csv);
+        batch.add_column("email").set_encoding(Encoding::Binary);
+        assert!(encrypt(&mut batch, CSV).is_err());
+    }
+
+    #[test]
+    fn progress_is_reported() -> Result<()> {
+        let mut seen = Vec::new();
+        {
+            let mut batch = Batch::new(Format::Csv);
+            batch.add_column("email").on_progress(|n| seen.push(n));
+            encrypt(&mut batch, CSV)?;
+        }
+        assert_eq!(seen, vec![1, 2]);
+        Ok(())
+    }
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(Format::from_path(Path::new("a/b.CSV")), Some(Format::Csv));
+        assert_eq!(
+            Format::from_path(Path::new("b.ndjson")),
+            Some(Format::Jsonl)
+        );
+        assert_eq!(Format::from_path(Path::new("b.txt")), None);
+        assert_eq!(Format::from_path(Path::new("b")), None);
+    }
+}
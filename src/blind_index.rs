@@ -0,0 +1,146 @@
+use crate::kdf::{Kdf, KdfParams};
+use crate::subkeys::{MasterKey, Purpose, MASTER_KEY_LEN};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+
+// A blind index lets a database find the boxes holding a value (e.g. the rows
+// of an email) without decrypting anything:
+//
+//   key        the blind index subkey for the index's name (e.g. "email"), of
+//              a master key derived with the deterministic salt (see kdf.rs)
+//   index      HMAC-SHA256(key, value), truncated to len bytes
+//
+// A box is indexed by one of its fields (e.g. the email of a [name, email]
+// box), so looking up the value alone finds it.
+//
+// Boxes get a random salt, so their keys differ; the index key must not, or
+// the indexes of equal values would. Every name gets its own key, so the
+// indexes of one column tell nothing about another's. Truncating leaves
+// collisions: a lookup returns a few false positives, which are weeded out by
+// decrypting, and in return the index reveals less about which values are equal.
+
+/// the length of indexes unless told otherwise (64 bits)
+pub const DEFAULT_INDEX_LEN: usize = 8;
+
+/// the longest index, an untruncated HMAC-SHA256
+pub const MAX_INDEX_LEN: usize = 32;
+
+/// Computes the blind indexes of one name (e.g. a column)
+#[derive(Clone)]
+pub struct BlindIndex {
+    name: String,
+    key: Vec<u8>,
+    len: usize,
+}
+
+impl BlindIndex {
+    /// the index called name, keyed by a subkey of master_key
+    pub fn new(name: &str, master_key: &[u8]) -> BlindIndex {
+        let key = MasterKey::new(master_key).subkey(Purpose::BlindIndex, name, MASTER_KEY_LEN);
+        BlindIndex {
+            name: name.to_string(),
+            key,
+            len: DEFAULT_INDEX_LEN,
+        }
+    }
+
+    /// the index called name, keyed by password (see `EncryptedBoxBuilder::with_blind_index`)
+    pub fn derive(name: &str, password: &str, kdf: Kdf) -> BlindIndex {
+        let master_key = KdfParams::deterministic(kdf).derive(password, MASTER_KEY_LEN);
+        BlindIndex::new(name, &master_key)
+    }
+
+    /// truncate indexes to len bytes (`DEFAULT_INDEX_LEN` by default).
+    /// Panics unless 0 < len <= `MAX_INDEX_LEN`
+    pub fn set_len(&mut self, len: usize) -> &mut BlindIndex {
+        assert!(
+            (1..=MAX_INDEX_LEN).contains(&len),
+            "index length must be between 1 and {}",
+            MAX_INDEX_LEN
+        );
+        self.len = len;
+        self
+    }
+
+    /// the name of this index
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// the length of its indexes
+    pub fn index_len(&self) -> usize {
+        self.len
+    }
+
+    /// the index of a value, equal to that of the boxes indexed by a field
+    /// holding value
+    pub fn index(&self, value: &[u8]) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_varkey(&self.key).expect("HMAC takes keys of any length");
+        mac.input(value);
+        mac.result().code()[..self.len].to_vec()
+    }
+}
+
+impl fmt::Debug for BlindIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BlindIndex")
+            .field("name", &self.name)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MASTER_KEY: [u8; MASTER_KEY_LEN] = [7; MASTER_KEY_LEN];
+
+    #[test]
+    fn equal_values_have_equal_indexes() {
+        let email = BlindIndex::new("email", &MASTER_KEY);
+        let index = email.index(b"alice@example.com");
+        assert_eq!(index.len(), DEFAULT_INDEX_LEN);
+        assert_eq!(
+            BlindIndex::new("email", &MASTER_KEY).index(b"alice@example.com"),
+            index
+        );
+        assert_ne!(email.index(b"bob@example.com"), index);
+    }
+
+    #[test]
+    fn names_and_keys_are_independent() {
+        let index = BlindIndex::new("email", &MASTER_KEY).index(b"alice");
+        assert_ne!(BlindIndex::new("name", &MASTER_KEY).index(b"alice"), index);
+        assert_ne!(
+            BlindIndex::new("email", &[8; MASTER_KEY_LEN]).index(b"alice"),
+            index
+        );
+    }
+
+    #[test]
+    fn indexes_are_truncated() {
+        let mut email = BlindIndex::new("email", &MASTER_KEY);
+        let full = email.set_len(MAX_INDEX_LEN).index(b"alice");
+        assert_eq!(email.set_len(4).index(b"alice"), &full[..4]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn empty_indexes_are_refused() {
+        BlindIndex::new("email", &MASTER_KEY).set_len(0);
+    }
+
+    #[test]
+    fn derive_ignores_the_box_salt() {
+        let kdf = Kdf::Pbkdf2Sha256 { iterations: 1000 };
+        let one = BlindIndex::derive("email", "password", kdf).index(b"alice");
+        let two = BlindIndex::derive("email", "password", kdf).index(b"alice");
+        assert_eq!(one, two);
+        assert_ne!(
+            BlindIndex::derive("email", "other", kdf).index(b"alice"),
+            one
+        );
+    }
+}
@@ -1,85 +1,619 @@
+use crate::batch::{Batch, Format};
+use crate::blind_index::{self, BlindIndex};
+use crate::compression::Compression;
+use crate::config::Config;
+use crate::encoding::{self, Encoding};
+use crate::encrypted_box::EncryptedBox;
 use crate::encrypted_box_builder::EncryptedBoxBuilder;
-use crate::encryption_scheme::EncryptionScheme;
-use crate::openssl_aes::{defs as aes_defs, wrapper as aes};
-use base64;
+use crate::encryption_scheme::{EncryptionScheme, SchemeRef, SecurityLevel};
+use crate::kdf::{self, Kdf, KeyCache};
+use crate::legacy;
+use crate::openssl_aes::{defs as aes_defs, errors as aes_errors};
+use crate::policy::DecryptPolicy;
+use crate::registry::SchemeRegistry;
 use exitfailure::ExitFailure;
 use failure::ResultExt;
 use std::fs;
+use std::io::{self, Read, Write};
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 use structopt::StructOpt;
 
+const DEFAULT_SCHEME: &str = "aes-256-gcm";
+const DEFAULT_PASSWORD_FILE: &str = ".pass";
+/// batch mode reports progress every that many records
+const PROGRESS_INTERVAL: usize = 10_000;
+
 pub fn cli() -> Result<(), ExitFailure> {
     let opt = Opt::from_args();
 
+    match opt.cmd {
+        Some(Command::ListSchemes) => {
+            list_schemes();
+            return Ok(());
+        }
+        Some(Command::Bench { target_ms }) => {
+            bench(Duration::from_millis(target_ms));
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    // flags take precedence over the config file
+    let config = match &opt.config {
+        Some(path) => Config::load(path)?,
+        None => Config::load_default()?,
+    };
+
     // get password
-    let password: String = get_password(opt.password, opt.path_to_password)
-        .with_context(|_| format!("could not determine password!"))?;
+    let path_to_password = opt
+        .path_to_password
+        .clone()
+        .or_else(|| config.keyring.clone())
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_PASSWORD_FILE));
+    let password: String = get_password(opt.password.clone(), path_to_password)
+        .with_context(|_| "could not determine password!")?;
+
+    match &opt.cmd {
+        Some(Command::Batch(batch_opt)) => return batch(password, &opt, config, batch_opt),
+        Some(Command::Index {
+            name,
+            length,
+            values,
+        }) => return index(&password, &opt, &config, name, *length, values),
+        Some(Command::Migrate) => return migrate(password, &opt, &config),
+        _ => {}
+    }
+
+    if opt.decrypt {
+        return decrypt(password, &opt, config);
+    }
+
+    let eb = build_box(password, &opt, &config, &opt.fields[..])?;
+
+    // encrypt
+    let enc = eb.encrypt().with_context(|_| "encryption failed!")?;
 
+    write_box(&opt, &config, &enc[..])
+}
+
+/// encode a box (in base 64 unless another encoding is chosen) and output it
+fn write_box(opt: &Opt, config: &Config, enc: &[u8]) -> Result<(), ExitFailure> {
+    let encoding = opt.encoding.or(config.encoding).unwrap_or(Encoding::Base64);
+    if !encoding.is_text() && opt.output.is_none() {
+        return Err(
+            failure::err_msg("binary output must be written to a file (see --output)").into(),
+        );
+    }
+    let mut out = encoding::encode(enc, encoding);
+    if encoding.is_text() && !out.ends_with(b"\n") {
+        out.push(b'\n');
+    }
+    write_output(opt.output.clone(), &out[..])?;
+    Ok(())
+}
+
+/// read a box and decode it, detecting its encoding unless it is given
+fn read_box(opt: &Opt) -> Result<Vec<u8>, ExitFailure> {
+    let raw = read_input(opt.input.clone()).with_context(|_| "could not read encrypted box!")?;
+    let enc = match opt.encoding {
+        Some(encoding) => encoding::decode(&raw[..], encoding),
+        None => encoding::decode_auto(&raw[..]),
+    }
+    .with_context(|_| "could not decode encrypted box!")?;
+    Ok(enc)
+}
+
+/// build a box of fields with the scheme and KDF chosen by flags and config
+fn build_box(
+    password: String,
+    opt: &Opt,
+    config: &Config,
+    fields: &[String],
+) -> Result<EncryptedBox<SchemeRef>, ExitFailure> {
     // get aes scheme
-    let aes_enum = aes_defs::openssl_index_to_enum(opt.scheme)
-        .with_context(|_| format!("unsupported scheme!"))?;
-    let scheme = aes::OpensslAesWrapper::new(&aes_enum);
+    let scheme_name = opt
+        .scheme
+        .as_ref()
+        .or(config.scheme.as_ref())
+        .map_or(DEFAULT_SCHEME, |s| &s[..]);
+    let registry = SchemeRegistry::default();
+    let identifier =
+        parse_scheme(scheme_name, &registry).with_context(|_| "unsupported scheme!")?;
+    let scheme = registry
+        .get(&identifier)
+        .with_context(|_| "unsupported scheme!")?;
+    check_security_level(&scheme, opt.insecure)?;
 
     // initialize builder & encrypted-box
     let mut ebb = EncryptedBoxBuilder::new(scheme);
-    let eb = ebb
-        .set_password(password)
-        .add_fields(&opt.fields[..])
-        .build()?;
+    if opt.insecure {
+        ebb.allow_insecure();
+    }
+    if let Some(kdf) = config.kdf {
+        ebb.set_kdf(kdf);
+    }
+    if let Some(compression) = opt.compress {
+        if compression != Compression::None {
+            eprintln!(
+                "warning: compressed boxes leak how well their fields compress; \
+                 do not compress fields an attacker influences along with secrets"
+            );
+        }
+        ebb.set_compression(compression);
+    }
+    ebb.set_password(password).add_fields(fields);
+    ebb.into_box()
+}
 
-    // encrypt
-    let enc = eb
-        .encrypt()
-        .with_context(|_| format!("encryption failed!"))?;
-    println!("{}", base64::encode(&enc[..]));
+/// encrypt or decrypt every record of a CSV or JSON Lines file,
+/// deriving the key once for the whole run
+fn batch(
+    password: String,
+    opt: &Opt,
+    config: Config,
+    batch_opt: &BatchOpt,
+) -> Result<(), ExitFailure> {
+    let format = batch_opt
+        .format
+        .or_else(|| opt.input.as_ref().and_then(|path| Format::from_path(path)))
+        .ok_or_else(|| failure::err_msg("could not detect the record format, use --format"))?;
+    let mut batch = Batch::new(format);
+    batch.add_columns(&batch_opt.columns[..]);
+    if batch_opt.per_row {
+        batch.per_row(&batch_opt.row_column);
+    }
+    if let Some(jobs) = batch_opt.jobs {
+        batch.set_jobs(jobs);
+    }
+    batch.on_progress(|records| {
+        if records % PROGRESS_INTERVAL == 0 {
+            eprintln!("{} records processed", records);
+        }
+    });
+
+    let input: Box<dyn Read> = match &opt.input {
+        Some(path) => Box::new(fs::File::open(path).with_context(|_| "could not open input!")?),
+        None => Box::new(io::stdin()),
+    };
+    let output: Box<dyn Write> = match &opt.output {
+        Some(path) => {
+            Box::new(fs::File::create(path).with_context(|_| "could not create output!")?)
+        }
+        None => Box::new(io::stdout()),
+    };
+    let output = io::BufWriter::new(output);
+
+    let report = match batch_opt.direction {
+        BatchDirection::Encrypt => {
+            if let Some(encoding) = opt.encoding.or(config.encoding) {
+                batch.set_encoding(encoding);
+            }
+            let eb = build_box(password, opt, &config, &[])?;
+            batch.encrypt(&eb, input, output)?
+        }
+        BatchDirection::Decrypt => {
+            if let Some(encoding) = opt.encoding {
+                batch.set_encoding(encoding);
+            }
+            let policy = get_policy(opt, config.policy)?;
+            let mut keys = KeyCache::new(password);
+            let registry = SchemeRegistry::default();
+            batch.decrypt(&mut keys, &policy, &registry, input, output)?
+        }
+    };
+
+    // report the records left out
+    match &batch_opt.error_report {
+        Some(path) => {
+            let mut out = Vec::new();
+            for error in &report.errors {
+                let line = serde_json::json!({ "record": error.record, "error": error.message });
+                writeln!(out, "{}", line)?;
+            }
+            fs::write(path, out).with_context(|_| "could not write error report!")?;
+        }
+        None => {
+            for error in &report.errors {
+                eprintln!("{}", error);
+            }
+        }
+    }
+    if !report.errors.is_empty() {
+        return Err(failure::format_err!(
+            "{} of {} records failed",
+            report.errors.len(),
+            report.records
+        )
+        .into());
+    }
+    eprintln!("{} records processed", report.records);
+    Ok(())
+}
+
+/// print the blind index of every value (see blind_index.rs), one per line in hex
+fn index(
+    password: &str,
+    opt: &Opt,
+    config: &Config,
+    name: &str,
+    length: usize,
+    values: &[String],
+) -> Result<(), ExitFailure> {
+    if length == 0 || length > blind_index::MAX_INDEX_LEN {
+        return Err(failure::format_err!(
+            "the index length must be between 1 and {}",
+            blind_index::MAX_INDEX_LEN
+        )
+        .into());
+    }
+    let mut index = BlindIndex::derive(name, password, config.kdf.unwrap_or_default());
+    index.set_len(length);
+    let mut out = String::new();
+    for value in values {
+        out.push_str(&hex::encode(index.index(value.as_bytes())));
+        out.push('\n');
+    }
+    write_output(opt.output.clone(), out.as_bytes())?;
+    Ok(())
+}
+
+fn decrypt(password: String, opt: &Opt, config: Config) -> Result<(), ExitFailure> {
+    let policy = get_policy(opt, config.policy)?;
+
+    let enc = read_box(opt)?;
+
+    // decrypt, a headerless box as version 0 if its scheme is named
+    let fields = match &opt.legacy_scheme {
+        Some(scheme) if legacy::is_v0(&enc[..]) => {
+            let variant = parse_legacy_scheme(scheme)?;
+            eprintln!("warning: opening a version 0 box, consider migrating it (see migrate)");
+            let key = legacy::v0_key(&password, &variant);
+            let plaintext = legacy::decrypt_v0(&key, &enc[..], &variant)
+                .with_context(|_| "decryption failed!")?;
+            vec![plaintext]
+        }
+        _ => {
+            let registry = SchemeRegistry::default();
+            let mut keys = KeyCache::new(password);
+            let eb = EncryptedBox::decrypt_with_registry(&enc[..], &policy, &mut keys, &registry)
+                .with_context(|_| "decryption failed!")?;
+            eb.fields().to_vec()
+        }
+    };
+
+    // output one field per line
+    let mut out = Vec::new();
+    for field in &fields {
+        out.extend_from_slice(&field[..]);
+        out.push(b'\n');
+    }
+    write_output(opt.output.clone(), &out[..])?;
 
     Ok(())
 }
 
+/// the decryption policy: the policy file (or else the configured policy,
+/// or else the strict default), extended by --allow-scheme, --insecure and
+/// --ignore-expiry
+fn get_policy(opt: &Opt, configured: Option<DecryptPolicy>) -> Result<DecryptPolicy, ExitFailure> {
+    let mut policy = match &opt.policy_file {
+        Some(path) => {
+            let toml = fs::read_to_string(path).with_context(|_| "could not read policy file!")?;
+            DecryptPolicy::from_toml(&toml)?
+        }
+        None => configured.unwrap_or_default(),
+    };
+    for scheme in &opt.allow_schemes {
+        policy.allow_scheme(scheme);
+    }
+    if opt.insecure {
+        policy.min_security_level(SecurityLevel::Insecure);
+    }
+    if opt.ignore_expiry {
+        eprintln!("warning: opening boxes outside their validity window");
+        policy.ignore_expiry();
+    }
+    Ok(policy)
+}
+
+fn list_schemes() {
+    println!(
+        "{:<26}{:>6}{:>6}  {:<15}DETERMINISTIC",
+        "SCHEME", "KEY", "IV", "AUTHENTICATED"
+    );
+    let yes_no = |flag| if flag { "yes" } else { "no" };
+    for variant in aes_defs::OpenSslVariants::iterator() {
+        let iv = variant
+            .iv_length()
+            .map_or_else(|| String::from("-"), |len| len.to_string());
+        println!(
+            "{:<26}{:>6}{:>6}  {:<15}{}",
+            variant.name(),
+            variant.key_length(),
+            iv,
+            yes_no(variant.is_authenticated()),
+            yes_no(variant.is_deterministic())
+        );
+    }
+}
+
+/// recommend the KDF parameters that take about target on this machine
+fn bench(target: Duration) {
+    eprintln!("timing pbkdf2-sha256 ...");
+    // round to thousands, people copy this by hand
+    let iterations = (kdf::calibrate_pbkdf2(target) / 1000).max(1) * 1000;
+    let kdf = Kdf::Pbkdf2Sha256 { iterations };
+    println!(
+        "{} takes about {} ms on this machine, add this to the config file:",
+        kdf,
+        target.as_millis()
+    );
+    println!(
+        "kdf = {{ algorithm = \"{}\", iterations = {} }}",
+        kdf.name(),
+        iterations
+    );
+    if let Err(e) = DecryptPolicy::default().check_kdf(&kdf) {
+        eprintln!(
+            "warning: the default decryption policy refuses boxes sealed with it ({})",
+            e
+        );
+    }
+}
+
+/// accepts the identifier of a registered scheme (e.g. aes-256-gcm) or, for
+/// backwards compatibility, one of the deprecated numeric indices
+fn parse_scheme(scheme: &str, registry: &SchemeRegistry) -> Result<String, aes_errors::Error> {
+    match scheme.parse::<u32>() {
+        Ok(index) => {
+            let variant = aes_defs::openssl_index_to_enum(index)?;
+            eprintln!(
+                "warning: numeric scheme indices are deprecated, use '--scheme {}' instead",
+                variant
+            );
+            Ok(variant.name().to_string())
+        }
+        Err(_) if registry.contains(scheme) => Ok(scheme.to_string()),
+        Err(_) => Err(aes_errors::Error::unsupported(format!(
+            "unknown scheme name '{}'",
+            scheme
+        ))),
+    }
+}
+
+/// reseal a version 0 box (see legacy.rs) in the current format, with the
+/// scheme and KDF chosen by flags and config
+fn migrate(password: String, opt: &Opt, config: &Config) -> Result<(), ExitFailure> {
+    let scheme = opt.legacy_scheme.as_ref().ok_or_else(|| {
+        failure::err_msg("version 0 boxes do not record their scheme, name it with --legacy-scheme")
+    })?;
+    let variant = parse_legacy_scheme(scheme)?;
+    let v0 = read_box(opt)?;
+    let key = legacy::v0_key(&password, &variant);
+    let eb = build_box(password, opt, config, &[])?;
+    let enc =
+        legacy::migrate(&key, &v0[..], &variant, &eb).with_context(|_| "migration failed!")?;
+    write_box(opt, config, &enc[..])
+}
+
+/// accepts the name or numeric index of one of the schemes version 0 boxes
+/// were sealed with (see legacy.rs)
+fn parse_legacy_scheme(scheme: &str) -> Result<aes_defs::OpenSslVariants, ExitFailure> {
+    let variant = match scheme.parse::<u32>() {
+        Ok(index) => aes_defs::openssl_index_to_enum(index),
+        Err(_) => scheme.parse::<aes_defs::OpenSslVariants>(),
+    }
+    .with_context(|_| "unsupported legacy scheme!")?;
+    if !legacy::is_v0_scheme(&variant) {
+        return Err(failure::format_err!(
+            "version 0 boxes were never sealed with scheme '{}'",
+            variant
+        )
+        .into());
+    }
+    Ok(variant)
+}
+
+/// insecure and unauthenticated schemes are only usable with --insecure,
+/// and even then come with a warning
+fn check_security_level(scheme: &SchemeRef, insecure: bool) -> Result<(), ExitFailure> {
+    let problem = match scheme.security_level() {
+        SecurityLevel::Authenticated => return Ok(()),
+        SecurityLevel::Unauthenticated => "is unauthenticated, so tampering goes undetected",
+        SecurityLevel::Insecure => "is insecure, its ciphertexts leak the plaintext",
+    };
+    if !insecure {
+        return Err(failure::format_err!(
+            "scheme '{}' {}; pass --insecure to use it anyway",
+            scheme.identifier(),
+            problem
+        )
+        .into());
+    }
+    eprintln!("warning: scheme '{}' {}", scheme.identifier(), problem);
+    Ok(())
+}
+
 /// This tool allows you to encrypt any number of fields
-/// with AES (choosing from a few flavors). It relies on
-/// the openssl implementation. Output is in base 64. See
+/// with AES or ChaCha20 (choosing from a few flavors). It relies on
+/// the openssl implementation. Output is in base 64 unless
+/// another encoding is chosen. See
 /// https://docs.rs/openssl/0.9.17/openssl/symm/struct.Cipher.html
 /// for more information.
 #[derive(StructOpt, Debug)]
-#[structopt(name = "encrypted-box", version = "0.2.6", author = "")]
+#[structopt(
+    name = "encrypted-box",
+    version = "0.2.6",
+    author = "",
+    raw(setting = "structopt::clap::AppSettings::SubcommandsNegateReqs")
+)]
 struct Opt {
     /// Password for encryption/decryption
-    #[structopt(short = "p", long = "password")]
+    #[structopt(short = "p", long = "password", raw(global = "true"))]
     password: Option<String>,
     /// Path to password file for encryption/decryption (password option supersedes this option)
-    #[structopt(
-        long = "password-file",
-        default_value = ".pass",
-        raw(required = "false"),
-        parse(from_os_str)
-    )]
-    path_to_password: PathBuf,
+    /// [default: the configured keyring, or .pass]
+    #[structopt(long = "password-file", parse(from_os_str), raw(global = "true"))]
+    path_to_password: Option<PathBuf>,
     /// Fields to add
-    #[structopt(short = "f", long = "field", raw(required = "true"))]
+    #[structopt(short = "f", long = "field", raw(required_unless = r#""decrypt""#))]
     fields: Vec<String>,
-    /// Encryption scheme
-    /// Options:     
-    /// [0. AES 128 ECB ;
-    /// 1. AES 128 CBC ;
-    /// 2. AES 128 CTR ;
-    /// 3. AES 128 OFB ;
-    /// 4. AES 192 ECB ;
-    /// 5. AES 192 CBC ;
-    /// 6. AES 192 CTR ;
-    /// 7. AES 192 OFB ;
-    /// 8. AES 256 ECB ;
-    /// 9. AES 256 CBC ;
-    /// 10. AES 256 CTR ;
-    /// 11. AES 256 OFB]
-    #[structopt(short = "s", long = "scheme", default_value = "0")]
-    scheme: u32,
+    /// Encryption scheme name, e.g. aes-256-gcm (see the list-schemes subcommand).
+    /// The numeric indices 0-11 of older versions are still accepted but deprecated
+    /// [default: the configured scheme, or aes-256-gcm]
+    #[structopt(short = "s", long = "scheme", raw(global = "true"))]
+    scheme: Option<String>,
+    /// Output encoding [base64 (default, unless configured), base64url, hex, binary, armor].
+    /// When decrypting the encoding is detected unless given explicitly
+    #[structopt(short = "e", long = "encoding", raw(global = "true"))]
+    encoding: Option<Encoding>,
+    /// Allow insecure (ECB, CTR, OFB) and unauthenticated (CBC) schemes,
+    /// both for encryption and decryption
+    #[structopt(long = "insecure", raw(global = "true"))]
+    insecure: bool,
+    /// Scheme to accept when decrypting (may be repeated). Once given,
+    /// only the listed schemes are accepted
+    #[structopt(long = "allow-scheme", raw(global = "true"))]
+    allow_schemes: Vec<String>,
+    /// Path to a TOML decryption policy (allowed_schemes, min_security_level,
+    /// allowed_kdfs, min_kdf_cost, max_kdf_cost)
+    #[structopt(long = "policy-file", parse(from_os_str), raw(global = "true"))]
+    policy_file: Option<PathBuf>,
+    /// Compress the fields before encrypting them [zstd, deflate]. Opt-in: the
+    /// length of a compressed box depends on its content, so an attacker who
+    /// influences some fields and sees the box can guess the others
+    #[structopt(long = "compress", raw(global = "true"))]
+    compress: Option<Compression>,
+    /// Open boxes outside their validity window (expired or not valid yet),
+    /// e.g. for forensics. Their headers are still authenticated
+    #[structopt(long = "ignore-expiry", raw(global = "true"))]
+    ignore_expiry: bool,
+    /// Scheme of headerless (version 0) boxes, by name or numeric index, e.g.
+    /// aes-256-cbc. Decrypting opens such boxes with it, see also migrate
+    #[structopt(long = "legacy-scheme", raw(global = "true"))]
+    legacy_scheme: Option<String>,
+    /// Decrypt an encrypted box instead of creating one
+    #[structopt(short = "d", long = "decrypt")]
+    decrypt: bool,
+    /// Path to read the encrypted box from when decrypting (defaults to stdin)
+    #[structopt(short = "i", long = "input", parse(from_os_str), raw(global = "true"))]
+    input: Option<PathBuf>,
+    /// Path to write the output to (defaults to stdout)
+    #[structopt(short = "o", long = "output", parse(from_os_str), raw(global = "true"))]
+    output: Option<PathBuf>,
+    /// Path to the config file
+    /// [default: $XDG_CONFIG_HOME/encrypted-box/config.toml]
+    #[structopt(long = "config", parse(from_os_str), raw(global = "true"))]
+    config: Option<PathBuf>,
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// List the supported encryption schemes with their key and IV lengths
+    #[structopt(name = "list-schemes")]
+    ListSchemes,
+    /// Encrypt or decrypt the selected columns of every record of a CSV
+    /// (with a header row) or JSON Lines file. Records that fail are left
+    /// out of the output and reported
+    #[structopt(name = "batch")]
+    Batch(BatchOpt),
+    /// Print the blind index of every value, as stored by boxes built with
+    /// that index (e.g. to look up the rows holding an email)
+    #[structopt(name = "index")]
+    Index {
+        /// The name of the index, e.g. email
+        #[structopt(short = "n", long = "name")]
+        name: String,
+        /// The length of the index in bytes
+        #[structopt(short = "l", long = "length", default_value = "8")]
+        length: usize,
+        /// The values to index
+        #[structopt(raw(required = "true"))]
+        values: Vec<String>,
+    },
+    /// Reseal a headerless (version 0) box, sealed with --legacy-scheme, in
+    /// the current format with the scheme and KDF of new boxes
+    #[structopt(name = "migrate")]
+    Migrate,
+    /// Time the KDF on this machine and recommend its parameters
+    #[structopt(name = "bench")]
+    Bench {
+        /// How long deriving a key may take, in milliseconds
+        #[structopt(long = "target-ms", default_value = "500")]
+        target_ms: u64,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+struct BatchOpt {
+    /// encrypt or decrypt
+    #[structopt(raw(possible_values = r#"&["encrypt", "decrypt"]"#))]
+    direction: BatchDirection,
+    /// Column (CSV) or key (JSON Lines) to encrypt or decrypt (may be repeated)
+    #[structopt(short = "c", long = "column", raw(number_of_values = "1"))]
+    columns: Vec<String>,
+    /// Record format [csv, jsonl], detected from the input file's extension unless given
+    #[structopt(long = "format")]
+    format: Option<Format>,
+    /// Seal the selected columns of a record into one box (stored in --row-column)
+    /// instead of one box per cell
+    #[structopt(long = "per-row")]
+    per_row: bool,
+    /// The column holding the box of a record with --per-row
+    #[structopt(long = "row-column", default_value = "encrypted_box")]
+    row_column: String,
+    /// Number of threads to encrypt with [default: one per core]
+    #[structopt(short = "j", long = "jobs")]
+    jobs: Option<usize>,
+    /// Path to write the failed records to (as JSON Lines) instead of stderr
+    #[structopt(long = "error-report", parse(from_os_str))]
+    error_report: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+enum BatchDirection {
+    Encrypt,
+    Decrypt,
+}
+
+impl FromStr for BatchDirection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<BatchDirection, String> {
+        match s {
+            "encrypt" => Ok(BatchDirection::Encrypt),
+            "decrypt" => Ok(BatchDirection::Decrypt),
+            _ => Err(format!("unknown direction '{}'", s)),
+        }
+    }
 }
 
 fn get_password(password: Option<String>, path: PathBuf) -> Result<String, std::io::Error> {
     let password = match password {
         Some(pass) => pass,
-        None => fs::read_to_string(path.to_str().unwrap())?,
+        None => fs::read_to_string(path)?,
     };
     Ok(password)
 }
+
+fn read_input(path: Option<PathBuf>) -> Result<Vec<u8>, std::io::Error> {
+    match path {
+        Some(path) => fs::read(path),
+        None => {
+            let mut buf = Vec::new();
+            io::stdin().read_to_end(&mut buf)?;
+            Ok(buf)
+        }
+    }
+}
+
+fn write_output(path: Option<PathBuf>, data: &[u8]) -> Result<(), std::io::Error> {
+    match path {
+        Some(path) => fs::write(path, data),
+        None => io::stdout().write_all(data),
+    }
+}
@@ -0,0 +1,196 @@
+use crate::errors::{Error, Result};
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+// Boxes of redundant data (e.g. JSON) shrink several times when the framed
+// fields (see framing.rs) are compressed before encryption:
+//
+//   plaintext  the compressed framed fields, framed again as a single field
+//              (so that padding, see padding.rs, may follow them)
+//
+// WARNING: the length of a compressed ciphertext depends on the content, not
+// only on its length. When an attacker controls part of the fields and sees
+// the box (e.g. a token holding both a secret and a user name), they can guess
+// the secret piece by piece, keeping the guesses that compress best (CRIME,
+// BREACH). Compress only fields nobody else influences, and consider padding
+// to hide what is left of the length. Boxes are not compressed by default.
+//
+// The algorithm is recorded in the header (see header.rs), which authenticated
+// schemes protect in version 2 boxes, so it cannot be swapped for another.
+//
+// A few bytes of compressed data may decompress to gigabytes, so decryption
+// stops at the policy's limit (see `DecryptPolicy::max_decompressed_len`).
+
+const COMPRESSION_DEFLATE: u8 = 1;
+const COMPRESSION_ZSTD: u8 = 2;
+
+// zstd's default level, a good balance for small records
+const ZSTD_LEVEL: i32 = 3;
+
+/// How the plaintext of a box is compressed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Compression {
+    /// no compression, the default
+    #[default]
+    None,
+    /// raw deflate (RFC 1951)
+    Deflate,
+    /// zstd (RFC 8878)
+    Zstd,
+}
+
+impl Compression {
+    /// the name of the algorithm, as accepted by `from_str`
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Deflate => "deflate",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    /// compress data
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Deflate => {
+                let mut encoder =
+                    flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).map_err(compression_failed)?;
+                encoder.finish().map_err(compression_failed)
+            }
+            Compression::Zstd => zstd::encode_all(data, ZSTD_LEVEL).map_err(compression_failed),
+        }
+    }
+
+    /// decompress data, failing once it decompresses to more than limit bytes
+    pub fn decompress(self, data: &[u8], limit: u64) -> Result<Vec<u8>> {
+        let decoder: Box<dyn Read + '_> = match self {
+            Compression::None => Box::new(data),
+            Compression::Deflate => Box::new(flate2::read::DeflateDecoder::new(data)),
+            Compression::Zstd => Box::new(
+                zstd::Decoder::with_buffer(data)
+                    .map_err(|e| Error::malformed(format!("bad compressed data: {}", e)))?,
+            ),
+        };
+        let mut out = Vec::new();
+        decoder
+            .take(limit.saturating_add(1))
+            .read_to_end(&mut out)
+            .map_err(|e| Error::malformed(format!("bad compressed data: {}", e)))?;
+        if out.len() as u64 > limit {
+            return Err(Error::policy(format!(
+                "the box decompresses to more than {} bytes",
+                limit
+            )));
+        }
+        Ok(out)
+    }
+
+    /// the compression's value in a header extension: u8 algorithm.
+    /// None is not recorded
+    pub(crate) fn to_bytes(self) -> Vec<u8> {
+        match self {
+            Compression::None => Vec::new(),
+            Compression::Deflate => vec![COMPRESSION_DEFLATE],
+            Compression::Zstd => vec![COMPRESSION_ZSTD],
+        }
+    }
+
+    /// parse the value of a compression header extension
+    pub(crate) fn from_bytes(value: &[u8]) -> Result<Compression> {
+        match value {
+            [COMPRESSION_DEFLATE] => Ok(Compression::Deflate),
+            [COMPRESSION_ZSTD] => Ok(Compression::Zstd),
+            _ => Err(Error::malformed("unknown compression")),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Compression> {
+        match s {
+            "none" => Ok(Compression::None),
+            "deflate" => Ok(Compression::Deflate),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(Error::scheme(format!(
+                "unknown compression '{}' (expected none, deflate or zstd)",
+                s
+            ))),
+        }
+    }
+}
+
+fn compression_failed(e: std::io::Error) -> Error {
+    Error::scheme(format!("compression failed: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ErrorKind;
+
+    const JSON: &[u8] = br#"[{"name": "alice", "role": "admin"}, {"name": "bob", "role": "admin"}, {"name": "carol", "role": "admin"}]"#;
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        for compression in &[Compression::None, Compression::Deflate, Compression::Zstd] {
+            let compressed = compression.compress(JSON)?;
+            assert_eq!(compression.decompress(&compressed, 1 << 20)?, JSON);
+            if *compression != Compression::None {
+                assert!(compressed.len() < JSON.len(), "{}", compression);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn decompression_is_limited() -> Result<()> {
+        let zeros = vec![0u8; 1 << 20];
+        for compression in &[Compression::Deflate, Compression::Zstd] {
+            let bomb = compression.compress(&zeros)?;
+            assert!(bomb.len() < 2048, "{}", compression);
+            assert_eq!(compression.decompress(&bomb, 1 << 20)?.len(), 1 << 20);
+            match compression
+                .decompress(&bomb, (1 << 20) - 1)
+                .unwrap_err()
+                .kind()
+            {
+                ErrorKind::PolicyViolation(_) => {}
+                kind => panic!("unexpected error {:?}", kind),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        for compression in &[Compression::Deflate, Compression::Zstd] {
+            assert!(compression.decompress(b"not compressed", 1 << 20).is_err());
+        }
+    }
+
+    #[test]
+    fn names_and_header_values() -> Result<()> {
+        for compression in &[Compression::Deflate, Compression::Zstd] {
+            assert_eq!(compression.name().parse::<Compression>()?, *compression);
+            assert_eq!(
+                Compression::from_bytes(&compression.to_bytes())?,
+                *compression
+            );
+        }
+        assert!("gzip".parse::<Compression>().is_err());
+        assert!(Compression::from_bytes(b"\x03").is_err());
+        assert!(Compression::from_bytes(b"").is_err());
+        Ok(())
+    }
+}
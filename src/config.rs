@@ -0,0 +1,155 @@
+use crate::encoding::Encoding;
+use crate::errors::{Error, Result};
+use crate::kdf::Kdf;
+use crate::policy::DecryptPolicy;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Defaults for the CLI, so they need not be repeated on every invocation.
+///
+/// Read from `$XDG_CONFIG_HOME/encrypted-box/config.toml` (see `default_path`)
+/// unless another file is given. Every key is optional and flags given on
+/// the command line take precedence, e.g.
+/// ```toml
+/// scheme = "chacha20-poly1305"
+/// encoding = "armor"
+/// keyring = "password"  # relative to the config file's directory
+/// kdf = { algorithm = "pbkdf2-sha256", iterations = 200000 }
+///
+/// [policy]
+/// allowed_schemes = ["chacha20-poly1305"]
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// the scheme new boxes are sealed with (see `OpenSslVariants::name`)
+    pub scheme: Option<String>,
+    /// the KDF new boxes are sealed with
+    pub kdf: Option<Kdf>,
+    /// the output encoding
+    pub encoding: Option<Encoding>,
+    /// the password file, used instead of `.pass` in the working directory
+    pub keyring: Option<PathBuf>,
+    /// the decryption policy, used instead of the default policy
+    pub policy: Option<DecryptPolicy>,
+}
+
+impl Config {
+    /// parse a config, relative paths are left as they are
+    pub fn from_toml(toml: &str) -> Result<Config> {
+        toml::from_str(toml).map_err(|e| Error::config(format!("invalid config: {}", e)))
+    }
+
+    /// read a config file, relative paths in it are relative to its directory
+    pub fn load(path: &Path) -> Result<Config> {
+        let toml = fs::read_to_string(path)
+            .map_err(|e| Error::config(format!("could not read {}: {}", path.display(), e)))?;
+        let mut config = Config::from_toml(&toml)?;
+        if let (Some(keyring), Some(dir)) = (&config.keyring, path.parent()) {
+            config.keyring = Some(dir.join(keyring));
+        }
+        Ok(config)
+    }
+
+    /// read the config file at `default_path`, if there is one
+    pub fn load_default() -> Result<Config> {
+        match default_path() {
+            Some(path) if path.is_file() => Config::load(&path),
+            _ => Ok(Config::default()),
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/encrypted-box/config.toml`, where `XDG_CONFIG_HOME`
+/// falls back to `$HOME/.config` as the XDG base directory spec says
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if Path::new(&dir).is_absolute() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("encrypted-box").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption_scheme::SecurityLevel;
+    use crate::errors::ErrorKind;
+    use std::io::Write;
+
+    #[test]
+    fn empty_config() -> Result<()> {
+        assert_eq!(Config::from_toml("")?, Config::default());
+        Ok(())
+    }
+
+    #[test]
+    fn full_config() -> Result<()> {
+        let config = Config::from_toml(
+            r#"
+            scheme = "chacha20-poly1305"
+            encoding = "base64url"
+            keyring = "/etc/encrypted-box/pass"
+            kdf = { algorithm = "pbkdf2-sha256", iterations = 200000 }
+
+            [policy]
+            min_security_level = "unauthenticated"
+            "#,
+        )?;
+        let mut policy = DecryptPolicy::default();
+        policy.min_security_level(SecurityLevel::Unauthenticated);
+        let expected = Config {
+            scheme: Some(String::from("chacha20-poly1305")),
+            kdf: Some(Kdf::Pbkdf2Sha256 {
+                iterations: 200_000,
+            }),
+            encoding: Some(Encoding::Base64Url),
+            keyring: Some(PathBuf::from("/etc/encrypted-box/pass")),
+            policy: Some(policy),
+        };
+        assert_eq!(config, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_kdf() -> Result<()> {
+        let config = Config::from_toml(r#"kdf = { algorithm = "sha512" }"#)?;
+        assert_eq!(config.kdf, Some(Kdf::Sha512));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        for toml in &[
+            "schema = \"aes-256-gcm\"",
+            "encoding = \"base32\"",
+            "kdf = { algorithm = \"pbkdf2-sha256\" }",
+            "kdf = { algorithm = \"scrypt\" }",
+            "[policy]\nallow_everything = true",
+        ] {
+            match Config::from_toml(toml) {
+                Err(e) => assert!(matches!(e.kind(), ErrorKind::ConfigError(_))),
+                Ok(_) => panic!("accepted {}", toml),
+            }
+        }
+    }
+
+    #[test]
+    fn load_resolves_keyring() -> Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let mut file = fs::File::create(&path).unwrap();
+        writeln!(file, "keyring = \"pass\"").unwrap();
+
+        let config = Config::load(&path)?;
+        assert_eq!(config.keyring, Some(dir.path().join("pass")));
+        Ok(())
+    }
+
+    #[test]
+    fn load_missing_file() {
+        assert!(Config::load(Path::new("no/such/config.toml")).is_err());
+    }
+}
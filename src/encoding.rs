@@ -0,0 +1,274 @@
+use crate::errors::{Error, Result};
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+
+const ARMOR_BEGIN: &str = "-----BEGIN ENCRYPTED BOX-----";
+const ARMOR_END: &str = "-----END ENCRYPTED BOX-----";
+const ARMOR_LINE_LEN: usize = 64;
+
+// CRC-24 as used by OpenPGP armor (RFC 4880, section 6.1)
+const CRC24_INIT: u32 = 0x00B7_04CE;
+const CRC24_POLY: u32 = 0x0186_4CFB;
+
+/// The textual (or binary) representations an encrypted box can be written in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// standard base64 alphabet with padding
+    Base64,
+    /// URL and filename safe base64 alphabet without padding
+    Base64Url,
+    /// lowercase hexadecimal
+    Hex,
+    /// raw bytes, no encoding at all
+    Binary,
+    /// base64 wrapped in BEGIN/END ENCRYPTED BOX lines with a CRC-24 checksum
+    Armor,
+}
+
+impl Encoding {
+    pub fn iterator() -> std::slice::Iter<'static, Encoding> {
+        static ENCODINGS: [Encoding; 5] = [
+            Encoding::Base64,
+            Encoding::Base64Url,
+            Encoding::Hex,
+            Encoding::Binary,
+            Encoding::Armor,
+        ];
+        ENCODINGS.iter()
+    }
+
+    /// the name of this encoding as accepted by `FromStr`
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Base64 => "base64",
+            Encoding::Base64Url => "base64url",
+            Encoding::Hex => "hex",
+            Encoding::Binary => "binary",
+            Encoding::Armor => "armor",
+        }
+    }
+
+    /// whether the encoded output is printable text
+    pub fn is_text(self) -> bool {
+        self != Encoding::Binary
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Encoding> {
+        Encoding::iterator()
+            .find(|e| e.name().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| Error::encoding(format!("unknown encoding '{}'", s)))
+    }
+}
+
+/// encode data with the given encoding
+pub fn encode(data: &[u8], encoding: Encoding) -> Vec<u8> {
+    match encoding {
+        Encoding::Base64 => base64::encode(data).into_bytes(),
+        Encoding::Base64Url => base64::encode_config(data, base64::URL_SAFE_NO_PAD).into_bytes(),
+        Encoding::Hex => hex::encode(data).into_bytes(),
+        Encoding::Binary => data.to_vec(),
+        Encoding::Armor => armor(data).into_bytes(),
+    }
+}
+
+/// decode data which is known to be in the given encoding
+pub fn decode(data: &[u8], encoding: Encoding) -> Result<Vec<u8>> {
+    if encoding == Encoding::Binary {
+        return Ok(data.to_vec());
+    }
+    let text = std::str::from_utf8(data).map_err(Error::encoding)?.trim();
+    match encoding {
+        Encoding::Base64 => base64::decode(text).map_err(Error::encoding),
+        Encoding::Base64Url => {
+            base64::decode_config(text.trim_end_matches('='), base64::URL_SAFE_NO_PAD)
+                .map_err(Error::encoding)
+        }
+        Encoding::Hex => hex::decode(text).map_err(Error::encoding),
+        Encoding::Armor => dearmor(text),
+        Encoding::Binary => unreachable!(),
+    }
+}
+
+/// guess the encoding of data produced by `encode`.
+/// note: a short base64 string consisting only of hex digits is indistinguishable
+/// from hex, in which case hex wins. Encrypted boxes are long enough for this to
+/// practically never happen, but callers who know the encoding should use `decode`.
+pub fn detect(data: &[u8]) -> Encoding {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text.trim(),
+        Err(_) => return Encoding::Binary,
+    };
+    if text.starts_with(ARMOR_BEGIN) {
+        return Encoding::Armor;
+    }
+    if text.is_empty() || !text.bytes().all(is_base64_byte) {
+        return Encoding::Binary;
+    }
+    if text.len() % 2 == 0 && text.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Encoding::Hex;
+    }
+    if text.bytes().any(|b| b == b'-' || b == b'_') || text.len() % 4 != 0 {
+        return Encoding::Base64Url;
+    }
+    Encoding::Base64
+}
+
+/// decode data, detecting its encoding first (see `detect`)
+pub fn decode_auto(data: &[u8]) -> Result<Vec<u8>> {
+    decode(data, detect(data))
+}
+
+/// wrap data in an ASCII armor
+pub fn armor(data: &[u8]) -> String {
+    let body = base64::encode(data);
+    let mut out = String::with_capacity(body.len() + body.len() / ARMOR_LINE_LEN + 96);
+    out.push_str(ARMOR_BEGIN);
+    out.push_str("\n\n");
+    for line in body.as_bytes().chunks(ARMOR_LINE_LEN) {
+        // base64 output is always ASCII, so this cannot fail
+        out.push_str(std::str::from_utf8(line).unwrap());
+        out.push('\n');
+    }
+    out.push('=');
+    out.push_str(&base64::encode(&crc24(data).to_be_bytes()[1..]));
+    out.push('\n');
+    out.push_str(ARMOR_END);
+    out.push('\n');
+    out
+}
+
+/// remove an ASCII armor, verifying its checksum
+pub fn dearmor(text: &str) -> Result<Vec<u8>> {
+    let mut lines = text.trim().lines().map(str::trim);
+    if lines.next() != Some(ARMOR_BEGIN) {
+        return Err(Error::encoding("missing armor header line"));
+    }
+    let mut body = String::new();
+    let mut checksum = None;
+    let mut ended = false;
+    for line in lines {
+        if line == ARMOR_END {
+            ended = true;
+            break;
+        } else if let Some(sum) = line.strip_prefix('=') {
+            checksum = Some(sum);
+        } else if checksum.is_some() {
+            return Err(Error::encoding("data after armor checksum"));
+        } else {
+            body.push_str(line);
+        }
+    }
+    if !ended {
+        return Err(Error::encoding("missing armor footer line"));
+    }
+    let data = base64::decode(&body).map_err(Error::encoding)?;
+    let checksum = checksum.ok_or_else(|| Error::encoding("missing armor checksum"))?;
+    let checksum = base64::decode(checksum).map_err(Error::encoding)?;
+    if checksum[..] != crc24(&data).to_be_bytes()[1..] {
+        return Err(Error::encoding("armor checksum mismatch"));
+    }
+    Ok(data)
+}
+
+fn is_base64_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'+' || b == b'/' || b == b'-' || b == b'_' || b == b'='
+}
+
+fn crc24(data: &[u8]) -> u32 {
+    let mut crc = CRC24_INIT;
+    for byte in data {
+        crc ^= u32::from(*byte) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x0100_0000 != 0 {
+                crc ^= CRC24_POLY;
+            }
+        }
+    }
+    crc & 0x00FF_FFFF
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &[u8] =
+        b"\xB4\xB9\xE7\x30\xD6\xD6\xF7\xDE\x77\x3F\x1C\xFF\xB3\x3E\x44\x5A\x91\xD7\x27\x62\x87\x4D\
+                          \xFB\x3C\x5E\xC4\x59\x72\x4A\xF4\x7C\xA1";
+
+    #[test]
+    fn encode_decode_all_encodings() -> Result<()> {
+        for encoding in Encoding::iterator() {
+            let enc = encode(DATA, *encoding);
+            assert_eq!(decode(&enc, *encoding)?, DATA);
+            assert_eq!(decode_auto(&enc)?, DATA);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn detect_all_encodings() {
+        for encoding in Encoding::iterator() {
+            assert_eq!(detect(&encode(DATA, *encoding)), *encoding);
+        }
+    }
+
+    #[test]
+    fn base64url_is_url_safe() {
+        let enc = encode(&[0xFB; 30], Encoding::Base64Url);
+        assert!(enc
+            .iter()
+            .all(|b| b.is_ascii_alphanumeric() || *b == b'-' || *b == b'_'));
+    }
+
+    #[test]
+    fn name_round_trip() -> Result<()> {
+        for encoding in Encoding::iterator() {
+            assert_eq!(encoding.to_string().parse::<Encoding>()?, *encoding);
+        }
+        assert!("base32".parse::<Encoding>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn crc24_known_value() {
+        // check value of the OpenPGP CRC-24 over "123456789"
+        assert_eq!(crc24(b"123456789"), 0x0021_CF02);
+    }
+
+    #[test]
+    fn armor_wraps_long_lines() {
+        let armored = armor(&[7u8; 200]);
+        assert!(armored.starts_with(ARMOR_BEGIN));
+        assert!(armored.trim_end().ends_with(ARMOR_END));
+        assert!(armored
+            .lines()
+            .all(|l| l.len() <= ARMOR_LINE_LEN || l.starts_with("-----")));
+    }
+
+    #[test]
+    fn armor_detects_corruption() {
+        let armored = armor(DATA).replacen("tLnn", "tLno", 1);
+        assert!(dearmor(&armored).is_err());
+    }
+
+    #[test]
+    fn armor_requires_footer() {
+        let armored = armor(DATA);
+        let truncated = armored.trim_end().trim_end_matches(ARMOR_END);
+        assert!(dearmor(truncated).is_err());
+    }
+}
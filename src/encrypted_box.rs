@@ -1,89 +1,861 @@
-use crate::encryption_scheme::EncryptionScheme;
-use crate::kdf;
+use crate::blind_index::BlindIndex;
+use crate::compression::Compression;
+use crate::encryption_scheme::{EncryptionScheme, SchemeRef};
+use crate::errors::{Error, Result};
+use crate::framing;
+use crate::header::{Header, LEGACY_VERSION, VERSION};
+use crate::kdf::{KdfParams, KeyCache};
+use crate::padding::Padding;
+use crate::policy::DecryptPolicy;
+use crate::registry::SchemeRegistry;
+use crate::subkeys::{MasterKey, Purpose, MASTER_KEY_LEN};
+use crate::validity::{self, Validity};
+use rayon::prelude::*;
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// The fields of a box
+pub type Fields = Vec<Vec<u8>>;
+
+// room reserved for a scheme's nonce, padding and tag, so that sealing a
+// record into an empty buffer allocates only once
+const SCHEME_OVERHEAD: usize = 64;
 
 /// An implementation of an encrypted box
 /// which holds the encryption of a few fields
 pub struct EncryptedBox<T> {
-    fields: Vec<u8>,
+    fields: Vec<Vec<u8>>,
+    // the scheme's key, derived from the master key according to version
+    // (see header.rs)
     key: Vec<u8>,
+    // kept to authenticate headers (see validity.rs), empty for legacy boxes
+    master_key: Vec<u8>,
+    kdf: KdfParams,
     scheme: T,
+    version: u8,
+    padding: Padding,
+    compression: Compression,
+    validity: Validity,
+    // the index and the position of the field it indexes
+    blind_index: Option<(BlindIndex, usize)>,
 }
 
 impl<T> EncryptedBox<T>
 where
     T: EncryptionScheme + Clone,
+    T::Error: Display,
 {
-    /// create a new encrypted box
-    pub fn new(fields: Vec<u8>, key: Vec<u8>, scheme: T) -> EncryptedBox<T> {
+    /// create a new encrypted box. master_key must have been derived with kdf
+    /// (`MASTER_KEY_LEN` bytes, see subkeys.rs); the scheme gets a subkey of it
+    pub fn new(
+        fields: Vec<Vec<u8>>,
+        master_key: Vec<u8>,
+        kdf: KdfParams,
+        scheme: T,
+    ) -> EncryptedBox<T> {
+        let key = scheme_key(&master_key, &scheme);
         EncryptedBox {
             fields,
             key,
+            master_key,
+            kdf,
             scheme,
+            version: VERSION,
+            padding: Padding::None,
+            compression: Compression::None,
+            validity: Validity::default(),
+            blind_index: None,
+        }
+    }
+
+    /// pad the plaintext of the boxes this box seals (see padding.rs).
+    /// Fails for buckets of 0 bytes, which no box could be opened with
+    pub fn with_padding(mut self, padding: Padding) -> Result<EncryptedBox<T>> {
+        if padding == Padding::Bucket(0) {
+            return Err(Error::scheme("cannot pad to buckets of 0 bytes"));
         }
+        self.padding = padding;
+        Ok(self)
+    }
+
+    /// how the plaintext of this box is padded
+    pub fn padding(&self) -> Padding {
+        self.padding
+    }
+
+    /// compress the plaintext of the boxes this box seals. Read the warning
+    /// in compression.rs first: compressing fields an attacker influences
+    /// can leak the others
+    pub fn with_compression(mut self, compression: Compression) -> EncryptedBox<T> {
+        self.compression = compression;
+        self
+    }
+
+    /// how the plaintext of this box is compressed
+    pub fn compression(&self) -> Compression {
+        self.compression
     }
 
-    /// encrypt content (fields)
-    pub fn encrypt(&self) -> Result<Vec<u8>, T::Error> {
-        self.scheme.encrypt(&self.key[..], &self.fields[..])
+    /// seal boxes that only open inside validity (see validity.rs). Their
+    /// header is then authenticated
+    pub fn with_validity(mut self, validity: Validity) -> EncryptedBox<T> {
+        self.validity = validity;
+        self
     }
 
-    /// decrypt ciphertext into new encrypted box
+    /// when the boxes this box seals may be opened
+    pub fn validity(&self) -> Validity {
+        self.validity
+    }
+
+    /// compute the blind index of the field at position field of this box
+    /// with index (see `blind_index` and `encrypt_indexed`)
+    pub fn with_blind_index(mut self, index: BlindIndex, field: usize) -> EncryptedBox<T> {
+        self.blind_index = Some((index, field));
+        self
+    }
+
+    /// the blind index of this box, if it has one and the field it indexes
+    /// (see blind_index.rs)
+    pub fn blind_index(&self) -> Option<Vec<u8>> {
+        let (index, field) = self.blind_index.as_ref()?;
+        self.fields.get(*field).map(|value| index.index(value))
+    }
+
+    /// the format version this box is sealed with (see header.rs)
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// the (decrypted) fields of this box
+    pub fn fields(&self) -> &[Vec<u8>] {
+        &self.fields[..]
+    }
+
+    /// encrypt content (fields) into a self-describing box (see header.rs)
+    pub fn encrypt(&self) -> Result<Vec<u8>> {
+        self.encrypt_fields(&self.fields)
+    }
+
+    /// encrypt content (see `encrypt`), returning the box together with its
+    /// blind index. Fails if the box has no blind index
+    pub fn encrypt_indexed(&self) -> Result<(Vec<u8>, Vec<u8>)> {
+        let index = self.blind_index().ok_or_else(|| {
+            Error::scheme(
+                "the box has no blind index, or lacks the field it indexes, \
+                 see EncryptedBoxBuilder::with_blind_index",
+            )
+        })?;
+        Ok((self.encrypt()?, index))
+    }
+
+    /// like `encrypt`, appending the box to out. Reusing out across records
+    /// saves an allocation per record. On error out is left as it was
+    pub fn encrypt_into(&self, out: &mut Vec<u8>) -> Result<()> {
+        self.encrypt_fields_into(&self.fields, out)
+    }
+
+    /// encrypt other fields with the key, KDF parameters and scheme of this box.
+    /// This is how many records are sealed while deriving the key only once
+    pub fn encrypt_fields(&self, fields: &[Vec<u8>]) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        self.encrypt_fields_into(fields, &mut out)?;
+        Ok(out)
+    }
+
+    /// like `encrypt_fields`, appending the box to out (see `encrypt_into`)
+    pub fn encrypt_fields_into(&self, fields: &[Vec<u8>], out: &mut Vec<u8>) -> Result<()> {
+        let mut header = Header::new(self.scheme.identifier(), self.kdf.clone());
+        header.version = self.version;
+        header.padding = self.padding;
+        header.compression = self.compression;
+        header.validity = self.validity;
+        let bound_key;
+        let key = if self.validity.is_unbounded() {
+            &self.key
+        } else {
+            if self.master_key.is_empty() {
+                return Err(Error::scheme("legacy boxes cannot have a validity window"));
+            }
+            let tag = validity::header_tag(
+                &self.master_key,
+                &header.scheme,
+                &header.authenticated_bytes()?,
+            );
+            bound_key = tagged_scheme_key(&self.master_key, &self.scheme, &tag);
+            header.tag = Some(tag);
+            &bound_key
+        };
+        let mut plaintext = match self.compression {
+            Compression::None => framing::encode(fields),
+            compression => framing::encode(&[compression.compress(&framing::encode(fields))?]),
+        };
+        self.padding.pad(&mut plaintext);
+        out.reserve(header.encoded_len() + plaintext.len() + SCHEME_OVERHEAD);
+        let begin = out.len();
+        header.write_to(out)?;
+        let sealed = if self.version == LEGACY_VERSION {
+            self.scheme.encrypt_into(&key[..], &plaintext[..], out)
+        } else {
+            // the header is the associated data, so altering it fails decryption
+            let aad = out[begin..].to_vec();
+            self.scheme
+                .encrypt_with_aad_into(&key[..], &aad, &plaintext[..], out)
+        };
+        sealed.map_err(|e| {
+            out.truncate(begin);
+            Error::scheme(e)
+        })
+    }
+
+    /// encrypt every item of fields (see `encrypt_fields`) in parallel, on
+    /// rayon's current thread pool (see `rayon::ThreadPool::install`).
+    /// The results are in the order of items
+    pub fn encrypt_many<I>(&self, items: I) -> Vec<Result<Vec<u8>>>
+    where
+        I: IntoIterator<Item = Fields>,
+        T: Sync,
+    {
+        let items: Vec<Fields> = items.into_iter().collect();
+        items
+            .par_iter()
+            .map(|fields| self.encrypt_fields(fields))
+            .collect()
+    }
+
+    /// decrypt ciphertext into new encrypted box.
+    /// The scheme and KDF are read from the box, and must be acceptable to policy
     pub fn decrypt(
         password: String,
         ciphertext: &[u8],
-        v: T::Variant,
-    ) -> Result<EncryptedBox<T>, T::Error> {
-        let scheme = T::new(&v);
-        let key = kdf::derive_key_from_password(&password, scheme.get_key_length());
-        let fields = scheme.decrypt(&key, ciphertext)?;
-        Ok(EncryptedBox::new(fields, key, scheme))
+        policy: &DecryptPolicy,
+    ) -> Result<EncryptedBox<T>>
+    where
+        T::Variant: FromStr,
+    {
+        EncryptedBox::decrypt_with_keys(ciphertext, policy, &mut KeyCache::new(password))
+    }
+
+    /// like `decrypt`, but derives the key through keys, so decrypting many
+    /// boxes sealed with the same KDF parameters derives the key only once
+    pub fn decrypt_with_keys(
+        ciphertext: &[u8],
+        policy: &DecryptPolicy,
+        keys: &mut KeyCache,
+    ) -> Result<EncryptedBox<T>>
+    where
+        T::Variant: FromStr,
+    {
+        EncryptedBox::open(ciphertext, policy, keys, |identifier| {
+            let variant = identifier
+                .parse::<T::Variant>()
+                .map_err(|_| Error::scheme(format!("scheme '{}' is unsupported", identifier)))?;
+            Ok(T::new(&variant))
+        })
+    }
+
+    /// decrypt ciphertext with the scheme scheme_for returns for the
+    /// identifier in its header
+    fn open<F>(
+        ciphertext: &[u8],
+        policy: &DecryptPolicy,
+        keys: &mut KeyCache,
+        scheme_for: F,
+    ) -> Result<EncryptedBox<T>>
+    where
+        F: FnOnce(&str) -> Result<T>,
+    {
+        let (header, ctext) = Header::parse(ciphertext)?;
+        policy.check_header(&header)?;
+        let scheme = scheme_for(&header.scheme)?;
+        policy.check_security_level(&header.scheme, scheme.security_level())?;
+        let (key, master_key) = if header.version == LEGACY_VERSION {
+            if header.tag.is_some() {
+                return Err(Error::malformed("legacy boxes have no header tag"));
+            }
+            (
+                keys.derive(&header.kdf, scheme.get_key_length()).to_vec(),
+                Vec::new(),
+            )
+        } else {
+            let master_key = keys.derive(&header.kdf, MASTER_KEY_LEN).to_vec();
+            (scheme_key(&master_key, &scheme), master_key)
+        };
+        let bound_key;
+        let content_key = match &header.tag {
+            Some(tag) => {
+                let authenticated = header.authenticated_bytes()?;
+                validity::verify_header_tag(&master_key, &header.scheme, &authenticated, tag)?;
+                policy.check_validity(&header.validity)?;
+                bound_key = tagged_scheme_key(&master_key, &scheme, tag);
+                &bound_key
+            }
+            None => &key,
+        };
+        let plaintext = if header.version == LEGACY_VERSION {
+            scheme.decrypt(content_key, ctext)
+        } else {
+            let aad = &ciphertext[..ciphertext.len() - ctext.len()];
+            scheme.decrypt_with_aad(content_key, aad, ctext)
+        }
+        .map_err(Error::scheme)?;
+        let mut fields = framing::decode_padded(&plaintext[..], header.padding)?;
+        if header.compression != Compression::None {
+            let compressed = match &fields[..] {
+                [compressed] => compressed,
+                _ => return Err(Error::malformed("a compressed box holds a single frame")),
+            };
+            let framed = header
+                .compression
+                .decompress(compressed, policy.decompression_limit())?;
+            fields = framing::decode(&framed[..])?;
+        }
+        Ok(EncryptedBox {
+            fields,
+            key,
+            master_key,
+            kdf: header.kdf,
+            scheme,
+            version: header.version,
+            padding: header.padding,
+            compression: header.compression,
+            validity: header.validity,
+            blind_index: None,
+        })
+    }
+}
+
+impl EncryptedBox<SchemeRef> {
+    /// decrypt a box sealed with any scheme of registry, see `decrypt_with_keys`
+    pub fn decrypt_with_registry(
+        ciphertext: &[u8],
+        policy: &DecryptPolicy,
+        keys: &mut KeyCache,
+        registry: &SchemeRegistry,
+    ) -> Result<EncryptedBox<SchemeRef>> {
+        EncryptedBox::open(ciphertext, policy, keys, |identifier| {
+            registry.get(identifier)
+        })
     }
 }
 
+/// the content encryption subkey of master_key for scheme
+fn scheme_key<T: EncryptionScheme>(master_key: &[u8], scheme: &T) -> Vec<u8> {
+    MasterKey::new(master_key).subkey(
+        Purpose::ContentEncryption,
+        scheme.identifier(),
+        scheme.get_key_length(),
+    )
+}
+
+/// the content encryption subkey of master_key for scheme in a box with a
+/// header tag, bound to the tag (see validity.rs)
+fn tagged_scheme_key<T: EncryptionScheme>(master_key: &[u8], scheme: &T, tag: &[u8]) -> Vec<u8> {
+    MasterKey::new(master_key).subkey(
+        Purpose::ContentEncryption,
+        &format!("{} {}", scheme.identifier(), hex::encode(tag)),
+        scheme.get_key_length(),
+    )
+}
+
 #[cfg(test)]
 mod tests {
 
-    use super::EncryptedBox;
-    use crate::encryption_scheme::EncryptionScheme;
-    use crate::kdf;
-    use crate::openssl_aes::{defs::OpenSslVariants as aes_variant, wrapper as aes};
+    use super::{scheme_key, EncryptedBox, Error, Fields};
+    use crate::compression::Compression;
+    use crate::encryption_scheme::{DefaultScheme, EncryptionScheme, SchemeRef};
+    use crate::errors::{ErrorKind, Result};
+    use crate::header::{Header, LEGACY_VERSION, VERSION};
+    use crate::kdf::{Kdf, KdfParams, KeyCache};
+    use crate::openssl_aes::defs::OpenSslVariants as aes_variant;
+    use crate::padding::Padding;
+    use crate::policy::DecryptPolicy;
+    use crate::registry::SchemeRegistry;
+    use crate::subkeys::MASTER_KEY_LEN;
+    use crate::validity::{from_unix_seconds, Clock, Validity};
 
     const FIELDS: &[u8; 16] = b"Some Crypto Text";
-    const KEY: &'static [u8; 16] =
-        b"\x00\x01\x02\x03\x04\x05\x06\x07\x08\x09\x0A\x0B\x0C\x0D\x0E\x0F";
-    const PASSWORD: &'static str = "password";
+    const PASSWORD: &str = "password";
+
+    fn fields() -> Vec<Vec<u8>> {
+        vec![FIELDS[..8].to_vec(), FIELDS[8..].to_vec()]
+    }
+
+    fn seal(variant: &aes_variant, kdf: Kdf) -> Result<Vec<u8>> {
+        let scheme = DefaultScheme::new(variant);
+        let params = KdfParams::new(kdf);
+        let key = params.derive(PASSWORD, MASTER_KEY_LEN);
+        EncryptedBox::new(fields(), key, params, scheme).encrypt()
+    }
+
+    fn open(enc: &[u8], policy: &DecryptPolicy) -> Result<EncryptedBox<DefaultScheme>> {
+        EncryptedBox::decrypt(String::from(PASSWORD), enc, policy)
+    }
+
+    fn permissive() -> DecryptPolicy {
+        let mut policy = DecryptPolicy::new();
+        for variant in aes_variant::iterator() {
+            policy.allow_scheme(variant.name());
+        }
+        policy.allow_kdf("sha512").min_kdf_cost(1);
+        policy
+    }
+
+    #[test]
+    fn encrypt_is_self_describing() -> Result<()> {
+        let enc = seal(&aes_variant::Aes256Gcm, Kdf::default())?;
+        let (header, _) = Header::parse(&enc[..])?;
+        assert_eq!(header.scheme, "aes-256-gcm");
+        assert_eq!(header.kdf.kdf, Kdf::default());
+        Ok(())
+    }
 
     #[test]
-    fn encrypt_with_vector() -> Result<(), aes::Error> {
-        let scheme = aes::OpensslAesWrapper::new(&aes_variant::Aes128Cbc);
+    fn encrypt_decrypt_aes_all_varieties() -> Result<()> {
+        let kdf = Kdf::Pbkdf2Sha256 { iterations: 1000 };
+        for variant in aes_variant::iterator() {
+            let enc = seal(variant, kdf)?;
+            let dec_eb = open(&enc[..], &permissive())?;
 
-        let eb = EncryptedBox::new(FIELDS[..].to_vec(), KEY.to_vec(), scheme);
-        let enc = eb.encrypt()?;
+            assert_eq!(dec_eb.fields(), &fields()[..]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn default_policy_opens_default_box() -> Result<()> {
+        let enc = seal(&aes_variant::ChaCha20Poly1305, Kdf::default())?;
+        let dec_eb = open(&enc[..], &DecryptPolicy::default())?;
+        assert_eq!(dec_eb.fields(), &fields()[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn default_policy_refuses_weak_boxes() -> Result<()> {
+        let weak = [
+            seal(&aes_variant::Aes128Ecb, Kdf::default())?,
+            seal(&aes_variant::Aes128Gcm, Kdf::Sha512)?,
+            seal(&aes_variant::Aes128Gcm, Kdf::Pbkdf2Sha256 { iterations: 1 })?,
+        ];
+        for enc in weak.iter() {
+            match open(&enc[..], &DecryptPolicy::default()) {
+                Err(e) => assert!(matches!(e.kind(), ErrorKind::PolicyViolation(_))),
+                Ok(_) => panic!("weak box was opened"),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn decrypt_with_wrong_password_fails() -> Result<()> {
+        let enc = seal(&aes_variant::Aes256Gcm, Kdf::default())?;
+        let dec = EncryptedBox::<DefaultScheme>::decrypt(
+            String::from("not the password"),
+            &enc[..],
+            &DecryptPolicy::default(),
+        );
+        assert!(dec.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn schemes_get_independent_keys() {
+        let params = KdfParams::new(Kdf::Pbkdf2Sha256 { iterations: 1000 });
+        let master = params.derive(PASSWORD, MASTER_KEY_LEN);
+        let aes128 = DefaultScheme::new(&aes_variant::Aes128Gcm);
+        let aes256 = DefaultScheme::new(&aes_variant::Aes256Gcm);
+        let eb128 = EncryptedBox::new(vec![], master.clone(), params.clone(), aes128);
+        let eb256 = EncryptedBox::new(vec![], master.clone(), params, aes256);
+        assert_eq!(eb128.key.len(), 16);
+        assert_ne!(&eb128.key[..], &eb256.key[..16]);
+        assert_ne!(&eb256.key[..], &master[..]);
+    }
+
+    #[test]
+    fn legacy_boxes_open_and_keep_their_version() -> Result<()> {
+        // a version 1 box, keyed with the KDF's output
+        let scheme = DefaultScheme::new(&aes_variant::Aes256Gcm);
+        let params = KdfParams::new(Kdf::Pbkdf2Sha256 { iterations: 1000 });
+        let key = params.derive(PASSWORD, scheme.get_key_length());
+        let mut header = Header::new(scheme.identifier(), params);
+        header.version = LEGACY_VERSION;
+        let mut enc = header.to_bytes()?;
+        let plaintext = crate::framing::encode(&fields());
+        enc.extend(scheme.encrypt(&key, &plaintext).map_err(Error::scheme)?);
+
+        let eb = open(&enc, &permissive())?;
+        assert_eq!(eb.fields(), &fields()[..]);
+        assert_eq!(eb.version(), LEGACY_VERSION);
+        let resealed = eb.encrypt()?;
+        assert_eq!(Header::parse(&resealed)?.0.version, LEGACY_VERSION);
+        assert_eq!(open(&resealed, &permissive())?.fields(), &fields()[..]);
 
-        // note: we use constant IVs tailored for this test case.
-        // If IVs change then so should this test (since it will fail).
+        let enc = seal(
+            &aes_variant::Aes256Gcm,
+            Kdf::Pbkdf2Sha256 { iterations: 1000 },
+        )?;
+        assert_eq!(open(&enc, &permissive())?.version(), VERSION);
+        Ok(())
+    }
+
+    #[test]
+    fn padding_hides_lengths() -> Result<()> {
+        let scheme = DefaultScheme::new(&aes_variant::Aes256Gcm);
+        let params = KdfParams::new(Kdf::Pbkdf2Sha256 { iterations: 1000 });
+        let key = params.derive(PASSWORD, MASTER_KEY_LEN);
+        let eb =
+            EncryptedBox::new(vec![], key, params, scheme).with_padding(Padding::Bucket(64))?;
+        let yes = eb.encrypt_fields(&[b"yes".to_vec()])?;
+        let no = eb.encrypt_fields(&[b"no".to_vec()])?;
+        assert_eq!(yes.len(), no.len());
+
+        let opened = open(&yes, &permissive())?;
+        assert_eq!(opened.fields(), &[b"yes".to_vec()][..]);
+        assert_eq!(opened.padding(), Padding::Bucket(64));
         assert_eq!(
-            b"\xB4\xB9\xE7\x30\xD6\xD6\xF7\xDE\x77\x3F\x1C\xFF\xB3\x3E\x44\x5A\x91\xD7\x27\x62\x87\x4D\
-              \xFB\x3C\x5E\xC4\x59\x72\x4A\xF4\x7C\xA1",
-            &enc[..]);
+            Header::parse(&opened.encrypt()?)?.0.padding,
+            Padding::Bucket(64)
+        );
         Ok(())
     }
 
     #[test]
-    fn encrypt_decrypt_aes_all_varieties() -> Result<(), aes::Error> {
-        for variant in aes_variant::iterator() {
-            let scheme = aes::OpensslAesWrapper::new(variant);
+    fn padding_must_match_the_header() -> Result<()> {
+        let scheme = DefaultScheme::new(&aes_variant::Aes256Gcm);
+        let params = KdfParams::new(Kdf::Pbkdf2Sha256 { iterations: 1000 });
+        let key = scheme_key(&params.derive(PASSWORD, MASTER_KEY_LEN), &scheme);
+        // fields padded to 64 bytes, with a header claiming other padding
+        let mut plaintext = crate::framing::encode(&fields());
+        Padding::Bucket(64).pad(&mut plaintext);
+        for padding in &[Padding::None, Padding::Bucket(32), Padding::PowerOfTwo] {
+            let mut header = Header::new(scheme.identifier(), params.clone());
+            header.padding = *padding;
+            let mut enc = header.to_bytes()?;
+            let aad = enc.clone();
+            scheme
+                .encrypt_with_aad_into(&key, &aad, &plaintext, &mut enc)
+                .map_err(Error::scheme)?;
+            match open(&enc, &permissive()).map(|_| ()).unwrap_err().kind() {
+                ErrorKind::MalformedBox(_) => (),
+                kind => panic!("unexpected error {:?}", kind),
+            }
+        }
+        Ok(())
+    }
 
-            let pass = String::from(PASSWORD);
-            let key = kdf::derive_key_from_password(&pass, scheme.get_key_length());
+    #[test]
+    fn padding_is_authenticated() -> Result<()> {
+        let params = KdfParams::new(Kdf::Pbkdf2Sha256 { iterations: 1000 });
+        let key = params.derive(PASSWORD, MASTER_KEY_LEN);
+        let scheme = DefaultScheme::new(&aes_variant::Aes256Gcm);
+        let eb =
+            EncryptedBox::new(fields(), key, params, scheme).with_padding(Padding::Bucket(64))?;
+        let enc = eb.encrypt()?;
+        let header_len = Header::parse(&enc)?.0.to_bytes()?.len();
+        // the header ends with the bucket size, which the forgery halves. The
+        // scheme must reject it, before the fields are unpadded
+        assert_eq!(enc[header_len - 1], 64);
+        let mut forged = enc.clone();
+        forged[header_len - 1] = 32;
+        assert_eq!(Header::parse(&forged)?.0.padding, Padding::Bucket(32));
+        match open(&forged, &permissive()).map(|_| ()).unwrap_err().kind() {
+            ErrorKind::SchemeError(_) => (),
+            kind => panic!("unexpected error {:?}", kind),
+        }
+        assert_eq!(open(&enc, &permissive())?.fields(), &fields()[..]);
+        Ok(())
+    }
 
-            let eb = EncryptedBox::new(FIELDS[..].to_vec(), key, scheme);
+    #[test]
+    fn buckets_of_zero_bytes_are_refused() {
+        let scheme = DefaultScheme::new(&aes_variant::Aes256Gcm);
+        let params = KdfParams::new(Kdf::Pbkdf2Sha256 { iterations: 1000 });
+        let key = params.derive(PASSWORD, MASTER_KEY_LEN);
+        match EncryptedBox::new(fields(), key, params, scheme)
+            .with_padding(Padding::Bucket(0))
+            .map(|_| ())
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::SchemeError(_) => (),
+            kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn compression_shrinks_boxes() -> Result<()> {
+        let params = KdfParams::new(Kdf::Pbkdf2Sha256 { iterations: 1000 });
+        let key = params.derive(PASSWORD, MASTER_KEY_LEN);
+        let json = br#"{"id": 1, "tags": ["a", "a", "a", "a", "a", "a", "a", "a", "a"]}"#.to_vec();
+        let record = vec![json.clone(), json];
+        let plain = EncryptedBox::new(
+            record.clone(),
+            key.clone(),
+            params.clone(),
+            DefaultScheme::new(&aes_variant::Aes256Gcm),
+        )
+        .encrypt()?;
+        for compression in &[Compression::Deflate, Compression::Zstd] {
+            let eb = EncryptedBox::new(
+                record.clone(),
+                key.clone(),
+                params.clone(),
+                DefaultScheme::new(&aes_variant::Aes256Gcm),
+            )
+            .with_compression(*compression)
+            .with_padding(Padding::Bucket(16))?;
             let enc = eb.encrypt()?;
-            let dec_eb: EncryptedBox<aes::OpensslAesWrapper> =
-                EncryptedBox::decrypt(pass, &enc[..], *variant)?;
+            assert!(enc.len() < plain.len(), "{}", compression);
+            let opened = open(&enc, &permissive())?;
+            assert_eq!(opened.fields(), &record[..]);
+            assert_eq!(opened.compression(), *compression);
+            assert_eq!(
+                Header::parse(&opened.encrypt()?)?.0.compression,
+                *compression
+            );
+        }
+        Ok(())
+    }
 
-            assert_eq!(dec_eb.fields[..], FIELDS[..]);
+    #[test]
+    fn compression_is_authenticated() -> Result<()> {
+        let params = KdfParams::new(Kdf::Pbkdf2Sha256 { iterations: 1000 });
+        let key = params.derive(PASSWORD, MASTER_KEY_LEN);
+        for (compression, other) in &[
+            (Compression::Deflate, Compression::Zstd),
+            (Compression::Zstd, Compression::Deflate),
+        ] {
+            let scheme = DefaultScheme::new(&aes_variant::Aes256Gcm);
+            let enc = EncryptedBox::new(fields(), key.clone(), params.clone(), scheme)
+                .with_compression(*compression)
+                .encrypt()?;
+            let (header, ctext) = Header::parse(&enc)?;
+            let mut toggled = header.clone();
+            toggled.compression = *other;
+            let mut forged = toggled.to_bytes()?;
+            forged.extend_from_slice(ctext);
+            match open(&forged, &permissive()).map(|_| ()).unwrap_err().kind() {
+                ErrorKind::SchemeError(_) => (),
+                kind => panic!("unexpected error {:?}", kind),
+            }
         }
         Ok(())
     }
+
+    #[test]
+    fn validity_is_authenticated() -> Result<()> {
+        let scheme = DefaultScheme::new(&aes_variant::Aes256Gcm);
+        let params = KdfParams::new(Kdf::Pbkdf2Sha256 { iterations: 1000 });
+        let key = params.derive(PASSWORD, MASTER_KEY_LEN);
+        let validity = Validity {
+            not_before: None,
+            expires_at: Some(4_000_000_000),
+        };
+        let enc = EncryptedBox::new(fields(), key, params, scheme)
+            .with_validity(validity)
+            .encrypt()?;
+        let (header, ctext) = Header::parse(&enc)?;
+        assert_eq!(header.validity, validity);
+        let opened = open(&enc, &permissive())?;
+        assert_eq!(opened.fields(), &fields()[..]);
+        assert_eq!(opened.validity(), validity);
+        assert_eq!(Header::parse(&opened.encrypt()?)?.0.validity, validity);
+
+        let mut policy = permissive();
+        policy.set_clock(Clock::Fixed(from_unix_seconds(4_000_000_000)));
+        match open(&enc, &policy).map(|_| ()).unwrap_err().kind() {
+            ErrorKind::ExpiryError(_) => (),
+            kind => panic!("unexpected error {:?}", kind),
+        }
+
+        // moving the expiry breaks the tag
+        let mut later = header.clone();
+        later.validity.expires_at = Some(4_000_000_001);
+        let mut forged = later.to_bytes()?;
+        forged.extend_from_slice(ctext);
+        match open(&forged, &permissive()).map(|_| ()).unwrap_err().kind() {
+            ErrorKind::SchemeError(_) => (),
+            kind => panic!("unexpected error {:?}", kind),
+        }
+
+        // and removing it (with the tag) the content key
+        let mut unbounded = header;
+        unbounded.validity = Validity::default();
+        unbounded.tag = None;
+        let mut forged = unbounded.to_bytes()?;
+        forged.extend_from_slice(ctext);
+        match open(&forged, &permissive()).map(|_| ()).unwrap_err().kind() {
+            ErrorKind::SchemeError(_) => (),
+            kind => panic!("unexpected error {:?}", kind),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn wrong_passwords_fail_alike_with_a_validity_window() -> Result<()> {
+        let params = KdfParams::new(Kdf::Pbkdf2Sha256 { iterations: 1000 });
+        let key = params.derive(PASSWORD, MASTER_KEY_LEN);
+        let validity = Validity {
+            not_before: None,
+            expires_at: Some(4_000_000_000),
+        };
+        let seal = |validity| {
+            let scheme = DefaultScheme::new(&aes_variant::Aes256CbcHmacSha256);
+            EncryptedBox::new(fields(), key.clone(), params.clone(), scheme)
+                .with_validity(validity)
+                .encrypt()
+        };
+        let open_wrong = |enc: &[u8]| {
+            EncryptedBox::<DefaultScheme>::decrypt(String::from("wrong"), enc, &permissive())
+                .map(|_| ())
+                .unwrap_err()
+        };
+        // a wrong password is not mistaken for a corrupt box
+        let bounded = open_wrong(&seal(validity)?);
+        let unbounded = open_wrong(&seal(Validity::default())?);
+        assert!(matches!(bounded.kind(), ErrorKind::SchemeError(_)));
+        assert_eq!(bounded.kind(), unbounded.kind());
+        Ok(())
+    }
+
+    #[test]
+    fn decompression_bombs_are_refused() -> Result<()> {
+        let scheme = DefaultScheme::new(&aes_variant::Aes256Gcm);
+        let params = KdfParams::new(Kdf::Pbkdf2Sha256 { iterations: 1000 });
+        let key = params.derive(PASSWORD, MASTER_KEY_LEN);
+        let eb = EncryptedBox::new(vec![vec![0; 1 << 20]], key, params, scheme)
+            .with_compression(Compression::Zstd);
+        let enc = eb.encrypt()?;
+        assert!(enc.len() < 2048);
+        let mut policy = permissive();
+        policy.max_decompressed_len(1 << 16);
+        match open(&enc, &policy).map(|_| ()).unwrap_err().kind() {
+            ErrorKind::PolicyViolation(_) => (),
+            kind => panic!("unexpected error {:?}", kind),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn encrypt_fields_reuses_key() -> Result<()> {
+        let scheme = DefaultScheme::new(&aes_variant::Aes256Gcm);
+        let params = KdfParams::new(Kdf::default());
+        let key = params.derive(PASSWORD, MASTER_KEY_LEN);
+        let eb = EncryptedBox::new(vec![], key, params, scheme);
+
+        let mut keys = KeyCache::new(String::from(PASSWORD));
+        for record in &[fields(), vec![b"another record".to_vec()]] {
+            let enc = eb.encrypt_fields(record)?;
+            let dec_eb = EncryptedBox::<DefaultScheme>::decrypt_with_keys(
+                &enc[..],
+                &DecryptPolicy::default(),
+                &mut keys,
+            )?;
+            assert_eq!(dec_eb.fields(), &record[..]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn encrypt_into_reuses_buffer() -> Result<()> {
+        let scheme = DefaultScheme::new(&aes_variant::Aes256Gcm);
+        let params = KdfParams::new(Kdf::default());
+        let key = params.derive(PASSWORD, MASTER_KEY_LEN);
+        let eb = EncryptedBox::new(fields(), key, params, scheme);
+
+        let mut out = Vec::new();
+        eb.encrypt_into(&mut out)?;
+        let len = out.len();
+        let capacity = out.capacity();
+        let mut keys = KeyCache::new(String::from(PASSWORD));
+        for _ in 0..3 {
+            let dec_eb = EncryptedBox::<DefaultScheme>::decrypt_with_keys(
+                &out[..],
+                &DecryptPolicy::default(),
+                &mut keys,
+            )?;
+            assert_eq!(dec_eb.fields(), &fields()[..]);
+            out.clear();
+            eb.encrypt_into(&mut out)?;
+            assert_eq!((out.len(), out.capacity()), (len, capacity));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn encrypt_many_keeps_order() -> Result<()> {
+        let scheme = DefaultScheme::new(&aes_variant::ChaCha20Poly1305);
+        let params = KdfParams::new(Kdf::default());
+        let key = params.derive(PASSWORD, MASTER_KEY_LEN);
+        let eb = EncryptedBox::new(vec![], key, params, scheme);
+
+        let items: Vec<Fields> = (0..200usize)
+            .map(|i| vec![i.to_string().into_bytes(); i % 5])
+            .collect();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
+        let sealed = pool.install(|| eb.encrypt_many(items.clone()));
+
+        let mut keys = KeyCache::new(String::from(PASSWORD));
+        assert_eq!(sealed.len(), items.len());
+        for (fields, enc) in items.iter().zip(sealed) {
+            let dec_eb = EncryptedBox::<DefaultScheme>::decrypt_with_keys(
+                &enc?[..],
+                &DecryptPolicy::default(),
+                &mut keys,
+            )?;
+            assert_eq!(dec_eb.fields(), &fields[..]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn decrypt_with_registry_opens_every_scheme() -> Result<()> {
+        let registry = SchemeRegistry::default();
+        let mut keys = KeyCache::new(String::from(PASSWORD));
+        for variant in aes_variant::iterator() {
+            let enc = seal(variant, Kdf::Pbkdf2Sha256 { iterations: 1000 })?;
+            let eb =
+                EncryptedBox::decrypt_with_registry(&enc, &permissive(), &mut keys, &registry)?;
+            assert_eq!(eb.fields(), &fields()[..]);
+            assert_eq!(eb.scheme.identifier(), variant.name());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn runtime_scheme_box_opens_with_static_scheme() -> Result<()> {
+        let scheme: SchemeRef = SchemeRegistry::default().get("chacha20-poly1305")?;
+        let params = KdfParams::new(Kdf::Pbkdf2Sha256 { iterations: 1000 });
+        let key = params.derive(PASSWORD, MASTER_KEY_LEN);
+        let enc = EncryptedBox::new(fields(), key, params, scheme).encrypt()?;
+        assert_eq!(open(&enc, &permissive())?.fields(), &fields()[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn decrypt_with_registry_refuses_unregistered_scheme() -> Result<()> {
+        let enc = seal(
+            &aes_variant::Aes256Gcm,
+            Kdf::Pbkdf2Sha256 { iterations: 1000 },
+        )?;
+        let mut keys = KeyCache::new(String::from(PASSWORD));
+        match EncryptedBox::decrypt_with_registry(
+            &enc,
+            &permissive(),
+            &mut keys,
+            &SchemeRegistry::new(),
+        ) {
+            Err(e) => assert!(matches!(e.kind(), ErrorKind::SchemeError(_))),
+            Ok(_) => panic!("opened a box of an unregistered scheme"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn decrypt_rejects_headerless_data() {
+        assert!(open(&FIELDS[..], &permissive()).is_err());
+    }
 }
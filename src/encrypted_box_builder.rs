@@ -1,8 +1,15 @@
+use crate::blind_index::BlindIndex;
+use crate::compression::Compression;
 pub use crate::encrypted_box::EncryptedBox;
-pub use crate::encryption_scheme::EncryptionScheme;
-use crate::kdf;
+pub use crate::encryption_scheme::{EncryptionScheme, SecurityLevel};
+use crate::fpe::{Alphabet, FormatPreserving, FpeMode};
+use crate::kdf::{Kdf, KdfParams};
+use crate::padding::Padding;
+use crate::subkeys::MASTER_KEY_LEN;
+use crate::validity::{self, Validity};
 use exitfailure::ExitFailure;
 use std::fmt;
+use std::time::SystemTime;
 
 /// This is a builder for an encrypted box object.
 /// It is modeled after
@@ -10,9 +17,36 @@ use std::fmt;
 /// as a non-consuming builder.
 pub struct EncryptedBoxBuilder<T> {
     cipher: T,
-    fields: Vec<u8>,
+    fields: Vec<Vec<u8>>,
     key: Vec<u8>,
+    kdf: KdfParams,
     password: String,
+    allow_insecure: bool,
+    deterministic: bool,
+    padding: Padding,
+    compression: Compression,
+    validity: Validity,
+    // the names of named fields and their positions (see `add_named_field`)
+    field_names: Vec<(String, usize)>,
+    // the name of the blind index, and of the field it indexes
+    index_name: Option<String>,
+    // derived along with key
+    index: Option<BlindIndex>,
+    // the master key of blind indexes and format-preserving fields, derived
+    // with the fixed salt of deterministic boxes, so that it is the same for
+    // every box. Empty until an index or field needs it
+    lookup_key: Vec<u8>,
+    // the fields encrypted format-preserving when the box is built, by position
+    fpe_fields: Vec<(usize, FpeField)>,
+}
+
+// how a field added with `add_format_preserving_field` is encrypted
+#[derive(Clone)]
+struct FpeField {
+    name: String,
+    mode: FpeMode,
+    alphabet: Alphabet,
+    tweak: Vec<u8>,
 }
 
 /// This is an implementation for an encrypted box builder
@@ -21,42 +55,196 @@ pub struct EncryptedBoxBuilder<T> {
 impl<T> EncryptedBoxBuilder<T>
 where
     T: EncryptionScheme + Clone,
+    T::Error: fmt::Display,
 {
     /// create a new builder with a given cipher
     pub fn new(cipher: T) -> EncryptedBoxBuilder<T> {
         EncryptedBoxBuilder {
-            cipher: cipher,
+            cipher,
             fields: Vec::new(),
             key: Vec::new(),
+            kdf: KdfParams::new(Kdf::default()),
             password: String::from(""),
+            allow_insecure: false,
+            deterministic: false,
+            padding: Padding::None,
+            compression: Compression::None,
+            validity: Validity::default(),
+            field_names: Vec::new(),
+            index_name: None,
+            index: None,
+            lookup_key: Vec::new(),
+            fpe_fields: Vec::new(),
         }
     }
 
-    /// generate a new EncryptedBox
+    /// generate a new EncryptedBox, copying fields, key and cipher so that
+    /// the builder can be reused (see `into_box` to avoid the copies)
     pub fn build(&mut self) -> Result<EncryptedBox<T>, ExitFailure> {
-        if self.key.len() == 0 {
+        self.check()?;
+        let mut fields = self.fields.clone();
+        self.encrypt_format_preserving(&mut fields)?;
+        let eb = EncryptedBox::new(
+            fields,
+            self.key.clone(),
+            self.kdf.clone(),
+            self.cipher.clone(),
+        )
+        .with_padding(self.padding)?
+        .with_compression(self.compression)
+        .with_validity(self.validity);
+        Ok(match (&self.index, self.index_field()) {
+            (Some(index), Some(field)) => eb.with_blind_index(index.clone(), field),
+            _ => eb,
+        })
+    }
+
+    /// generate a new EncryptedBox, moving fields, key and cipher into it
+    pub fn into_box(mut self) -> Result<EncryptedBox<T>, ExitFailure> {
+        self.check()?;
+        let mut fields = std::mem::take(&mut self.fields);
+        self.encrypt_format_preserving(&mut fields)?;
+        let field = self.index_field();
+        let eb = EncryptedBox::new(fields, self.key, self.kdf, self.cipher)
+            .with_padding(self.padding)?
+            .with_compression(self.compression)
+            .with_validity(self.validity);
+        Ok(match (self.index, field) {
+            (Some(index), Some(field)) => eb.with_blind_index(index, field),
+            _ => eb,
+        })
+    }
+
+    // replace the format-preserving fields among fields by their encryption
+    fn encrypt_format_preserving(&self, fields: &mut [Vec<u8>]) -> Result<(), ExitFailure> {
+        for (position, field) in &self.fpe_fields {
+            let fpe = self.format_preserving(&field.name, field.mode, field.alphabet.clone())?;
+            let value = String::from_utf8_lossy(&fields[*position]).into_owned();
+            fields[*position] = fpe.encrypt(&value, &field.tweak)?.into_bytes();
+        }
+        Ok(())
+    }
+
+    // the position of the field the blind index indexes, if there is one
+    fn index_field(&self) -> Option<usize> {
+        let name = self.index_name.as_ref()?;
+        self.field_names
+            .iter()
+            .find(|(field_name, _)| field_name == name)
+            .map(|(_, field)| *field)
+    }
+
+    fn check(&self) -> Result<(), ExitFailure> {
+        if self.key.is_empty() {
             return Err(ExitFailure::from(BuildError::new(
                 "cannot build an encrypted box with no key",
             )));
         }
-        Ok(EncryptedBox::new(
-            self.fields.clone(),
-            self.key.clone(),
-            self.cipher.clone(),
-        ))
+        if self.padding == Padding::Bucket(0) {
+            return Err(ExitFailure::from(BuildError::new(
+                "cannot pad to buckets of 0 bytes",
+            )));
+        }
+        if let Validity {
+            not_before: Some(not_before),
+            expires_at: Some(expires_at),
+        } = self.validity
+        {
+            if expires_at <= not_before {
+                return Err(ExitFailure::from(BuildError::new(
+                    "the box would expire before it is valid",
+                )));
+            }
+        }
+        for (i, (name, _)) in self.field_names.iter().enumerate() {
+            if self.field_names[..i].iter().any(|(other, _)| other == name) {
+                return Err(ExitFailure::from(BuildError::new(&format!(
+                    "two fields are named '{}'",
+                    name
+                ))));
+            }
+        }
+        if let Some(name) = &self.index_name {
+            if self.index_field().is_none() {
+                return Err(ExitFailure::from(BuildError::new(&format!(
+                    "the blind index '{}' names no field, see add_named_field()",
+                    name
+                ))));
+            }
+        }
+        if self.deterministic && !self.cipher.is_deterministic() {
+            return Err(ExitFailure::from(BuildError::new(
+                "deterministic boxes need a deterministic scheme (e.g. aes-256-siv)",
+            )));
+        }
+        if !self.allow_insecure {
+            match self.cipher.security_level() {
+                SecurityLevel::Insecure => {
+                    return Err(ExitFailure::from(BuildError::new(
+                        "refusing to use an insecure scheme (e.g. ECB), see allow_insecure()",
+                    )));
+                }
+                SecurityLevel::Unauthenticated => {
+                    return Err(ExitFailure::from(BuildError::new(
+                        "refusing to use an unauthenticated scheme, see allow_insecure()",
+                    )));
+                }
+                SecurityLevel::Authenticated => (),
+            }
+        }
+        Ok(())
     }
 
     /// add a field to the encryption data
-    pub fn add_field<'a, F>(&'a mut self, field: F) -> &'a mut EncryptedBoxBuilder<T>
+    pub fn add_field<F>(&mut self, field: F) -> &mut EncryptedBoxBuilder<T>
     where
         F: ToString,
     {
-        self.fields.extend(field.to_string().as_bytes());
+        self.fields.push(field.to_string().into_bytes());
         self
     }
 
+    /// add a field called name (e.g. "email"), which a blind index of that
+    /// name indexes (see `with_blind_index`)
+    pub fn add_named_field<F>(&mut self, name: &str, field: F) -> &mut EncryptedBoxBuilder<T>
+    where
+        F: ToString,
+    {
+        self.field_names.push((name.to_string(), self.fields.len()));
+        self.add_field(field)
+    }
+
+    /// add a field called name (e.g. "card", see `add_named_field`) that is
+    /// encrypted format-preserving (see fpe.rs) under the key of that name with
+    /// tweak when the box is built, so
+    /// that it keeps its format once the box is opened, e.g. for a legacy
+    /// system. value must consist of characters of alphabet; opening the box
+    /// gives its encryption, which `format_preserving` decrypts
+    pub fn add_format_preserving_field(
+        &mut self,
+        name: &str,
+        value: &str,
+        tweak: &[u8],
+        mode: FpeMode,
+        alphabet: Alphabet,
+    ) -> &mut EncryptedBoxBuilder<T> {
+        self.fpe_fields.push((
+            self.fields.len(),
+            FpeField {
+                name: name.to_string(),
+                mode,
+                alphabet,
+                tweak: tweak.to_vec(),
+            },
+        ));
+        if !self.key.is_empty() {
+            self.lookup_key();
+        }
+        self.add_named_field(name, value)
+    }
+
     /// add multiple fields at once
-    pub fn add_fields<'a, F>(&'a mut self, fields: &[F]) -> &'a mut EncryptedBoxBuilder<T>
+    pub fn add_fields<F>(&mut self, fields: &[F]) -> &mut EncryptedBoxBuilder<T>
     where
         F: ToString + std::fmt::Display,
     {
@@ -67,23 +255,163 @@ where
     }
 
     /// set a password (of which a key will be derived)
-    pub fn set_password<'a>(&'a mut self, password: String) -> &'a mut EncryptedBoxBuilder<T> {
+    pub fn set_password(&mut self, password: String) -> &mut EncryptedBoxBuilder<T> {
         self.password = password;
         self.set_key(self.password.clone());
         self
     }
 
+    // the master key, which the box derives the cipher's key from
     fn set_key(&mut self, password: String) {
-        self.key = kdf::derive_key_from_password(&password, self.cipher.get_key_length());
+        self.key = self.kdf.derive(&password, MASTER_KEY_LEN);
+        self.lookup_key.clear();
+        if !self.fpe_fields.is_empty() {
+            self.lookup_key();
+        }
+        self.derive_index();
+    }
+
+    // the key of the blind index, a subkey of the lookup key (see blind_index.rs)
+    fn derive_index(&mut self) {
+        if let Some(name) = self.index_name.clone() {
+            self.index = Some(BlindIndex::new(&name, self.lookup_key()));
+        }
+    }
+
+    // the lookup key, derived once per password and KDF. The master key of
+    // deterministic boxes has the fixed salt already, so it is reused
+    fn lookup_key(&mut self) -> &[u8] {
+        if self.lookup_key.is_empty() {
+            self.lookup_key = if self.deterministic {
+                self.key.clone()
+            } else {
+                KdfParams::deterministic(self.kdf.kdf).derive(&self.password, MASTER_KEY_LEN)
+            };
+        }
+        &self.lookup_key
+    }
+
+    // the lookup key, if it needs no deriving
+    fn cached_lookup_key(&self) -> Option<&[u8]> {
+        if !self.lookup_key.is_empty() {
+            Some(&self.lookup_key)
+        } else if self.deterministic && !self.key.is_empty() {
+            Some(&self.key)
+        } else {
+            None
+        }
+    }
+
+    /// set the key derivation function (PBKDF2-HMAC-SHA256 by default).
+    /// This picks a fresh salt (unless `deterministic`), so the key is
+    /// re-derived if a password was set
+    pub fn set_kdf(&mut self, kdf: Kdf) -> &mut EncryptedBoxBuilder<T> {
+        self.kdf = if self.deterministic {
+            KdfParams::deterministic(kdf)
+        } else {
+            KdfParams::new(kdf)
+        };
+        if !self.key.is_empty() {
+            self.set_key(self.password.clone());
+        }
+        self
+    }
+
+    /// seal deterministically: the same fields sealed with the same password,
+    /// KDF and scheme give the same box, with any builder. That lets an
+    /// application look boxes up by equality (e.g. an encrypted email column)
+    /// by sealing the value it looks for, without decrypting anything.
+    /// The scheme must be deterministic (e.g. aes-256-siv, see
+    /// `EncryptionScheme::is_deterministic`), and the salt is fixed (see
+    /// `KdfParams::deterministic`). Boxes then reveal which of them are equal,
+    /// and with a SIV scheme nothing else
+    pub fn deterministic(&mut self) -> &mut EncryptedBoxBuilder<T> {
+        self.deterministic = true;
+        self.set_kdf(self.kdf.kdf)
+    }
+
+    /// pad the plaintext so that its length only reveals its size class
+    /// (see padding.rs), e.g. `Padding::Padme`. Boxes are not padded by default
+    pub fn set_padding(&mut self, padding: Padding) -> &mut EncryptedBoxBuilder<T> {
+        self.padding = padding;
+        self
+    }
+
+    /// compress the plaintext before encrypting it (see compression.rs).
+    /// Boxes are not compressed by default, and for a reason: a compressed
+    /// box's length depends on its content, so if an attacker influences some
+    /// fields and sees the box, they can guess the other fields (CRIME, BREACH).
+    /// Only compress fields nobody else controls
+    pub fn set_compression(&mut self, compression: Compression) -> &mut EncryptedBoxBuilder<T> {
+        self.compression = compression;
+        self
+    }
+
+    /// make the boxes stop opening at time (to the second), e.g. for short-lived
+    /// tokens. The expiry is stored in the header, authenticated (see
+    /// validity.rs), and `EncryptedBox::decrypt` refuses expired boxes
+    pub fn expires_at(&mut self, time: SystemTime) -> &mut EncryptedBoxBuilder<T> {
+        self.validity.expires_at = Some(validity::unix_seconds(time));
+        self
+    }
+
+    /// make the boxes open only from time on (to the second), see `expires_at`
+    pub fn not_before(&mut self, time: SystemTime) -> &mut EncryptedBoxBuilder<T> {
+        self.validity.not_before = Some(validity::unix_seconds(time));
+        self
+    }
+
+    /// give the boxes a blind index called name (e.g. "email") of the field of
+    /// that name (see `add_named_field`), returned by
+    /// `EncryptedBox::encrypt_indexed` along with the sealed box. Looking up
+    /// the index of a value (see `BlindIndex::index`, or the CLI's index
+    /// subcommand) then finds the boxes whose field holds it. The index key is
+    /// a subkey of a master key derived from the password and KDF with a fixed
+    /// salt, so that it is the same for every box. That takes a second run of
+    /// the KDF per password, unless the boxes are `deterministic`
+    pub fn with_blind_index(&mut self, name: &str) -> &mut EncryptedBoxBuilder<T> {
+        self.index_name = Some(name.to_string());
+        if !self.key.is_empty() {
+            self.derive_index();
+        }
+        self
+    }
+
+    /// format-preserving encryption (see fpe.rs) of the values called name
+    /// (e.g. "card"), for fields that must keep their format, in a box (see
+    /// `add_format_preserving_field`) or outside one. The key is a subkey of
+    /// the master key a blind index uses (see `with_blind_index`), derived
+    /// here unless the builder has it already, so derive it once for many values
+    pub fn format_preserving(
+        &self,
+        name: &str,
+        mode: FpeMode,
+        alphabet: Alphabet,
+    ) -> Result<FormatPreserving, ExitFailure> {
+        if self.key.is_empty() {
+            return Err(ExitFailure::from(BuildError::new(
+                "cannot encrypt format-preserving fields with no key",
+            )));
+        }
+        Ok(match self.cached_lookup_key() {
+            Some(key) => FormatPreserving::with_master_key(name, mode, alphabet, key),
+            None => FormatPreserving::derive(name, mode, alphabet, &self.password, self.kdf.kdf),
+        })
+    }
+
+    /// allow building boxes with insecure (ECB, and CTR and OFB with their
+    /// fixed IV) or unauthenticated (CBC) schemes, which are refused by default
+    pub fn allow_insecure(&mut self) -> &mut EncryptedBoxBuilder<T> {
+        self.allow_insecure = true;
+        self
     }
 
     /// set a cipher to some other cipher
     /// this is handy if you are looking for a single builder with set fields
     /// to be encrypted by many ciphers
-    pub fn set_cipher<'a>(&'a mut self, cipher: &T) -> &'a mut EncryptedBoxBuilder<T> {
+    pub fn set_cipher(&mut self, cipher: &T) -> &mut EncryptedBoxBuilder<T> {
+        // the master key does not depend on the cipher, so it is kept
         self.cipher = cipher.clone();
-        // we need to reset the key for the new cipher
-        self.set_key(self.password.clone());
         self
     }
 }
@@ -112,7 +440,15 @@ impl std::error::Error for BuildError {}
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::openssl_aes::{defs::OpenSslVariants as variants, wrapper as aes};
+    use crate::encryption_scheme::DefaultScheme;
+    use crate::errors::ErrorKind;
+    use crate::openssl_aes::defs::OpenSslVariants as variants;
+    use crate::policy::DecryptPolicy;
+    use crate::validity::Clock;
+    use std::time::Duration;
+
+    // a cheap kdf, to keep tests that derive many keys fast
+    const TEST_KDF: Kdf = Kdf::Pbkdf2Sha256 { iterations: 1000 };
 
     // note: this text is long on purpose, as some modes of AES show differences only
     // with large enough plaintexts
@@ -121,22 +457,20 @@ mod tests {
     #[test]
     fn add_int_field_test() {
         let field = 42;
-        let mut ebb = EncryptedBoxBuilder::new(aes::OpensslAesWrapper::new(&variants::Aes128Cbc));
-        let ebb = ebb.add_field(&field);
-        let mut vec: Vec<u8> = Vec::new();
-        vec.extend(field.to_string().as_bytes());
-        assert_eq!(ebb.fields, vec);
+        let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes128Cbc));
+        let ebb = ebb.add_field(field);
+        assert_eq!(ebb.fields, vec![field.to_string().into_bytes()]);
     }
 
     fn add_fields_test<T>(fields: Vec<T>)
     where
         T: ToString + std::fmt::Display,
     {
-        let mut ebb = EncryptedBoxBuilder::new(aes::OpensslAesWrapper::new(&variants::Aes128Cbc));
+        let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes128Cbc));
         let ebb = ebb.add_fields(&fields);
-        let mut vec: Vec<u8> = Vec::new();
+        let mut vec: Vec<Vec<u8>> = Vec::new();
         for field in fields.iter() {
-            vec.extend(field.to_string().as_bytes());
+            vec.push(field.to_string().into_bytes());
         }
         assert_eq!(ebb.fields, vec);
     }
@@ -155,7 +489,7 @@ mod tests {
 
     #[test]
     fn set_long_password() -> Result<(), ExitFailure> {
-        let mut ebb = EncryptedBoxBuilder::new(aes::OpensslAesWrapper::new(&variants::Aes128Cbc));
+        let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes128Gcm));
         ebb.set_password(String::from(LONG_TEXT));
         ebb.build()?;
         Ok(())
@@ -165,25 +499,22 @@ mod tests {
     fn set_password_forall_aes_variants() {
         let password = String::from("password");
         for variant in variants::iterator() {
-            let scheme = aes::OpensslAesWrapper::new(variant);
+            let scheme = DefaultScheme::new(variant);
             let mut ebb = EncryptedBoxBuilder::new(scheme);
-            let ebb = ebb.set_password(password.clone());
-            let key: Vec<u8> = kdf::derive_key_from_password(&password, scheme.get_key_length());
+            let ebb = ebb.set_kdf(TEST_KDF).set_password(password.clone());
+            let key: Vec<u8> = ebb.kdf.derive(&password, MASTER_KEY_LEN);
             assert_eq!(ebb.key, key);
         }
     }
 
     #[test]
     fn build_fails_when_no_password_is_set() -> Result<(), ExitFailure> {
-        let scheme = aes::OpensslAesWrapper::new(&variants::Aes192Cbc);
+        let scheme = DefaultScheme::new(&variants::Aes192Cbc);
 
         // initialize builder & encrypted-box
         let mut ebb = EncryptedBoxBuilder::new(scheme);
         let eb = ebb.add_field("field").build();
-        match eb {
-            Ok(_) => assert!(false),
-            _ => assert!(true),
-        }
+        assert!(eb.is_err());
         Ok(())
     }
 
@@ -191,11 +522,13 @@ mod tests {
     fn set_cipher_forall_aes_variants_differect_encryption() -> Result<(), ExitFailure> {
         for variant1 in variants::iterator() {
             // create encrypted box builder with first variant
-            let scheme1 = aes::OpensslAesWrapper::new(variant1);
+            let scheme1 = DefaultScheme::new(variant1);
             let mut ebb = EncryptedBoxBuilder::new(scheme1);
             let eb1 = ebb
+                .set_kdf(TEST_KDF)
                 .set_password(String::from("password"))
                 .add_field(LONG_TEXT)
+                .allow_insecure()
                 .build()?;
             let ctext1 = eb1.encrypt()?;
             for variant2 in variants::iterator() {
@@ -203,7 +536,7 @@ mod tests {
                     continue;
                 }
                 // change cipher to different cipher
-                let scheme2 = aes::OpensslAesWrapper::new(variant2);
+                let scheme2 = DefaultScheme::new(variant2);
                 let ebb = ebb.set_cipher(&scheme2);
                 let eb2 = ebb.build()?;
                 let ctext2 = eb2.encrypt()?;
@@ -216,20 +549,375 @@ mod tests {
     }
 
     #[test]
-    fn key_reset_after_cipher_changed() -> Result<(), ExitFailure> {
+    fn key_kept_after_cipher_changed() -> Result<(), ExitFailure> {
         // create encrypted box builder with first variant
-        let init_scheme = aes::OpensslAesWrapper::new(&variants::Aes128Cbc);
+        let init_scheme = DefaultScheme::new(&variants::Aes128Cbc);
         let mut ebb = EncryptedBoxBuilder::new(init_scheme);
         let ebb = ebb
+            .set_kdf(TEST_KDF)
             .set_password(String::from("password"))
             .add_field(LONG_TEXT);
+        let key = ebb.key.clone();
         for variant in variants::iterator() {
-            let change_scheme = aes::OpensslAesWrapper::new(variant);
-            let key_len = change_scheme.get_key_length();
+            let change_scheme = DefaultScheme::new(variant);
             let ebb = ebb.set_cipher(&change_scheme);
-            //test that key length is as supposed to be
-            assert_eq!(ebb.key.len(), key_len);
+            // the master key is the same, every cipher derives its own key from it
+            assert_eq!(ebb.key, key);
+        }
+        Ok(())
+    }
+
+    fn seal_indexed(field: &str) -> Result<(Vec<u8>, Vec<u8>), ExitFailure> {
+        let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes256Gcm));
+        Ok(ebb
+            .set_kdf(TEST_KDF)
+            .set_password(String::from("password"))
+            .with_blind_index("email")
+            .add_named_field("email", field)
+            .build()?
+            .encrypt_indexed()?)
+    }
+
+    #[test]
+    fn blind_indexes_index_one_field() -> Result<(), ExitFailure> {
+        let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes256Gcm));
+        ebb.set_kdf(TEST_KDF)
+            .set_password(String::from("password"))
+            .with_blind_index("email")
+            .add_field("alice")
+            .add_named_field("email", "alice@example.com")
+            .add_field("admin");
+        let (sealed, index) = ebb.build()?.encrypt_indexed()?;
+
+        // a lookup of the email alone finds the box
+        let lookup = BlindIndex::derive("email", "password", TEST_KDF);
+        assert_eq!(lookup.index(b"alice@example.com"), index);
+        assert_ne!(lookup.index(b"alice"), index);
+        let mut policy = DecryptPolicy::default();
+        policy.min_kdf_cost(1000);
+        let opened =
+            EncryptedBox::<DefaultScheme>::decrypt(String::from("password"), &sealed, &policy)?;
+        assert_eq!(opened.fields()[1], b"alice@example.com");
+
+        // the indexed field must exist, once
+        ebb.with_blind_index("phone");
+        assert!(ebb.build().is_err());
+        ebb.with_blind_index("email")
+            .add_named_field("email", "bob@example.com");
+        assert!(ebb.build().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn blind_indexes_follow_the_named_field() -> Result<(), ExitFailure> {
+        let lookup = BlindIndex::derive("email", "password", TEST_KDF);
+        let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes256Siv));
+        ebb.set_kdf(TEST_KDF)
+            .set_password(String::from("password"))
+            .with_blind_index("email")
+            .add_named_field("email", "alice@example.com")
+            .add_field("alice");
+        assert_eq!(
+            ebb.build()?.blind_index(),
+            Some(lookup.index(b"alice@example.com"))
+        );
+
+        // deterministic boxes reuse their master key for the index
+        ebb.deterministic();
+        assert_eq!(ebb.lookup_key, ebb.key);
+        assert_eq!(
+            ebb.build()?.blind_index(),
+            Some(lookup.index(b"alice@example.com"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn blind_indexes_find_equal_fields() -> Result<(), ExitFailure> {
+        let (sealed, index) = seal_indexed("alice@example.com")?;
+        let (again, same) = seal_indexed("alice@example.com")?;
+        // the boxes differ, their indexes do not
+        assert_ne!(again, sealed);
+        assert_eq!(same, index);
+        assert_ne!(seal_indexed("bob@example.com")?.1, index);
+
+        let lookup = BlindIndex::derive("email", "password", TEST_KDF);
+        assert_eq!(lookup.index(b"alice@example.com"), index);
+        Ok(())
+    }
+
+    #[test]
+    fn blind_index_is_optional() -> Result<(), ExitFailure> {
+        let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes256Gcm));
+        let eb = ebb
+            .set_kdf(TEST_KDF)
+            .set_password(String::from("password"))
+            .add_field("alice@example.com")
+            .build()?;
+        assert!(eb.blind_index().is_none());
+        assert!(eb.encrypt_indexed().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn format_preserving_fields() -> Result<(), ExitFailure> {
+        let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes256Gcm));
+        assert!(ebb
+            .format_preserving("card", FpeMode::Ff1, Alphabet::digits())
+            .is_err());
+        ebb.set_kdf(TEST_KDF).set_password(String::from("password"));
+        let card = ebb.format_preserving("card", FpeMode::Ff1, Alphabet::digits())?;
+        let enc = card.encrypt("4111111111111111", b"record 1")?;
+        assert_eq!(enc.len(), 16);
+        assert!(enc.chars().all(|c| c.is_ascii_digit()));
+
+        // the key depends on the password and KDF only
+        let again = FormatPreserving::derive(
+            "card",
+            FpeMode::Ff1,
+            Alphabet::digits(),
+            "password",
+            TEST_KDF,
+        );
+        assert_eq!(again.decrypt(&enc, b"record 1")?, "4111111111111111");
+        Ok(())
+    }
+
+    #[test]
+    fn format_preserving_fields_in_boxes() -> Result<(), ExitFailure> {
+        let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes256Gcm));
+        ebb.set_kdf(TEST_KDF)
+            .set_password(String::from("password"))
+            .add_field("alice")
+            .add_format_preserving_field(
+                "card",
+                "4111111111111111",
+                b"record 1",
+                FpeMode::Ff1,
+                Alphabet::digits(),
+            );
+        // the key of the field is derived once, with the fixed salt
+        let lookup_key = KdfParams::deterministic(TEST_KDF).derive("password", MASTER_KEY_LEN);
+        assert_eq!(ebb.lookup_key, lookup_key);
+        let eb = ebb.build()?;
+        let enc = String::from_utf8(eb.fields()[1].clone())?;
+        assert_ne!(enc, "4111111111111111");
+        assert_eq!(enc.len(), 16);
+        assert!(enc.chars().all(|c| c.is_ascii_digit()));
+
+        // the box carries the encrypted field, in its format
+        let mut policy = DecryptPolicy::default();
+        policy.min_kdf_cost(1000);
+        let opened = EncryptedBox::<DefaultScheme>::decrypt(
+            String::from("password"),
+            &eb.encrypt()?,
+            &policy,
+        )?;
+        assert_eq!(opened.fields()[0], b"alice");
+        assert_eq!(opened.fields()[1], enc.as_bytes());
+        let card = ebb.format_preserving("card", FpeMode::Ff1, Alphabet::digits())?;
+        assert_eq!(card.decrypt(&enc, b"record 1")?, "4111111111111111");
+        assert_eq!(ebb.into_box()?.fields()[1], enc.as_bytes());
+
+        // values outside the alphabet are refused
+        let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes256Gcm));
+        ebb.set_kdf(TEST_KDF)
+            .set_password(String::from("password"))
+            .add_format_preserving_field(
+                "card",
+                "4111-1111-1111-1111",
+                b"record 1",
+                FpeMode::Ff1,
+                Alphabet::digits(),
+            );
+        assert!(ebb.build().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn padded_boxes_hide_lengths() -> Result<(), ExitFailure> {
+        let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes256Gcm));
+        ebb.set_kdf(TEST_KDF)
+            .set_password(String::from("password"))
+            .set_padding(Padding::Bucket(64));
+        let eb = ebb.build()?;
+        let short = eb.encrypt_fields(&[b"a name".to_vec()])?;
+        let longer = eb.encrypt_fields(&[b"a longer name".to_vec()])?;
+        assert_eq!(short.len(), longer.len());
+
+        ebb.set_padding(Padding::Bucket(0));
+        assert!(ebb.build().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn compressed_boxes_open() -> Result<(), ExitFailure> {
+        let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes256Gcm));
+        ebb.set_kdf(TEST_KDF)
+            .set_password(String::from("password"))
+            .set_compression(Compression::Zstd)
+            .add_field("{\"a\": 1, \"b\": 1, \"c\": 1, \"d\": 1, \"e\": 1, \"f\": 1}");
+        let eb = ebb.build()?;
+        assert_eq!(eb.compression(), Compression::Zstd);
+        let mut policy = DecryptPolicy::default();
+        policy.min_kdf_cost(1000);
+        let opened = EncryptedBox::<DefaultScheme>::decrypt(
+            String::from("password"),
+            &eb.encrypt()?,
+            &policy,
+        )?;
+        assert_eq!(opened.fields(), eb.fields());
+        assert_eq!(opened.compression(), Compression::Zstd);
+        Ok(())
+    }
+
+    #[test]
+    fn boxes_expire() -> Result<(), ExitFailure> {
+        let now = SystemTime::now();
+        let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes256Gcm));
+        ebb.set_kdf(TEST_KDF)
+            .set_password(String::from("password"))
+            .add_field("session 42")
+            .not_before(now)
+            .expires_at(now + Duration::from_secs(60));
+        let enc = ebb.build()?.encrypt()?;
+
+        let mut policy = DecryptPolicy::default();
+        policy.min_kdf_cost(1000);
+        let open = |policy: &DecryptPolicy| {
+            EncryptedBox::<DefaultScheme>::decrypt(String::from("password"), &enc, policy)
+        };
+        assert_eq!(open(&policy)?.fields(), &[b"session 42".to_vec()][..]);
+        for time in &[now - Duration::from_secs(1), now + Duration::from_secs(60)] {
+            policy.set_clock(Clock::Fixed(*time));
+            match open(&policy).map(|_| ()).unwrap_err().kind() {
+                ErrorKind::ExpiryError(_) => (),
+                kind => panic!("unexpected error {:?}", kind),
+            }
+        }
+        assert_eq!(
+            open(policy.ignore_expiry())?.fields(),
+            &[b"session 42".to_vec()][..]
+        );
+
+        ebb.not_before(now + Duration::from_secs(60));
+        assert!(ebb.build().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn build_refuses_insecure_schemes_by_default() {
+        for variant in variants::iterator() {
+            let scheme = DefaultScheme::new(variant);
+            let mut ebb = EncryptedBoxBuilder::new(scheme);
+            let eb = ebb
+                .set_kdf(TEST_KDF)
+                .set_password(String::from("password"))
+                .build();
+            assert_eq!(
+                eb.is_ok(),
+                scheme.security_level() == SecurityLevel::Authenticated
+            );
+        }
+    }
+
+    #[test]
+    fn allow_insecure_permits_every_scheme() -> Result<(), ExitFailure> {
+        for variant in variants::iterator() {
+            let scheme = DefaultScheme::new(variant);
+            let mut ebb = EncryptedBoxBuilder::new(scheme);
+            ebb.set_kdf(TEST_KDF)
+                .set_password(String::from("password"))
+                .allow_insecure()
+                .build()?;
         }
         Ok(())
     }
+
+    #[test]
+    fn into_box_moves_fields() -> Result<(), ExitFailure> {
+        let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes256Gcm));
+        ebb.set_kdf(TEST_KDF)
+            .set_password(String::from("password"))
+            .add_fields(&["a", "b"]);
+        let eb = ebb.into_box()?;
+        assert_eq!(eb.fields(), &[b"a".to_vec(), b"b".to_vec()][..]);
+        assert_eq!(eb.encrypt()?[..5], b"EBOX\x02"[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn into_box_refuses_insecure_schemes_by_default() {
+        let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes128Ecb));
+        ebb.set_kdf(TEST_KDF).set_password(String::from("password"));
+        assert!(ebb.into_box().is_err());
+    }
+
+    #[test]
+    fn set_kdf_rederives_key() {
+        let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes256Gcm));
+        ebb.set_kdf(TEST_KDF).set_password(String::from("password"));
+        let key1 = ebb.key.clone();
+        let salt1 = ebb.kdf.salt.clone();
+        ebb.set_kdf(Kdf::Pbkdf2Sha256 { iterations: 2000 });
+        assert_ne!(ebb.kdf.salt, salt1);
+        assert_ne!(ebb.key, key1);
+        assert_eq!(ebb.key, ebb.kdf.derive("password", 32));
+    }
+
+    #[test]
+    fn build_preserves_field_boundaries() -> Result<(), ExitFailure> {
+        let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes256Gcm));
+        let eb = ebb
+            .set_kdf(TEST_KDF)
+            .set_password(String::from("password"))
+            .add_fields(&["ab", "c"])
+            .build()?;
+        assert_eq!(eb.fields(), &[b"ab".to_vec(), b"c".to_vec()][..]);
+        Ok(())
+    }
+
+    fn seal_deterministic(field: &str) -> Result<Vec<u8>, ExitFailure> {
+        let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes256Siv));
+        Ok(ebb
+            .set_password(String::from("password"))
+            .deterministic()
+            .set_kdf(TEST_KDF)
+            .add_field(field)
+            .build()?
+            .encrypt()?)
+    }
+
+    #[test]
+    fn deterministic_boxes_are_equal() -> Result<(), ExitFailure> {
+        let sealed = seal_deterministic("alice@example.com")?;
+        // by another builder, with the options in another order
+        let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes256Siv));
+        let again = ebb
+            .deterministic()
+            .set_kdf(TEST_KDF)
+            .set_password(String::from("password"))
+            .add_field("alice@example.com")
+            .build()?
+            .encrypt()?;
+        assert_eq!(again, sealed);
+        assert_ne!(seal_deterministic("bob@example.com")?, sealed);
+
+        let mut policy = DecryptPolicy::default();
+        policy.min_kdf_cost(1000);
+        let eb =
+            EncryptedBox::<DefaultScheme>::decrypt(String::from("password"), &sealed, &policy)?;
+        assert_eq!(eb.fields(), &[b"alice@example.com".to_vec()][..]);
+        Ok(())
+    }
+
+    #[test]
+    fn deterministic_needs_deterministic_scheme() {
+        let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variants::Aes256Gcm));
+        ebb.set_kdf(TEST_KDF)
+            .set_password(String::from("password"))
+            .deterministic();
+        assert!(ebb.build().is_err());
+        assert_eq!(ebb.kdf.salt, crate::kdf::DETERMINISTIC_SALT);
+    }
 }
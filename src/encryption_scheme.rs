@@ -1,3 +1,27 @@
+use serde::Deserialize;
+use std::fmt::Display;
+use std::sync::Arc;
+
+/// How much protection a scheme offers, ordered from weakest to strongest
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SecurityLevel {
+    /// leaks plaintext (e.g. ECB, where equal blocks encrypt equally, or CTR
+    /// with a fixed IV, which reuses its keystream)
+    Insecure,
+    /// confidential, but ciphertexts can be modified undetected (e.g. CBC)
+    Unauthenticated,
+    /// confidential and tamper-evident (AEAD, e.g. GCM, ChaCha20-Poly1305, AES-SIV, or encrypt-then-MAC)
+    Authenticated,
+}
+
+/// The scheme implementation built-in schemes come from: the openssl
+/// backend, or the rustcrypto backend when built without it
+#[cfg(feature = "openssl")]
+pub type DefaultScheme = crate::openssl_aes::wrapper::OpensslAesWrapper;
+#[cfg(all(feature = "rustcrypto", not(feature = "openssl")))]
+pub type DefaultScheme = crate::rustcrypto_aes::wrapper::RustCryptoAesWrapper;
+
 pub trait EncryptionScheme {
     type Error;
     type Variant;
@@ -5,12 +29,198 @@ pub trait EncryptionScheme {
     // get a new scheme based on a variant of the original scheme
     fn new(v: &Self::Variant) -> Self;
 
+    // get the identifier of this scheme (recorded in every box it seals)
+    fn identifier(&self) -> &str;
+
     // get the key length of this scheme
     fn get_key_length(&self) -> usize;
 
+    // get the security level of this scheme
+    fn security_level(&self) -> SecurityLevel;
+
+    // whether equal messages encrypt to equal ciphertexts under the same key
+    // (e.g. AES-SIV), which reveals which ciphertexts are equal. Schemes
+    // with a random IV or nonce need not override this
+    fn is_deterministic(&self) -> bool {
+        false
+    }
+
     // encrypt msg using key
     fn encrypt(&self, key: &[u8], msg: &[u8]) -> Result<Vec<u8>, Self::Error>;
 
+    // encrypt msg using key, appending the ciphertext to out. Schemes that can
+    // write in place should override this, the default copies from encrypt
+    fn encrypt_into(&self, key: &[u8], msg: &[u8], out: &mut Vec<u8>) -> Result<(), Self::Error> {
+        out.extend(self.encrypt(key, msg)?);
+        Ok(())
+    }
+
     // decrypt ciphertext using key
     fn decrypt(&self, key: &[u8], ctext: &[u8]) -> Result<Vec<u8>, Self::Error>;
+
+    // encrypt msg using key as encrypt_into does, authenticating aad along
+    // with it (boxes pass their header). Authenticated schemes must override
+    // this, the default ignores aad
+    fn encrypt_with_aad_into(
+        &self,
+        key: &[u8],
+        aad: &[u8],
+        msg: &[u8],
+        out: &mut Vec<u8>,
+    ) -> Result<(), Self::Error> {
+        let _ = aad;
+        self.encrypt_into(key, msg, out)
+    }
+
+    // the inverse of encrypt_with_aad_into, failing unless aad is the same
+    fn decrypt_with_aad(
+        &self,
+        key: &[u8],
+        aad: &[u8],
+        ctext: &[u8],
+    ) -> Result<Vec<u8>, Self::Error> {
+        let _ = aad;
+        self.decrypt(key, ctext)
+    }
+}
+
+/// An object-safe view of an `EncryptionScheme`, so that schemes can be
+/// chosen at runtime (see registry.rs). Errors are the scheme's error message
+pub trait DynScheme: Send + Sync {
+    // get the identifier of this scheme (recorded in every box it seals)
+    fn identifier(&self) -> &str;
+
+    // get the key length of this scheme
+    fn get_key_length(&self) -> usize;
+
+    // get the security level of this scheme
+    fn security_level(&self) -> SecurityLevel;
+
+    // whether equal messages encrypt to equal ciphertexts under the same key
+    fn is_deterministic(&self) -> bool;
+
+    // encrypt msg using key
+    fn encrypt(&self, key: &[u8], msg: &[u8]) -> Result<Vec<u8>, String>;
+
+    // encrypt msg using key, appending the ciphertext to out
+    fn encrypt_into(&self, key: &[u8], msg: &[u8], out: &mut Vec<u8>) -> Result<(), String>;
+
+    // decrypt ciphertext using key
+    fn decrypt(&self, key: &[u8], ctext: &[u8]) -> Result<Vec<u8>, String>;
+
+    // encrypt msg using key, authenticating aad, appending the ciphertext to out
+    fn encrypt_with_aad_into(
+        &self,
+        key: &[u8],
+        aad: &[u8],
+        msg: &[u8],
+        out: &mut Vec<u8>,
+    ) -> Result<(), String>;
+
+    // decrypt ciphertext using key, failing unless aad is the one it was sealed with
+    fn decrypt_with_aad(&self, key: &[u8], aad: &[u8], ctext: &[u8]) -> Result<Vec<u8>, String>;
+}
+
+impl<T> DynScheme for T
+where
+    T: EncryptionScheme + Send + Sync,
+    T::Error: Display,
+{
+    fn identifier(&self) -> &str {
+        EncryptionScheme::identifier(self)
+    }
+
+    fn get_key_length(&self) -> usize {
+        EncryptionScheme::get_key_length(self)
+    }
+
+    fn security_level(&self) -> SecurityLevel {
+        EncryptionScheme::security_level(self)
+    }
+
+    fn is_deterministic(&self) -> bool {
+        EncryptionScheme::is_deterministic(self)
+    }
+
+    fn encrypt(&self, key: &[u8], msg: &[u8]) -> Result<Vec<u8>, String> {
+        EncryptionScheme::encrypt(self, key, msg).map_err(|e| e.to_string())
+    }
+
+    fn encrypt_into(&self, key: &[u8], msg: &[u8], out: &mut Vec<u8>) -> Result<(), String> {
+        EncryptionScheme::encrypt_into(self, key, msg, out).map_err(|e| e.to_string())
+    }
+
+    fn decrypt(&self, key: &[u8], ctext: &[u8]) -> Result<Vec<u8>, String> {
+        EncryptionScheme::decrypt(self, key, ctext).map_err(|e| e.to_string())
+    }
+
+    fn encrypt_with_aad_into(
+        &self,
+        key: &[u8],
+        aad: &[u8],
+        msg: &[u8],
+        out: &mut Vec<u8>,
+    ) -> Result<(), String> {
+        EncryptionScheme::encrypt_with_aad_into(self, key, aad, msg, out).map_err(|e| e.to_string())
+    }
+
+    fn decrypt_with_aad(&self, key: &[u8], aad: &[u8], ctext: &[u8]) -> Result<Vec<u8>, String> {
+        EncryptionScheme::decrypt_with_aad(self, key, aad, ctext).map_err(|e| e.to_string())
+    }
+}
+
+/// A scheme chosen at runtime, e.g. `EncryptedBox<SchemeRef>`
+pub type SchemeRef = Arc<dyn DynScheme>;
+
+/// Lets `EncryptedBox` and `EncryptedBoxBuilder` use runtime-chosen schemes.
+/// The variant of a `SchemeRef` is the scheme itself
+impl EncryptionScheme for SchemeRef {
+    type Error = String;
+    type Variant = SchemeRef;
+
+    fn new(v: &SchemeRef) -> SchemeRef {
+        Arc::clone(v)
+    }
+
+    fn identifier(&self) -> &str {
+        DynScheme::identifier(&**self)
+    }
+
+    fn get_key_length(&self) -> usize {
+        DynScheme::get_key_length(&**self)
+    }
+
+    fn security_level(&self) -> SecurityLevel {
+        DynScheme::security_level(&**self)
+    }
+
+    fn is_deterministic(&self) -> bool {
+        DynScheme::is_deterministic(&**self)
+    }
+
+    fn encrypt(&self, key: &[u8], msg: &[u8]) -> Result<Vec<u8>, String> {
+        DynScheme::encrypt(&**self, key, msg)
+    }
+
+    fn encrypt_into(&self, key: &[u8], msg: &[u8], out: &mut Vec<u8>) -> Result<(), String> {
+        DynScheme::encrypt_into(&**self, key, msg, out)
+    }
+
+    fn decrypt(&self, key: &[u8], ctext: &[u8]) -> Result<Vec<u8>, String> {
+        DynScheme::decrypt(&**self, key, ctext)
+    }
+
+    fn encrypt_with_aad_into(
+        &self,
+        key: &[u8],
+        aad: &[u8],
+        msg: &[u8],
+        out: &mut Vec<u8>,
+    ) -> Result<(), String> {
+        DynScheme::encrypt_with_aad_into(&**self, key, aad, msg, out)
+    }
+
+    fn decrypt_with_aad(&self, key: &[u8], aad: &[u8], ctext: &[u8]) -> Result<Vec<u8>, String> {
+        DynScheme::decrypt_with_aad(&**self, key, aad, ctext)
+    }
 }
@@ -0,0 +1,157 @@
+//? implementation of errors taken from
+//? https://github.com/BurntSushi/imdb-rename/blob/master/imdb-index/src/error.rs
+
+use std::fmt;
+use std::result;
+
+use failure::{Backtrace, Context, Fail};
+
+/// A type alias for handling errors throughout encrypted-box.
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(Debug)]
+pub struct Error {
+    ctx: Context<ErrorKind>,
+}
+
+impl Error {
+    /// Return the kind of this error.
+    pub fn kind(&self) -> &ErrorKind {
+        self.ctx.get_context()
+    }
+
+    pub(crate) fn batch<T>(msg: T) -> Error
+    where
+        T: ToString,
+    {
+        Error::from(ErrorKind::BatchError(msg.to_string()))
+    }
+
+    pub(crate) fn config<T>(msg: T) -> Error
+    where
+        T: ToString,
+    {
+        Error::from(ErrorKind::ConfigError(msg.to_string()))
+    }
+
+    pub(crate) fn encoding<T>(msg: T) -> Error
+    where
+        T: ToString,
+    {
+        Error::from(ErrorKind::EncodingError(msg.to_string()))
+    }
+
+    pub(crate) fn expiry<T>(msg: T) -> Error
+    where
+        T: ToString,
+    {
+        Error::from(ErrorKind::ExpiryError(msg.to_string()))
+    }
+
+    // only the async API does its own IO so far
+    #[cfg_attr(not(feature = "async"), allow(dead_code))]
+    pub(crate) fn io<T>(msg: T) -> Error
+    where
+        T: ToString,
+    {
+        Error::from(ErrorKind::IoError(msg.to_string()))
+    }
+
+    pub(crate) fn malformed<T>(msg: T) -> Error
+    where
+        T: ToString,
+    {
+        Error::from(ErrorKind::MalformedBox(msg.to_string()))
+    }
+
+    pub(crate) fn policy<T>(msg: T) -> Error
+    where
+        T: ToString,
+    {
+        Error::from(ErrorKind::PolicyViolation(msg.to_string()))
+    }
+
+    pub(crate) fn scheme<T>(msg: T) -> Error
+    where
+        T: ToString,
+    {
+        Error::from(ErrorKind::SchemeError(msg.to_string()))
+    }
+}
+
+impl Fail for Error {
+    fn cause(&self) -> Option<&dyn Fail> {
+        self.ctx.cause()
+    }
+
+    fn backtrace(&self) -> Option<&Backtrace> {
+        self.ctx.backtrace()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.ctx.fmt(f)
+    }
+}
+
+/// The specific kind of error that can occur.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ErrorKind {
+    /// A batch run could not read or write its records.
+    BatchError(String),
+
+    /// The configuration file could not be read or parsed.
+    ConfigError(String),
+
+    /// An error in encoding or decoding an encrypted box.
+    EncodingError(String),
+
+    /// The box is outside its validity window: it expired, or is not valid yet.
+    ExpiryError(String),
+
+    /// Reading or writing a box failed.
+    IoError(String),
+
+    /// The encrypted box (header or framing) could not be parsed.
+    MalformedBox(String),
+
+    /// The encrypted box was sealed in a way the decryption policy forbids.
+    PolicyViolation(String),
+
+    /// The underlying encryption scheme failed.
+    SchemeError(String),
+    /// This enum may grow additional variants, so this makes sure clients
+    /// don't count on exhaustive matching. (Otherwise, adding a new variant
+    /// could break existing code.)
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::BatchError(err) => write!(f, "batch error: '{}'", err),
+            ErrorKind::ConfigError(err) => write!(f, "config error: '{}'", err),
+            ErrorKind::EncodingError(err) => write!(f, "encoding error: '{}'", err),
+            ErrorKind::ExpiryError(err) => write!(f, "expiry error: '{}'", err),
+            ErrorKind::IoError(err) => write!(f, "io error: '{}'", err),
+            ErrorKind::MalformedBox(err) => write!(f, "malformed encrypted box: '{}'", err),
+            ErrorKind::PolicyViolation(err) => write!(f, "policy violation: '{}'", err),
+            ErrorKind::SchemeError(err) => write!(f, "scheme error: '{}'", err),
+            ErrorKind::__Nonexhaustive => panic!("invalid error"),
+        }
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Error {
+        Error::from(Context::new(kind))
+    }
+}
+
+impl From<Context<ErrorKind>> for Error {
+    fn from(ctx: Context<ErrorKind>) -> Error {
+        Error { ctx }
+    }
+}
@@ -0,0 +1,525 @@
+use crate::encryption_scheme::{DefaultScheme, EncryptionScheme};
+use crate::errors::{Error, Result};
+use crate::kdf::{Kdf, KdfParams};
+use crate::openssl_aes::defs::OpenSslVariants;
+use crate::subkeys::{MasterKey, Purpose, MASTER_KEY_LEN};
+use std::fmt;
+
+// Format-preserving encryption (NIST SP 800-38G, revision 1) encrypts a
+// string of numerals in some alphabet (e.g. the digits of a card number) to
+// another string of the same length in the same alphabet:
+//
+//   FF1     a 10 round Feistel network, with CBC-MAC of the tweak and one half
+//           as its round function, and tweaks of any length
+//   FF3-1   an 8 round Feistel network, with a single AES block of the tweak
+//           and one half as its round function, and 7 byte (56 bit) tweaks
+//
+// Both use AES through the CBC mode of the default scheme. Values are
+// encrypted deterministically, so equal values (with equal tweaks) give
+// equal ciphertexts, and there is no room for a MAC: tampering is not
+// detected. Either half must fit 96 bits here (FF3-1's own limit), so values
+// are at most 2 * floor(log_radix(2^96)) numerals long (56 digits), and at
+// least long enough for a million possible values.
+
+/// the length of FF3-1's tweaks
+pub const FF3_1_TWEAK_LEN: usize = 7;
+
+/// the minimal number of possible values of an input (see SP 800-38G, 5.2)
+const MIN_DOMAIN: u128 = 1_000_000;
+
+/// the halves of the Feistel network are at most 2^96
+const MAX_HALF: u128 = 1 << 96;
+
+type Block = [u8; 16];
+
+/// The format-preserving encryption modes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FpeMode {
+    /// FF1, with tweaks of any length
+    Ff1,
+    /// FF3-1, with tweaks of `FF3_1_TWEAK_LEN` bytes
+    Ff3_1,
+}
+
+impl FpeMode {
+    /// the name of this mode
+    pub fn name(self) -> &'static str {
+        match self {
+            FpeMode::Ff1 => "ff1",
+            FpeMode::Ff3_1 => "ff3-1",
+        }
+    }
+}
+
+impl fmt::Display for FpeMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The characters values are written in. The radix is their number, and
+/// every character stands for its position, e.g. "0123456789" for decimals
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Alphabet {
+    chars: Vec<char>,
+}
+
+impl Alphabet {
+    /// an alphabet of 2 to 65536 distinct characters
+    pub fn new(chars: &str) -> Result<Alphabet> {
+        let chars: Vec<char> = chars.chars().collect();
+        if chars.len() < 2 || chars.len() > 1 << 16 {
+            return Err(Error::scheme("an alphabet must have 2 to 65536 characters"));
+        }
+        if chars
+            .iter()
+            .enumerate()
+            .any(|(i, c)| chars[..i].contains(c))
+        {
+            return Err(Error::scheme(
+                "the characters of an alphabet must be distinct",
+            ));
+        }
+        Ok(Alphabet { chars })
+    }
+
+    /// the decimal digits
+    pub fn digits() -> Alphabet {
+        Alphabet {
+            chars: "0123456789".chars().collect(),
+        }
+    }
+
+    /// the number of characters
+    pub fn radix(&self) -> u32 {
+        self.chars.len() as u32
+    }
+
+    fn numerals(&self, value: &str) -> Result<Vec<u32>> {
+        value
+            .chars()
+            .map(|c| {
+                self.chars
+                    .iter()
+                    .position(|&a| a == c)
+                    .map(|i| i as u32)
+                    .ok_or_else(|| Error::scheme(format!("'{}' is not in the alphabet", c)))
+            })
+            .collect()
+    }
+
+    fn string(&self, numerals: &[u32]) -> String {
+        numerals.iter().map(|&n| self.chars[n as usize]).collect()
+    }
+}
+
+/// Encrypts values of an alphabet to values of the same length in it
+#[derive(Clone)]
+pub struct FormatPreserving {
+    mode: FpeMode,
+    alphabet: Alphabet,
+    key: Vec<u8>,
+    cipher: DefaultScheme,
+}
+
+impl FormatPreserving {
+    /// encrypt with mode under an AES key (16, 24 or 32 bytes)
+    pub fn new(mode: FpeMode, alphabet: Alphabet, key: &[u8]) -> Result<FormatPreserving> {
+        let variant = match key.len() {
+            16 => OpenSslVariants::Aes128Cbc,
+            24 => OpenSslVariants::Aes192Cbc,
+            32 => OpenSslVariants::Aes256Cbc,
+            len => {
+                return Err(Error::scheme(format!(
+                    "key length mismatch: expected 16, 24 or 32 but got {}",
+                    len
+                )))
+            }
+        };
+        // FF3-1 uses the key with its bytes reversed
+        let key = match mode {
+            FpeMode::Ff1 => key.to_vec(),
+            FpeMode::Ff3_1 => key.iter().rev().cloned().collect(),
+        };
+        Ok(FormatPreserving {
+            mode,
+            alphabet,
+            key,
+            cipher: DefaultScheme::new(&variant),
+        })
+    }
+
+    /// encrypt the values called name (e.g. a column) with an AES-256 subkey
+    /// of master_key
+    pub fn with_master_key(
+        name: &str,
+        mode: FpeMode,
+        alphabet: Alphabet,
+        master_key: &[u8],
+    ) -> FormatPreserving {
+        let label = format!("{} {}", mode, name);
+        let key = MasterKey::new(master_key).subkey(Purpose::FormatPreserving, &label, 32);
+        FormatPreserving::new(mode, alphabet, &key).expect("AES-256 keys are 32 bytes")
+    }
+
+    /// encrypt the values called name with a key derived from password (see
+    /// `EncryptedBoxBuilder::format_preserving`). As there is nowhere to store
+    /// a salt, the master key is derived with the deterministic salt (see kdf.rs)
+    pub fn derive(
+        name: &str,
+        mode: FpeMode,
+        alphabet: Alphabet,
+        password: &str,
+        kdf: Kdf,
+    ) -> FormatPreserving {
+        let master_key = KdfParams::deterministic(kdf).derive(password, MASTER_KEY_LEN);
+        FormatPreserving::with_master_key(name, mode, alphabet, &master_key)
+    }
+
+    /// the mode values are encrypted with
+    pub fn mode(&self) -> FpeMode {
+        self.mode
+    }
+
+    /// the alphabet of values
+    pub fn alphabet(&self) -> &Alphabet {
+        &self.alphabet
+    }
+
+    /// the length of the shortest value, which has at least a million
+    /// possible values
+    pub fn min_len(&self) -> usize {
+        let radix = u128::from(self.alphabet.radix());
+        let mut len = 1;
+        while radix.pow(len) < MIN_DOMAIN {
+            len += 1;
+        }
+        (len as usize).max(2)
+    }
+
+    /// the length of the longest value, whose halves fit 96 bits
+    pub fn max_len(&self) -> usize {
+        let radix = u128::from(self.alphabet.radix());
+        let mut half = 0;
+        while radix.pow(half + 1) <= MAX_HALF {
+            half += 1;
+        }
+        2 * half as usize
+    }
+
+    /// encrypt value, which must consist of characters of the alphabet,
+    /// with tweak (which need not be secret, e.g. the id of a record)
+    pub fn encrypt(&self, value: &str, tweak: &[u8]) -> Result<String> {
+        self.apply(value, tweak, false)
+    }
+
+    /// decrypt a value encrypted with tweak
+    pub fn decrypt(&self, value: &str, tweak: &[u8]) -> Result<String> {
+        self.apply(value, tweak, true)
+    }
+
+    fn apply(&self, value: &str, tweak: &[u8], decrypt: bool) -> Result<String> {
+        let numerals = self.alphabet.numerals(value)?;
+        let (min, max) = (self.min_len(), self.max_len());
+        if numerals.len() < min || numerals.len() > max {
+            return Err(Error::scheme(format!(
+                "values must have {} to {} characters, not {}",
+                min,
+                max,
+                numerals.len()
+            )));
+        }
+        let numerals = match self.mode {
+            FpeMode::Ff1 => self.ff1(&numerals, tweak, decrypt)?,
+            FpeMode::Ff3_1 => {
+                if tweak.len() != FF3_1_TWEAK_LEN {
+                    return Err(Error::scheme(format!(
+                        "FF3-1 tweaks must have {} bytes, not {}",
+                        FF3_1_TWEAK_LEN,
+                        tweak.len()
+                    )));
+                }
+                self.ff3_1(&numerals, tweak, decrypt)?
+            }
+        };
+        Ok(self.alphabet.string(&numerals))
+    }
+
+    // SP 800-38G, algorithms 7 and 8
+    fn ff1(&self, x: &[u32], tweak: &[u8], decrypt: bool) -> Result<Vec<u32>> {
+        let radix = self.alphabet.radix();
+        let n = x.len();
+        let (u, v) = (n / 2, n - n / 2);
+        let (mut a, mut b) = (x[..u].to_vec(), x[u..].to_vec());
+        // the bytes of a half, and of the part of the round function used
+        let bytes = bits(pow(radix, v) - 1).div_ceil(8);
+        let d = 4 * bytes.div_ceil(4) + 4;
+
+        let mut p = vec![1, 2, 1];
+        p.extend_from_slice(&radix.to_be_bytes()[1..]);
+        p.extend_from_slice(&[10, u as u8]);
+        p.extend_from_slice(&(n as u32).to_be_bytes());
+        p.extend_from_slice(&(tweak.len() as u32).to_be_bytes());
+        let zeros = (16 - (tweak.len() + bytes + 1) % 16) % 16;
+
+        for round in 0..10u8 {
+            let i = if decrypt { 9 - round } else { round };
+            let mut q = p.clone();
+            q.extend_from_slice(tweak);
+            q.resize(q.len() + zeros, 0);
+            q.push(i);
+            let half = if decrypt { &a } else { &b };
+            q.extend_from_slice(&num(half, radix).to_be_bytes()[16 - bytes..]);
+            // d is at most 16, so the round function is the first d bytes of R
+            let r = self.cbc_mac(&q)?;
+            let y = r[..d]
+                .iter()
+                .fold(0u128, |acc, &byte| acc << 8 | u128::from(byte));
+            let m = if i % 2 == 0 { u } else { v };
+            let c = feistel(if decrypt { &b } else { &a }, y, radix, m, decrypt);
+            if decrypt {
+                b = a;
+                a = c;
+            } else {
+                a = b;
+                b = c;
+            }
+        }
+        a.extend(b);
+        Ok(a)
+    }
+
+    // SP 800-38G revision 1, algorithms 9 and 10
+    fn ff3_1(&self, x: &[u32], tweak: &[u8], decrypt: bool) -> Result<Vec<u32>> {
+        let radix = self.alphabet.radix();
+        let n = x.len();
+        let (u, v) = (n - n / 2, n / 2);
+        // numerals are read least significant first
+        let (mut a, mut b): (Vec<u32>, Vec<u32>) = (
+            x[..u].iter().rev().cloned().collect(),
+            x[u..].iter().rev().cloned().collect(),
+        );
+        let left = [tweak[0], tweak[1], tweak[2], tweak[3] & 0xf0];
+        let right = [tweak[4], tweak[5], tweak[6], tweak[3] << 4];
+
+        for round in 0..8u8 {
+            let i = if decrypt { 7 - round } else { round };
+            let (m, w) = if i % 2 == 0 { (u, right) } else { (v, left) };
+            let mut p = [0u8; 16];
+            p[..4].copy_from_slice(&w);
+            p[3] ^= i;
+            let half = if decrypt { &a } else { &b };
+            p[4..].copy_from_slice(&num(half, radix).to_be_bytes()[4..]);
+            // the block is encrypted with its bytes reversed, as is the key
+            p.reverse();
+            let mut s = self.cbc_mac(&p)?;
+            s.reverse();
+            let y = u128::from_be_bytes(s);
+            let c = feistel(if decrypt { &b } else { &a }, y, radix, m, decrypt);
+            if decrypt {
+                b = a;
+                a = c;
+            } else {
+                a = b;
+                b = c;
+            }
+        }
+        a.reverse();
+        b.reverse();
+        a.extend(b);
+        Ok(a)
+    }
+
+    // CBC-MAC with a zero IV, of a message of whole blocks, which is CIPH for
+    // a single block
+    fn cbc_mac(&self, msg: &[u8]) -> Result<Block> {
+        let out = self
+            .cipher
+            .encrypt_with_iv(&self.key, Some(&[0u8; 16]), &[], msg)
+            .map_err(Error::scheme)?;
+        // the last block is padding
+        let mut block = [0u8; 16];
+        block.copy_from_slice(&out[msg.len() - 16..msg.len()]);
+        Ok(block)
+    }
+}
+
+impl fmt::Debug for FormatPreserving {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FormatPreserving")
+            .field("mode", &self.mode)
+            .field("alphabet", &self.alphabet)
+            .finish()
+    }
+}
+
+// the new half of a round: half plus (or, decrypting, minus) y modulo radix^m,
+// as m numerals
+fn feistel(half: &[u32], y: u128, radix: u32, m: usize, decrypt: bool) -> Vec<u32> {
+    let modulus = pow(radix, m);
+    let (half, y) = (num(half, radix), y % modulus);
+    let mut c = if decrypt {
+        (half + modulus - y) % modulus
+    } else {
+        (half + y) % modulus
+    };
+    let mut out = vec![0; m];
+    for numeral in out.iter_mut().rev() {
+        *numeral = (c % u128::from(radix)) as u32;
+        c /= u128::from(radix);
+    }
+    out
+}
+
+// the number the numerals stand for, most significant first
+fn num(numerals: &[u32], radix: u32) -> u128 {
+    numerals
+        .iter()
+        .fold(0, |acc, &n| acc * u128::from(radix) + u128::from(n))
+}
+
+fn pow(radix: u32, m: usize) -> u128 {
+    u128::from(radix).pow(m as u32)
+}
+
+fn bits(x: u128) -> usize {
+    128 - x.leading_zeros() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "2b7e151628aed2a6abf7158809cf4f3cef4359d8d580aa4f7f036d6f04fc6a94";
+    const BASE36: &str = "0123456789abcdefghijklmnopqrstuvwxyz";
+
+    fn check(mode: FpeMode, key: &str, tweak: &str, alphabet: &str, plain: &str, cipher: &str) {
+        let alphabet = Alphabet::new(alphabet).unwrap();
+        let fpe = FormatPreserving::new(mode, alphabet, &hex::decode(key).unwrap()).unwrap();
+        let tweak = hex::decode(tweak).unwrap();
+        assert_eq!(fpe.encrypt(plain, &tweak).unwrap(), cipher);
+        assert_eq!(fpe.decrypt(cipher, &tweak).unwrap(), plain);
+    }
+
+    #[test]
+    fn ff1_known_answers() {
+        // SP 800-38G, FF1 samples 1 to 9: AES-128, AES-192 and AES-256
+        let samples = [
+            (32, "", "2433477484"),
+            (32, "39383736353433323130", "6124200773"),
+            (48, "", "2830668132"),
+            (48, "39383736353433323130", "2496655549"),
+            (64, "", "6657667009"),
+            (64, "39383736353433323130", "1001623463"),
+        ];
+        for (key_len, tweak, cipher) in &samples {
+            let key = &KEY[..*key_len];
+            check(
+                FpeMode::Ff1,
+                key,
+                tweak,
+                &BASE36[..10],
+                "0123456789",
+                cipher,
+            );
+        }
+        let samples = [
+            (32, "a9tv40mll9kdu509eum"),
+            (48, "xbj3kv35jrawxv32ysr"),
+            (64, "xs8a0azh2avyalyzuwd"),
+        ];
+        for (key_len, cipher) in &samples {
+            let (key, tweak) = (&KEY[..*key_len], "3737373770717273373737");
+            check(
+                FpeMode::Ff1,
+                key,
+                tweak,
+                BASE36,
+                "0123456789abcdefghi",
+                cipher,
+            );
+        }
+    }
+
+    #[test]
+    fn ff3_1_known_answers() {
+        // NIST ACVP, FF3-1
+        check(
+            FpeMode::Ff3_1,
+            "2de79d232df5585d68ce47882ae256d6",
+            "cbd09280979564",
+            &BASE36[..10],
+            "3992520240",
+            "8901801106",
+        );
+        // the longest decimal value
+        check(
+            FpeMode::Ff3_1,
+            "01c63017111438f7fc8e24eb16c71ab5",
+            "c4e822dcd09f27",
+            &BASE36[..10],
+            "60761757463116869318437658042297305934914824457484538562",
+            "35637144092473838892796702739628394376915177448290847293",
+        );
+    }
+
+    #[test]
+    fn odd_lengths_and_alphabets_round_trip() -> Result<()> {
+        let key = hex::decode(KEY).unwrap();
+        let alphabet = Alphabet::new("αβγδεζηθικλμνξοπ")?;
+        for mode in &[FpeMode::Ff1, FpeMode::Ff3_1] {
+            let fpe = FormatPreserving::new(*mode, alphabet.clone(), &key)?;
+            let value = "αβγδεζηθικλμνξο";
+            let enc = fpe.encrypt(value, &[1; FF3_1_TWEAK_LEN])?;
+            assert_eq!(enc.chars().count(), value.chars().count());
+            assert!(enc.chars().all(|c| alphabet.chars.contains(&c)));
+            assert_ne!(enc, value);
+            assert_ne!(fpe.encrypt(value, &[2; FF3_1_TWEAK_LEN])?, enc);
+            assert_eq!(fpe.decrypt(&enc, &[1; FF3_1_TWEAK_LEN])?, value);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn lengths_are_bounded() -> Result<()> {
+        let fpe = FormatPreserving::new(FpeMode::Ff1, Alphabet::digits(), &[7; 16])?;
+        assert_eq!((fpe.min_len(), fpe.max_len()), (6, 56));
+        assert!(fpe.encrypt("12345", &[]).is_err());
+        assert!(fpe.encrypt("123456", &[]).is_ok());
+        assert!(fpe.encrypt(&"1".repeat(57), &[]).is_err());
+        let binary = FormatPreserving::new(FpeMode::Ff1, Alphabet::new("01")?, &[7; 16])?;
+        assert_eq!((binary.min_len(), binary.max_len()), (20, 192));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_input_is_refused() -> Result<()> {
+        assert!(Alphabet::new("0").is_err());
+        assert!(Alphabet::new("0120").is_err());
+        assert!(FormatPreserving::new(FpeMode::Ff1, Alphabet::digits(), &[7; 20]).is_err());
+        let fpe = FormatPreserving::new(FpeMode::Ff3_1, Alphabet::digits(), &[7; 16])?;
+        assert!(fpe.encrypt("4111-1111", &[0; FF3_1_TWEAK_LEN]).is_err());
+        assert!(fpe.encrypt("41111111", &[0; 8]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn names_get_their_own_keys() -> Result<()> {
+        let master_key = [7; MASTER_KEY_LEN];
+        let card = FormatPreserving::with_master_key(
+            "card",
+            FpeMode::Ff1,
+            Alphabet::digits(),
+            &master_key,
+        );
+        let phone = FormatPreserving::with_master_key(
+            "phone",
+            FpeMode::Ff1,
+            Alphabet::digits(),
+            &master_key,
+        );
+        let value = "4111111111111111";
+        assert_ne!(card.encrypt(value, &[])?, phone.encrypt(value, &[])?);
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+use crate::errors::{Error, Result};
+use crate::padding::Padding;
+
+// Fields are framed as a big-endian u32 field count followed by, for every
+// field, a big-endian u32 length and the field's bytes. This keeps field
+// boundaries intact through encryption, whatever the fields contain.
+
+/// frame fields into a single plaintext
+pub fn encode(fields: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(encoded_len(fields));
+    encode_into(fields, &mut out);
+    out
+}
+
+/// the length of the framed fields
+pub fn encoded_len(fields: &[Vec<u8>]) -> usize {
+    4 + fields.iter().map(|f| 4 + f.len()).sum::<usize>()
+}
+
+/// frame fields, appending them to out
+pub fn encode_into(fields: &[Vec<u8>], out: &mut Vec<u8>) {
+    out.extend_from_slice(&(fields.len() as u32).to_be_bytes());
+    for field in fields {
+        out.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        out.extend_from_slice(&field[..]);
+    }
+}
+
+/// split a framed plaintext back into its fields
+pub fn decode(data: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut reader = Reader::new(data);
+    let fields = read_fields(&mut reader)?;
+    reader.finish()?;
+    Ok(fields)
+}
+
+/// like `decode`, for a plaintext padded with padding. The fields must be
+/// followed by exactly the zeros padding adds
+pub fn decode_padded(data: &[u8], padding: Padding) -> Result<Vec<Vec<u8>>> {
+    let mut reader = Reader::new(data);
+    let fields = read_fields(&mut reader)?;
+    let framed = data.len() - reader.remaining();
+    if padding.padded_len(framed) != data.len() || reader.rest().iter().any(|&b| b != 0) {
+        return Err(Error::malformed("bad padding"));
+    }
+    Ok(fields)
+}
+
+fn read_fields(reader: &mut Reader) -> Result<Vec<Vec<u8>>> {
+    let count = reader.read_u32()? as usize;
+    // every field takes at least 4 bytes, so never trust count beyond that
+    let mut fields = Vec::with_capacity(count.min(reader.remaining() / 4));
+    for _ in 0..count {
+        let len = reader.read_u32()? as usize;
+        fields.push(reader.read_bytes(len)?.to_vec());
+    }
+    Ok(fields)
+}
+
+/// A bounds-checked cursor over untrusted bytes
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data }
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    /// the bytes not consumed yet
+    pub(crate) fn rest(self) -> &'a [u8] {
+        self.data
+    }
+
+    pub(crate) fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.data.len() {
+            return Err(Error::malformed("unexpected end of data"));
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub(crate) fn read_u16(&mut self) -> Result<u16> {
+        let mut buf = [0u8; 2];
+        buf.copy_from_slice(self.read_bytes(2)?);
+        Ok(u16::from_be_bytes(buf))
+    }
+
+    pub(crate) fn read_u32(&mut self) -> Result<u32> {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(self.read_bytes(4)?);
+        Ok(u32::from_be_bytes(buf))
+    }
+
+    /// fail unless all data was consumed
+    pub(crate) fn finish(self) -> Result<()> {
+        if !self.data.is_empty() {
+            return Err(Error::malformed("trailing data"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode_preserves_boundaries() -> Result<()> {
+        let fields = vec![b"ab".to_vec(), vec![], b"c".to_vec()];
+        assert_eq!(decode(&encode(&fields))?, fields);
+        // same concatenation, different fields
+        let other = vec![b"a".to_vec(), b"bc".to_vec()];
+        assert_ne!(encode(&fields), encode(&other));
+        Ok(())
+    }
+
+    #[test]
+    fn decode_no_fields() -> Result<()> {
+        assert!(decode(&encode(&[]))?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn decode_rejects_truncated_field() {
+        let mut framed = encode(&[b"field".to_vec()]);
+        framed.pop();
+        assert!(decode(&framed).is_err());
+    }
+
+    #[test]
+    fn decode_rejects_trailing_data() {
+        let mut framed = encode(&[b"field".to_vec()]);
+        framed.push(0);
+        assert!(decode(&framed).is_err());
+    }
+
+    #[test]
+    fn decode_padded_strips_exactly_the_padding() -> Result<()> {
+        let fields = vec![b"yes".to_vec()];
+        let mut padded = encode(&fields);
+        Padding::Bucket(16).pad(&mut padded);
+        assert_eq!(decode_padded(&padded, Padding::Bucket(16))?, fields);
+        assert!(decode(&padded).is_err());
+        assert!(decode_padded(&padded, Padding::Bucket(32)).is_err());
+        assert!(decode_padded(&padded[..15], Padding::Bucket(16)).is_err());
+        padded[15] = 1;
+        assert!(decode_padded(&padded, Padding::Bucket(16)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn decode_rejects_huge_count() {
+        assert!(decode(b"\xFF\xFF\xFF\xFF").is_err());
+    }
+}
@@ -0,0 +1,413 @@
+use crate::compression::Compression;
+use crate::errors::{Error, Result};
+use crate::framing::Reader;
+use crate::kdf::{Kdf, KdfParams};
+use crate::padding::Padding;
+use crate::validity::{Validity, HEADER_TAG_LEN};
+use std::convert::TryInto;
+
+// An encrypted box starts with a header describing how it was sealed:
+//
+//   magic      "EBOX"
+//   version    u8, 1 or 2 (see below)
+//   scheme     u8 length, scheme identifier (e.g. "aes-256-gcm")
+//   kdf        u8 kdf id, followed by the kdf's parameters
+//                0 = sha512 (no parameters)
+//                1 = pbkdf2-sha256 (u32 iterations)
+//   salt       u8 length, salt bytes
+//   extensions u8 count, then per extension: u8 type, u16 length, value
+//
+// followed by the scheme's ciphertext of the framed fields (see framing.rs).
+// All integers are big-endian. The extension types are
+//
+//   1          padding (see padding.rs)
+//   2          compression (see compression.rs)
+//   3          not before, u64 seconds since the Unix epoch (see validity.rs)
+//   4          expires at, u64 seconds since the Unix epoch
+//   5          header tag, authenticating the header up to it
+//
+// and each may appear once. Unknown types are rejected rather than silently
+// ignored, so boxes with extensions are refused by older readers. A box with
+// a validity window (types 3 and 4) must have a header tag, as its last
+// extension.
+//
+// The version decides how the scheme's key is derived from the password:
+//
+//   1          the KDF's output of the scheme's key length is the key
+//   2          the KDF's output is a master key (32 bytes), and the key is its
+//              content encryption subkey for the scheme (see subkeys.rs)
+//
+// Version 2 boxes also pass the whole header to the scheme as associated data,
+// so authenticated schemes reject a box whose header (e.g. its padding) was
+// altered. Version 1 headers are not authenticated.
+//
+// Version 1 boxes are still opened, and sealed again as version 1. Boxes
+// from before the header (version 0) open through legacy.rs.
+
+/// the magic bytes every encrypted box starts with
+pub const MAGIC: &[u8; 4] = b"EBOX";
+
+/// the current format version
+pub const VERSION: u8 = 2;
+
+/// the first format version, whose boxes use the KDF's output as the key
+pub const LEGACY_VERSION: u8 = 1;
+
+const KDF_SHA512: u8 = 0;
+const KDF_PBKDF2_SHA256: u8 = 1;
+
+const EXT_PADDING: u8 = 1;
+const EXT_COMPRESSION: u8 = 2;
+const EXT_NOT_BEFORE: u8 = 3;
+const EXT_EXPIRES_AT: u8 = 4;
+const EXT_TAG: u8 = 5;
+
+/// The (unencrypted) header of an encrypted box
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub scheme: String,
+    pub kdf: KdfParams,
+    pub padding: Padding,
+    pub compression: Compression,
+    pub validity: Validity,
+    /// the header tag, which boxes with a validity window carry
+    pub tag: Option<Vec<u8>>,
+}
+
+impl Header {
+    pub fn new(scheme: &str, kdf: KdfParams) -> Header {
+        Header {
+            version: VERSION,
+            scheme: scheme.to_string(),
+            kdf,
+            padding: Padding::None,
+            compression: Compression::None,
+            validity: Validity::default(),
+            tag: None,
+        }
+    }
+
+    /// serialize the header
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(self.encoded_len());
+        self.write_to(&mut out)?;
+        Ok(out)
+    }
+
+    /// the length of the serialized header
+    pub fn encoded_len(&self) -> usize {
+        let kdf_params = match self.kdf.kdf {
+            Kdf::Sha512 => 0,
+            Kdf::Pbkdf2Sha256 { .. } => 4,
+        };
+        let extensions: usize = self.extensions().iter().map(|(_, v)| 3 + v.len()).sum();
+        MAGIC.len()
+            + 1
+            + 1
+            + self.scheme.len()
+            + 1
+            + kdf_params
+            + 1
+            + self.kdf.salt.len()
+            + 1
+            + extensions
+    }
+
+    /// the type and value of every extension
+    fn extensions(&self) -> Vec<(u8, Vec<u8>)> {
+        let mut extensions = Vec::new();
+        if self.padding != Padding::None {
+            extensions.push((EXT_PADDING, self.padding.to_bytes()));
+        }
+        if self.compression != Compression::None {
+            extensions.push((EXT_COMPRESSION, self.compression.to_bytes()));
+        }
+        if let Some(not_before) = self.validity.not_before {
+            extensions.push((EXT_NOT_BEFORE, not_before.to_be_bytes().to_vec()));
+        }
+        if let Some(expires_at) = self.validity.expires_at {
+            extensions.push((EXT_EXPIRES_AT, expires_at.to_be_bytes().to_vec()));
+        }
+        if let Some(tag) = &self.tag {
+            extensions.push((EXT_TAG, tag.clone()));
+        }
+        extensions
+    }
+
+    /// the bytes the header tag authenticates: the serialized header up to
+    /// its tag extension (as if it had one)
+    pub fn authenticated_bytes(&self) -> Result<Vec<u8>> {
+        let mut tagged = self.clone();
+        tagged.tag = Some(vec![0; HEADER_TAG_LEN]);
+        let mut out = tagged.to_bytes()?;
+        out.truncate(out.len() - 3 - HEADER_TAG_LEN);
+        Ok(out)
+    }
+
+    /// serialize the header, appending it to out
+    pub fn write_to(&self, out: &mut Vec<u8>) -> Result<()> {
+        let begin = out.len();
+        let written = self.write_fields(out);
+        if written.is_err() {
+            out.truncate(begin);
+        }
+        written
+    }
+
+    fn write_fields(&self, out: &mut Vec<u8>) -> Result<()> {
+        out.extend_from_slice(MAGIC);
+        out.push(self.version);
+        write_short_bytes(out, self.scheme.as_bytes(), "scheme identifier")?;
+        match self.kdf.kdf {
+            Kdf::Sha512 => out.push(KDF_SHA512),
+            Kdf::Pbkdf2Sha256 { iterations } => {
+                out.push(KDF_PBKDF2_SHA256);
+                out.extend_from_slice(&iterations.to_be_bytes());
+            }
+        }
+        write_short_bytes(out, &self.kdf.salt[..], "salt")?;
+        let extensions = self.extensions();
+        out.push(extensions.len() as u8);
+        for (kind, value) in extensions {
+            out.push(kind);
+            out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            out.extend_from_slice(&value);
+        }
+        Ok(())
+    }
+
+    /// parse a header, returning it with the rest of the data (the ciphertext)
+    pub fn parse(data: &[u8]) -> Result<(Header, &[u8])> {
+        let mut reader = Reader::new(data);
+        if reader.read_bytes(MAGIC.len())? != &MAGIC[..] {
+            return Err(Error::malformed("not an encrypted box (bad magic)"));
+        }
+        let version = reader.read_u8()?;
+        if !(LEGACY_VERSION..=VERSION).contains(&version) {
+            return Err(Error::malformed(format!("unsupported version {}", version)));
+        }
+        let len = reader.read_u8()? as usize;
+        let scheme = std::str::from_utf8(reader.read_bytes(len)?)
+            .map_err(|_| Error::malformed("scheme identifier is not valid UTF-8"))?
+            .to_string();
+        let kdf = match reader.read_u8()? {
+            KDF_SHA512 => Kdf::Sha512,
+            KDF_PBKDF2_SHA256 => Kdf::Pbkdf2Sha256 {
+                iterations: reader.read_u32()?,
+            },
+            id => return Err(Error::malformed(format!("unknown kdf id {}", id))),
+        };
+        let len = reader.read_u8()? as usize;
+        let salt = reader.read_bytes(len)?.to_vec();
+        let mut header = Header {
+            version,
+            scheme,
+            kdf: KdfParams { kdf, salt },
+            padding: Padding::None,
+            compression: Compression::None,
+            validity: Validity::default(),
+            tag: None,
+        };
+        let mut seen = Vec::new();
+        let count = reader.read_u8()?;
+        for i in 0..count {
+            let kind = reader.read_u8()?;
+            let len = reader.read_u16()? as usize;
+            let value = reader.read_bytes(len)?;
+            if seen.contains(&kind) {
+                return Err(Error::malformed(format!(
+                    "duplicate extension type {}",
+                    kind
+                )));
+            }
+            seen.push(kind);
+            match kind {
+                EXT_PADDING => header.padding = Padding::from_bytes(value)?,
+                EXT_COMPRESSION => header.compression = Compression::from_bytes(value)?,
+                EXT_NOT_BEFORE => header.validity.not_before = Some(read_time(value)?),
+                EXT_EXPIRES_AT => header.validity.expires_at = Some(read_time(value)?),
+                EXT_TAG if i + 1 < count => {
+                    return Err(Error::malformed("the header tag is not the last extension"))
+                }
+                EXT_TAG if value.len() != HEADER_TAG_LEN => {
+                    return Err(Error::malformed("bad header tag length"))
+                }
+                EXT_TAG => header.tag = Some(value.to_vec()),
+                _ => return Err(Error::malformed(format!("unknown extension type {}", kind))),
+            }
+        }
+        if !header.validity.is_unbounded() && header.tag.is_none() {
+            return Err(Error::malformed("a validity window without a header tag"));
+        }
+        Ok((header, reader.rest()))
+    }
+}
+
+fn read_time(value: &[u8]) -> Result<u64> {
+    let bytes: [u8; 8] = value
+        .try_into()
+        .map_err(|_| Error::malformed("bad timestamp length"))?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+fn write_short_bytes(out: &mut Vec<u8>, bytes: &[u8], what: &str) -> Result<()> {
+    if bytes.len() > u8::MAX as usize {
+        return Err(Error::malformed(format!("{} is too long", what)));
+    }
+    out.push(bytes.len() as u8);
+    out.extend_from_slice(bytes);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> Header {
+        Header::new(
+            "aes-256-gcm",
+            KdfParams {
+                kdf: Kdf::Pbkdf2Sha256 { iterations: 1000 },
+                salt: b"0123456789abcdef".to_vec(),
+            },
+        )
+    }
+
+    #[test]
+    fn serialize_parse_round_trip() -> Result<()> {
+        let mut data = header().to_bytes()?;
+        data.extend_from_slice(b"ciphertext");
+        let (parsed, rest) = Header::parse(&data)?;
+        assert_eq!(parsed, header());
+        assert_eq!(rest, b"ciphertext");
+        Ok(())
+    }
+
+    #[test]
+    fn known_layout() -> Result<()> {
+        let expected = b"EBOX\x02\x0baes-256-gcm\x01\x00\x00\x03\xe8\x100123456789abcdef\x00";
+        assert_eq!(&header().to_bytes()?[..], &expected[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn write_to_appends() -> Result<()> {
+        let mut out = b"prefix".to_vec();
+        header().write_to(&mut out)?;
+        assert_eq!(out.len(), 6 + header().encoded_len());
+        assert_eq!(&out[6..], &header().to_bytes()?[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_rejects_bad_magic() {
+        assert!(Header::parse(b"EBOY\x01").is_err());
+    }
+
+    #[test]
+    fn parse_rejects_unknown_version() -> Result<()> {
+        let mut data = header().to_bytes()?;
+        for version in &[0, VERSION + 1, u8::MAX] {
+            data[4] = *version;
+            assert!(Header::parse(&data).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_accepts_legacy_version() -> Result<()> {
+        let mut data = header().to_bytes()?;
+        data[4] = LEGACY_VERSION;
+        let (parsed, _) = Header::parse(&data)?;
+        assert_eq!(parsed.version, LEGACY_VERSION);
+        assert_eq!(parsed.to_bytes()?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_rejects_every_truncation() -> Result<()> {
+        let data = header().to_bytes()?;
+        for len in 0..data.len() {
+            assert!(Header::parse(&data[..len]).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn padding_extension() -> Result<()> {
+        let mut padded = header();
+        padded.padding = Padding::Bucket(256);
+        let data = padded.to_bytes()?;
+        assert_eq!(data.len(), padded.encoded_len());
+        assert_eq!(
+            &data[data.len() - 9..],
+            b"\x01\x01\x00\x05\x01\x00\x00\x01\x00"
+        );
+        assert_eq!(Header::parse(&data)?.0, padded);
+
+        let mut twice = data.clone();
+        let len = twice.len();
+        twice[len - 9] = 2;
+        twice.extend_from_slice(&data[len - 8..]);
+        assert!(Header::parse(&twice).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn compression_extension() -> Result<()> {
+        let mut compressed = header();
+        compressed.padding = Padding::PowerOfTwo;
+        compressed.compression = Compression::Zstd;
+        let data = compressed.to_bytes()?;
+        assert_eq!(data.len(), compressed.encoded_len());
+        assert_eq!(
+            &data[data.len() - 9..],
+            b"\x02\x01\x00\x01\x02\x02\x00\x01\x02"
+        );
+        assert_eq!(Header::parse(&data)?.0, compressed);
+        Ok(())
+    }
+
+    #[test]
+    fn validity_extensions() -> Result<()> {
+        let mut bounded = header();
+        bounded.validity = Validity {
+            not_before: Some(1_000),
+            expires_at: Some(2_000),
+        };
+        bounded.tag = Some(vec![7; HEADER_TAG_LEN]);
+        let data = bounded.to_bytes()?;
+        assert_eq!(data.len(), bounded.encoded_len());
+        assert_eq!(Header::parse(&data)?.0, bounded);
+        let authenticated = bounded.authenticated_bytes()?;
+        assert_eq!(&data[..authenticated.len()], &authenticated[..]);
+        assert_eq!(
+            &data[authenticated.len()..],
+            &[&b"\x05\x00\x20"[..], &[7; HEADER_TAG_LEN]].concat()[..]
+        );
+
+        // a window needs a tag
+        let mut untagged = bounded.clone();
+        untagged.tag = None;
+        assert!(Header::parse(&untagged.to_bytes()?).is_err());
+
+        // which comes last
+        let mut data = header().to_bytes()?;
+        data.pop();
+        data.extend_from_slice(b"\x02\x05\x00\x20");
+        data.extend_from_slice(&[7; HEADER_TAG_LEN]);
+        data.extend_from_slice(b"\x03\x00\x08\x00\x00\x00\x00\x00\x00\x03\xe8");
+        assert!(Header::parse(&data).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_rejects_extensions() -> Result<()> {
+        let mut data = header().to_bytes()?;
+        data.pop();
+        data.extend_from_slice(b"\x01\x07\x00\x00");
+        assert!(Header::parse(&data).is_err());
+        Ok(())
+    }
+}
@@ -1,4 +1,181 @@
-use sha2::{Digest, Sha512};
+use hmac::Hmac;
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// length of the random salt generated for every new builder
+pub const SALT_LEN: usize = 16;
+
+/// the salt of deterministic boxes (see `KdfParams::deterministic`)
+pub const DETERMINISTIC_SALT: &[u8] = b"encrypted-box deterministic salt";
+
+/// PBKDF2 iterations used unless told otherwise
+pub const DEFAULT_PBKDF2_ITERATIONS: u32 = 100_000;
+
+/// The key derivation functions a box can be sealed with.
+///
+/// In TOML a KDF is written as e.g.
+/// `{ algorithm = "pbkdf2-sha256", iterations = 200000 }`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(tag = "algorithm", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Kdf {
+    /// a single unsalted SHA-512 (see `derive_key_from_password`), kept for compatibility only
+    Sha512,
+    /// PBKDF2-HMAC-SHA256 with the given number of iterations
+    Pbkdf2Sha256 { iterations: u32 },
+}
+
+impl Kdf {
+    /// the name of this KDF (without parameters)
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kdf::Sha512 => "sha512",
+            Kdf::Pbkdf2Sha256 { .. } => "pbkdf2-sha256",
+        }
+    }
+
+    /// the work factor of this KDF, comparable between instances of the same KDF
+    pub fn cost(&self) -> u64 {
+        match self {
+            Kdf::Sha512 => 1,
+            Kdf::Pbkdf2Sha256 { iterations } => u64::from(*iterations),
+        }
+    }
+
+    /// derive a key of key_len bytes from password and salt
+    pub fn derive(&self, password: &str, salt: &[u8], key_len: usize) -> Vec<u8> {
+        match self {
+            Kdf::Sha512 => derive_key_from_password(&String::from(password), key_len),
+            Kdf::Pbkdf2Sha256 { iterations } => {
+                let mut key = vec![0u8; key_len];
+                pbkdf2::pbkdf2::<Hmac<Sha256>>(
+                    password.as_bytes(),
+                    salt,
+                    *iterations as usize,
+                    &mut key,
+                );
+                key
+            }
+        }
+    }
+}
+
+impl Default for Kdf {
+    fn default() -> Kdf {
+        Kdf::Pbkdf2Sha256 {
+            iterations: DEFAULT_PBKDF2_ITERATIONS,
+        }
+    }
+}
+
+impl fmt::Display for Kdf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kdf::Sha512 => write!(f, "{}", self.name()),
+            Kdf::Pbkdf2Sha256 { iterations } => {
+                write!(f, "{} ({} iterations)", self.name(), iterations)
+            }
+        }
+    }
+}
+
+/// A KDF together with the salt it is used with
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct KdfParams {
+    pub kdf: Kdf,
+    pub salt: Vec<u8>,
+}
+
+impl KdfParams {
+    /// use kdf with a fresh random salt
+    pub fn new(kdf: Kdf) -> KdfParams {
+        KdfParams {
+            kdf,
+            salt: generate_salt(),
+        }
+    }
+
+    /// use kdf with a fixed salt, so that a password always derives the same
+    /// key. Only for deterministic boxes, which must be equal across builders
+    /// (see `EncryptedBoxBuilder::deterministic`): without a random salt, one
+    /// dictionary attack covers every such box
+    pub fn deterministic(kdf: Kdf) -> KdfParams {
+        KdfParams {
+            kdf,
+            salt: DETERMINISTIC_SALT.to_vec(),
+        }
+    }
+
+    /// derive a key of key_len bytes from password
+    pub fn derive(&self, password: &str, key_len: usize) -> Vec<u8> {
+        self.kdf.derive(password, &self.salt[..], key_len)
+    }
+}
+
+/// Derives keys from a single password, remembering every key it derived.
+///
+/// Boxes sealed in one go (e.g. by a batch run) share their KDF parameters,
+/// so decrypting them through a cache derives the key only once.
+pub struct KeyCache {
+    password: String,
+    keys: HashMap<(KdfParams, usize), Vec<u8>>,
+}
+
+impl KeyCache {
+    pub fn new(password: String) -> KeyCache {
+        KeyCache {
+            password,
+            keys: HashMap::new(),
+        }
+    }
+
+    /// the key of key_len bytes derived with params, derived only if not cached
+    pub fn derive(&mut self, params: &KdfParams, key_len: usize) -> &[u8] {
+        let password = &self.password;
+        self.keys
+            .entry((params.clone(), key_len))
+            .or_insert_with(|| params.derive(password, key_len))
+    }
+}
+
+/// the number of PBKDF2 iterations deriving a key takes about target on
+/// this machine (for a build with optimizations, that is)
+pub fn calibrate_pbkdf2(target: Duration) -> u32 {
+    calibrate_with(target, |iterations| {
+        let start = Instant::now();
+        Kdf::Pbkdf2Sha256 { iterations }.derive("password", &[0u8; SALT_LEN], 32);
+        start.elapsed()
+    })
+}
+
+// the iterations taking about target, given how long time says a number of
+// iterations takes
+fn calibrate_with<F>(target: Duration, mut time: F) -> u32
+where
+    F: FnMut(u32) -> Duration,
+{
+    // time ever more iterations, until the measurement is long enough to
+    // extrapolate from
+    let mut iterations: u32 = 1000;
+    loop {
+        let elapsed = time(iterations);
+        if elapsed * 4 >= target || elapsed >= Duration::from_millis(250) {
+            let scaled = f64::from(iterations) * target.as_secs_f64() / elapsed.as_secs_f64();
+            return scaled.max(1.0).min(f64::from(u32::MAX)) as u32;
+        }
+        iterations = iterations.saturating_mul(2);
+    }
+}
+
+/// generate a random salt of SALT_LEN bytes
+pub fn generate_salt() -> Vec<u8> {
+    let mut salt = vec![0u8; SALT_LEN];
+    rand::rngs::OsRng.fill_bytes(&mut salt);
+    salt
+}
 
 pub fn derive_key_from_password(password: &String, key_len: usize) -> Vec<u8> {
     // create a Sha256 object
@@ -13,3 +190,77 @@ pub fn derive_key_from_password(password: &String, key_len: usize) -> Vec<u8> {
     vec.truncate(key_len);
     vec
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWORD: &str = "password";
+
+    #[test]
+    fn pbkdf2_known_answer() {
+        // RFC 7914, section 11 (PBKDF2-HMAC-SHA256 test vector)
+        let kdf = Kdf::Pbkdf2Sha256 { iterations: 1 };
+        let key = kdf.derive("passwd", b"salt", 64);
+        assert_eq!(hex::encode(&key[..16]), "55ac046e56e3089fec1691c22544b605");
+    }
+
+    #[test]
+    fn sha512_ignores_salt() {
+        let kdf = Kdf::Sha512;
+        assert_eq!(
+            kdf.derive(PASSWORD, b"salt", 16),
+            derive_key_from_password(&String::from(PASSWORD), 16)
+        );
+    }
+
+    #[test]
+    fn salt_changes_key() {
+        let kdf = Kdf::Pbkdf2Sha256 { iterations: 10 };
+        assert_ne!(
+            kdf.derive(PASSWORD, b"salt1", 32),
+            kdf.derive(PASSWORD, b"salt2", 32)
+        );
+    }
+
+    #[test]
+    fn key_cache_derives_once() {
+        let params = KdfParams::new(Kdf::Pbkdf2Sha256 { iterations: 10 });
+        let other = KdfParams::new(Kdf::Pbkdf2Sha256 { iterations: 10 });
+        let mut cache = KeyCache::new(String::from(PASSWORD));
+        assert_eq!(cache.derive(&params, 32), &params.derive(PASSWORD, 32)[..]);
+        assert_eq!(cache.derive(&other, 32), &other.derive(PASSWORD, 32)[..]);
+        assert_eq!(cache.derive(&params, 16), &params.derive(PASSWORD, 16)[..]);
+        assert_eq!(cache.keys.len(), 3);
+        cache.derive(&params, 32);
+        assert_eq!(cache.keys.len(), 3);
+    }
+
+    #[test]
+    fn calibrate_pbkdf2_scales() {
+        // a machine doing an iteration per microsecond
+        let per_microsecond = |iterations| Duration::from_micros(u64::from(iterations));
+        assert_eq!(
+            calibrate_with(Duration::from_millis(5), per_microsecond),
+            5000
+        );
+        assert_eq!(
+            calibrate_with(Duration::from_millis(50), per_microsecond),
+            50_000
+        );
+        assert_eq!(
+            calibrate_with(Duration::from_secs(2), per_microsecond),
+            2_000_000
+        );
+        // at least one iteration, however fast the target
+        assert_eq!(calibrate_with(Duration::from_nanos(1), per_microsecond), 1);
+    }
+
+    #[test]
+    fn params_generate_fresh_salts() {
+        let p1 = KdfParams::new(Kdf::default());
+        let p2 = KdfParams::new(Kdf::default());
+        assert_eq!(p1.salt.len(), SALT_LEN);
+        assert_ne!(p1.salt, p2.salt);
+    }
+}
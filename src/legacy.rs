@@ -0,0 +1,214 @@
+use crate::encrypted_box::{EncryptedBox, Fields};
+use crate::encryption_scheme::{DefaultScheme, EncryptionScheme};
+use crate::errors::{Error, Result};
+use crate::header::MAGIC;
+use crate::kdf::{Kdf, KeyCache};
+use crate::openssl_aes::defs::OpenSslVariants;
+use crate::policy::DecryptPolicy;
+use std::fmt::Display;
+use std::str::FromStr;
+
+// Before boxes had a header (see header.rs), the output of `OpensslAesWrapper`
+// was the whole box. Such version 0 boxes are still around:
+//
+//   ciphertext   the fields, concatenated without framing, encrypted with
+//                one of the original twelve schemes (AES-128/192/256 in ECB,
+//                CBC, CTR or OFB mode) under a fixed IV
+//   key          SHA-512 of the password, truncated to the scheme's key length
+//
+// Nothing in a version 0 box tells its scheme, or even that it is one, so the
+// caller has to name the scheme. Any data not starting with the magic of a
+// header is taken for a version 0 box (a version 0 box starts with the magic
+// by chance about once in four billion).
+//
+// The IV is fixed here rather than taken from the schemes, so that version 0
+// boxes keep opening whatever becomes of the schemes' own IVs. The format
+// offers neither confidentiality for repeated fields nor integrity: open
+// version 0 boxes only to migrate them to the current format.
+
+// the IV of the original CBC, CTR and OFB schemes (ECB takes none)
+const V0_IV: &[u8] = b"\x00\x01\x02\x03\x04\x05\x06\x07\x00\x01\x02\x03\x04\x05\x06\x07";
+
+/// whether data is a version 0 box, i.e. does not start with a header
+pub fn is_v0(data: &[u8]) -> bool {
+    !data.starts_with(MAGIC)
+}
+
+/// whether variant is one of the schemes version 0 boxes were sealed with
+pub fn is_v0_scheme(variant: &OpenSslVariants) -> bool {
+    matches!(
+        variant,
+        OpenSslVariants::Aes128Ecb
+            | OpenSslVariants::Aes128Cbc
+            | OpenSslVariants::Aes128Ctr
+            | OpenSslVariants::Aes128Ofb
+            | OpenSslVariants::Aes192Ecb
+            | OpenSslVariants::Aes192Cbc
+            | OpenSslVariants::Aes192Ctr
+            | OpenSslVariants::Aes192Ofb
+            | OpenSslVariants::Aes256Ecb
+            | OpenSslVariants::Aes256Cbc
+            | OpenSslVariants::Aes256Ctr
+            | OpenSslVariants::Aes256Ofb
+    )
+}
+
+/// the key of the version 0 boxes sealed with variant under password
+pub fn v0_key(password: &str, variant: &OpenSslVariants) -> Vec<u8> {
+    Kdf::Sha512.derive(password, &[], variant.key_length())
+}
+
+/// decrypt a version 0 box sealed with variant under key (see `v0_key`)
+/// into its plaintext, the concatenated fields
+pub fn decrypt_v0(key: &[u8], ciphertext: &[u8], variant: &OpenSslVariants) -> Result<Vec<u8>> {
+    if !is_v0_scheme(variant) {
+        return Err(Error::scheme(format!(
+            "version 0 boxes were never sealed with scheme '{}'",
+            variant
+        )));
+    }
+    let iv = variant.iv_length().map(|_| V0_IV);
+    DefaultScheme::new(variant)
+        .decrypt_with_iv(key, iv, &[], ciphertext)
+        .map_err(Error::scheme)
+}
+
+/// open a box of either format: a box with a header as `EncryptedBox::decrypt`
+/// does (checking it against policy), a version 0 box with legacy_scheme.
+/// The fields of a version 0 box cannot be told apart, so it opens to a
+/// single field
+pub fn decrypt<T>(
+    password: String,
+    data: &[u8],
+    legacy_scheme: &OpenSslVariants,
+    policy: &DecryptPolicy,
+) -> Result<Fields>
+where
+    T: EncryptionScheme + Clone,
+    T::Error: Display,
+    T::Variant: FromStr,
+{
+    if !is_v0(data) {
+        let eb = EncryptedBox::<T>::decrypt_with_keys(data, policy, &mut KeyCache::new(password))?;
+        return Ok(eb.fields().to_vec());
+    }
+    let key = v0_key(&password, legacy_scheme);
+    Ok(vec![decrypt_v0(&key, data, legacy_scheme)?])
+}
+
+/// reseal a version 0 box sealed with variant under key (see `v0_key`) in
+/// the current format, with the scheme, key and options of target. The
+/// migrated box holds the plaintext as a single field
+pub fn migrate<T>(
+    key: &[u8],
+    ciphertext: &[u8],
+    variant: &OpenSslVariants,
+    target: &EncryptedBox<T>,
+) -> Result<Vec<u8>>
+where
+    T: EncryptionScheme + Clone,
+    T::Error: Display,
+{
+    if !is_v0(ciphertext) {
+        return Err(Error::malformed("not a version 0 box, it has a header"));
+    }
+    let plaintext = decrypt_v0(key, ciphertext, variant)?;
+    target.encrypt_fields(&[plaintext])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ErrorKind;
+    use crate::kdf::KdfParams;
+    use crate::subkeys::MASTER_KEY_LEN;
+
+    const FIELDS: &[u8; 16] = b"Some Crypto Text";
+    const KEY: &[u8; 16] = b"\x00\x01\x02\x03\x04\x05\x06\x07\x08\x09\x0A\x0B\x0C\x0D\x0E\x0F";
+    const PASSWORD: &str = "password";
+
+    // the ciphertext of the encrypt_with_vector tests of the backends, a
+    // version 0 box of FIELDS sealed with aes-128-cbc under KEY
+    const VECTOR: &[u8] = b"\xB4\xB9\xE7\x30\xD6\xD6\xF7\xDE\x77\x3F\x1C\xFF\xB3\x3E\x44\x5A\
+                            \x91\xD7\x27\x62\x87\x4D\xFB\x3C\x5E\xC4\x59\x72\x4A\xF4\x7C\xA1";
+
+    fn target(variant: &OpenSslVariants) -> EncryptedBox<DefaultScheme> {
+        let params = KdfParams::new(Kdf::default());
+        let master_key = params.derive(PASSWORD, MASTER_KEY_LEN);
+        EncryptedBox::new(Vec::new(), master_key, params, DefaultScheme::new(variant))
+    }
+
+    #[test]
+    fn vector_decrypts() -> Result<()> {
+        assert!(is_v0(VECTOR));
+        assert_eq!(
+            decrypt_v0(KEY, VECTOR, &OpenSslVariants::Aes128Cbc)?,
+            &FIELDS[..]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn vector_migrates() -> Result<()> {
+        let migrated = migrate(
+            KEY,
+            VECTOR,
+            &OpenSslVariants::Aes128Cbc,
+            &target(&OpenSslVariants::Aes256Gcm),
+        )?;
+        assert!(!is_v0(&migrated));
+        let fields = decrypt::<DefaultScheme>(
+            String::from(PASSWORD),
+            &migrated,
+            &OpenSslVariants::Aes128Cbc,
+            &DecryptPolicy::default(),
+        )?;
+        assert_eq!(fields, vec![FIELDS.to_vec()]);
+
+        // a migrated box is not migrated again
+        match migrate(
+            KEY,
+            &migrated,
+            &OpenSslVariants::Aes128Cbc,
+            &target(&OpenSslVariants::Aes256Gcm),
+        ) {
+            Err(e) => assert!(matches!(e.kind(), ErrorKind::MalformedBox(_))),
+            Ok(_) => panic!("migrated a box with a header"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn password_boxes_of_every_v0_scheme() -> Result<()> {
+        for variant in OpenSslVariants::iterator().filter(|v| is_v0_scheme(v)) {
+            // a version 0 box as the schemes sealed them, with their fixed IVs
+            let key = v0_key(PASSWORD, variant);
+            let v0 = DefaultScheme::new(variant)
+                .encrypt(&key, FIELDS)
+                .map_err(Error::scheme)?;
+            let fields = decrypt::<DefaultScheme>(
+                String::from(PASSWORD),
+                &v0,
+                variant,
+                &DecryptPolicy::default(),
+            )?;
+            assert_eq!(fields, vec![FIELDS.to_vec()], "{}", variant);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn only_v0_schemes() {
+        assert_eq!(
+            OpenSslVariants::iterator()
+                .filter(|v| is_v0_scheme(v))
+                .count(),
+            12
+        );
+        let key = [0u8; 32];
+        match decrypt_v0(&key, VECTOR, &OpenSslVariants::Aes256Gcm) {
+            Err(e) => assert!(matches!(e.kind(), ErrorKind::SchemeError(_))),
+            Ok(_) => panic!("opened a version 0 box with aes-256-gcm"),
+        }
+    }
+}
@@ -1,6 +1,31 @@
+#[cfg(feature = "async")]
+pub mod async_box;
+pub mod authenticated;
+pub mod batch;
+pub mod blind_index;
 pub mod cli;
+pub mod compression;
+pub mod config;
+pub mod encoding;
 pub mod encrypted_box;
 pub mod encrypted_box_builder;
 pub mod encryption_scheme;
+pub mod errors;
+pub mod fpe;
+pub mod framing;
+pub mod header;
 pub mod kdf;
+pub mod legacy;
 pub mod openssl_aes;
+pub mod padding;
+pub mod policy;
+pub mod registry;
+#[cfg(feature = "rustcrypto")]
+pub mod rustcrypto_aes;
+pub mod subkeys;
+#[cfg(test)]
+mod test_vectors;
+pub mod validity;
+
+#[cfg(not(any(feature = "openssl", feature = "rustcrypto")))]
+compile_error!("enable a backend, the `openssl` or the `rustcrypto` feature");
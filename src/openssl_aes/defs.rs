@@ -1,8 +1,21 @@
-use crate::openssl_aes::errors;
+use crate::encryption_scheme::SecurityLevel;
+use crate::openssl_aes::{errors, etm};
+#[cfg(feature = "openssl")]
 use openssl::symm::Cipher;
+use std::fmt;
 use std::slice::Iter;
+use std::str::FromStr;
 
-#[derive(Clone, Copy, PartialEq)]
+// The variants are named after OpenSSL's ciphers, but they are shared by
+// every backend (see rustcrypto_aes/), so that a box sealed by one backend
+// opens with any other.
+
+// the fixed IVs of the unauthenticated modes, AEAD modes use a random nonce,
+// encrypt-then-MAC modes a random IV per box and SIV modes a synthetic IV
+const _IV12: &[u8] = b"\x00\x01\x02\x03\x04\x05\x06\x07\x00\x01\x02\x03";
+const _IV16: &[u8] = b"\x00\x01\x02\x03\x04\x05\x06\x07\x00\x01\x02\x03\x04\x05\x06\x07";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum OpenSslVariants {
     //AES128
     Aes128Ecb,
@@ -19,11 +32,30 @@ pub enum OpenSslVariants {
     Aes256Cbc,
     Aes256Ctr,
     Aes256Ofb,
+    //AEAD
+    Aes128Gcm,
+    Aes192Gcm,
+    Aes256Gcm,
+    ChaCha20Poly1305,
+    //ENCRYPT-THEN-MAC (see etm.rs)
+    Aes128CbcHmacSha256,
+    Aes128CtrHmacSha256,
+    Aes128OfbHmacSha256,
+    Aes192CbcHmacSha256,
+    Aes192CtrHmacSha256,
+    Aes192OfbHmacSha256,
+    Aes256CbcHmacSha256,
+    Aes256CtrHmacSha256,
+    Aes256OfbHmacSha256,
+    //DETERMINISTIC AEAD (see siv.rs)
+    Aes128Siv,
+    Aes192Siv,
+    Aes256Siv,
 }
 
 impl OpenSslVariants {
     pub fn iterator() -> Iter<'static, OpenSslVariants> {
-        static VARIANTS: [OpenSslVariants; 12] = [
+        static VARIANTS: [OpenSslVariants; 28] = [
             //AES128
             OpenSslVariants::Aes128Ecb,
             OpenSslVariants::Aes128Cbc,
@@ -39,11 +71,203 @@ impl OpenSslVariants {
             OpenSslVariants::Aes256Cbc,
             OpenSslVariants::Aes256Ctr,
             OpenSslVariants::Aes256Ofb,
+            //AEAD
+            OpenSslVariants::Aes128Gcm,
+            OpenSslVariants::Aes192Gcm,
+            OpenSslVariants::Aes256Gcm,
+            OpenSslVariants::ChaCha20Poly1305,
+            //ENCRYPT-THEN-MAC
+            OpenSslVariants::Aes128CbcHmacSha256,
+            OpenSslVariants::Aes128CtrHmacSha256,
+            OpenSslVariants::Aes128OfbHmacSha256,
+            OpenSslVariants::Aes192CbcHmacSha256,
+            OpenSslVariants::Aes192CtrHmacSha256,
+            OpenSslVariants::Aes192OfbHmacSha256,
+            OpenSslVariants::Aes256CbcHmacSha256,
+            OpenSslVariants::Aes256CtrHmacSha256,
+            OpenSslVariants::Aes256OfbHmacSha256,
+            //DETERMINISTIC AEAD
+            OpenSslVariants::Aes128Siv,
+            OpenSslVariants::Aes192Siv,
+            OpenSslVariants::Aes256Siv,
         ];
         VARIANTS.iter()
     }
+
+    /// the name of this variant (e.g. "aes-256-gcm") as accepted by `FromStr`
+    pub fn name(self) -> &'static str {
+        match self {
+            //AES128
+            OpenSslVariants::Aes128Ecb => "aes-128-ecb",
+            OpenSslVariants::Aes128Cbc => "aes-128-cbc",
+            OpenSslVariants::Aes128Ctr => "aes-128-ctr",
+            OpenSslVariants::Aes128Ofb => "aes-128-ofb",
+            //AES192
+            OpenSslVariants::Aes192Ecb => "aes-192-ecb",
+            OpenSslVariants::Aes192Cbc => "aes-192-cbc",
+            OpenSslVariants::Aes192Ctr => "aes-192-ctr",
+            OpenSslVariants::Aes192Ofb => "aes-192-ofb",
+            //AES256
+            OpenSslVariants::Aes256Ecb => "aes-256-ecb",
+            OpenSslVariants::Aes256Cbc => "aes-256-cbc",
+            OpenSslVariants::Aes256Ctr => "aes-256-ctr",
+            OpenSslVariants::Aes256Ofb => "aes-256-ofb",
+            //AEAD
+            OpenSslVariants::Aes128Gcm => "aes-128-gcm",
+            OpenSslVariants::Aes192Gcm => "aes-192-gcm",
+            OpenSslVariants::Aes256Gcm => "aes-256-gcm",
+            OpenSslVariants::ChaCha20Poly1305 => "chacha20-poly1305",
+            //ENCRYPT-THEN-MAC
+            OpenSslVariants::Aes128CbcHmacSha256 => "aes-128-cbc-hmac-sha256",
+            OpenSslVariants::Aes128CtrHmacSha256 => "aes-128-ctr-hmac-sha256",
+            OpenSslVariants::Aes128OfbHmacSha256 => "aes-128-ofb-hmac-sha256",
+            OpenSslVariants::Aes192CbcHmacSha256 => "aes-192-cbc-hmac-sha256",
+            OpenSslVariants::Aes192CtrHmacSha256 => "aes-192-ctr-hmac-sha256",
+            OpenSslVariants::Aes192OfbHmacSha256 => "aes-192-ofb-hmac-sha256",
+            OpenSslVariants::Aes256CbcHmacSha256 => "aes-256-cbc-hmac-sha256",
+            OpenSslVariants::Aes256CtrHmacSha256 => "aes-256-ctr-hmac-sha256",
+            OpenSslVariants::Aes256OfbHmacSha256 => "aes-256-ofb-hmac-sha256",
+            //DETERMINISTIC AEAD
+            OpenSslVariants::Aes128Siv => "aes-128-siv",
+            OpenSslVariants::Aes192Siv => "aes-192-siv",
+            OpenSslVariants::Aes256Siv => "aes-256-siv",
+        }
+    }
+
+    /// the unauthenticated variant an encrypt-then-MAC variant encrypts with
+    pub fn etm_base(self) -> Option<OpenSslVariants> {
+        match self {
+            OpenSslVariants::Aes128CbcHmacSha256 => Some(OpenSslVariants::Aes128Cbc),
+            OpenSslVariants::Aes128CtrHmacSha256 => Some(OpenSslVariants::Aes128Ctr),
+            OpenSslVariants::Aes128OfbHmacSha256 => Some(OpenSslVariants::Aes128Ofb),
+            OpenSslVariants::Aes192CbcHmacSha256 => Some(OpenSslVariants::Aes192Cbc),
+            OpenSslVariants::Aes192CtrHmacSha256 => Some(OpenSslVariants::Aes192Ctr),
+            OpenSslVariants::Aes192OfbHmacSha256 => Some(OpenSslVariants::Aes192Ofb),
+            OpenSslVariants::Aes256CbcHmacSha256 => Some(OpenSslVariants::Aes256Cbc),
+            OpenSslVariants::Aes256CtrHmacSha256 => Some(OpenSslVariants::Aes256Ctr),
+            OpenSslVariants::Aes256OfbHmacSha256 => Some(OpenSslVariants::Aes256Ofb),
+            _ => None,
+        }
+    }
+
+    /// the CTR variant a SIV variant encrypts with
+    pub fn siv_base(self) -> Option<OpenSslVariants> {
+        match self {
+            OpenSslVariants::Aes128Siv => Some(OpenSslVariants::Aes128Ctr),
+            OpenSslVariants::Aes192Siv => Some(OpenSslVariants::Aes192Ctr),
+            OpenSslVariants::Aes256Siv => Some(OpenSslVariants::Aes256Ctr),
+            _ => None,
+        }
+    }
+
+    /// key length in bytes, the MAC key included
+    pub fn key_length(self) -> usize {
+        if let Some(base) = self.etm_base() {
+            return etm::MAC_KEY_LEN + base.key_length();
+        }
+        if let Some(base) = self.siv_base() {
+            // the CMAC key is as long as the CTR key
+            return 2 * base.key_length();
+        }
+        match self {
+            OpenSslVariants::Aes128Ecb
+            | OpenSslVariants::Aes128Cbc
+            | OpenSslVariants::Aes128Ctr
+            | OpenSslVariants::Aes128Ofb
+            | OpenSslVariants::Aes128Gcm => 16,
+            OpenSslVariants::Aes192Ecb
+            | OpenSslVariants::Aes192Cbc
+            | OpenSslVariants::Aes192Ctr
+            | OpenSslVariants::Aes192Ofb
+            | OpenSslVariants::Aes192Gcm => 24,
+            _ => 32,
+        }
+    }
+
+    /// IV (or nonce) length in bytes, if the mode uses one
+    pub fn iv_length(self) -> Option<usize> {
+        match self {
+            OpenSslVariants::Aes128Ecb
+            | OpenSslVariants::Aes192Ecb
+            | OpenSslVariants::Aes256Ecb => None,
+            v if v.is_aead() => Some(12),
+            _ => Some(16),
+        }
+    }
+
+    // the IV the non-AEAD modes use.
+    // currently we only support 12-byte or 16-byte IVs
+    pub(crate) fn fixed_iv(self) -> Option<&'static [u8]> {
+        if self.etm_base().is_some() || self.siv_base().is_some() {
+            return None;
+        }
+        match self.iv_length() {
+            Some(12) => Some(_IV12),
+            Some(16) => Some(_IV16),
+            _ => None,
+        }
+    }
+
+    /// whether the mode authenticates the ciphertext (AEAD, encrypt-then-MAC or SIV)
+    pub fn is_authenticated(self) -> bool {
+        self.is_aead() || self.etm_base().is_some() || self.siv_base().is_some()
+    }
+
+    /// whether equal plaintexts encrypt to equal ciphertexts under the same key:
+    /// SIV modes by design, ECB and the modes with a fixed IV as a weakness
+    pub fn is_deterministic(self) -> bool {
+        !self.is_aead() && self.etm_base().is_none()
+    }
+
+    /// whether the mode is an AEAD mode (with a nonce and a tag of its own)
+    pub fn is_aead(self) -> bool {
+        matches!(
+            self,
+            OpenSslVariants::Aes128Gcm
+                | OpenSslVariants::Aes192Gcm
+                | OpenSslVariants::Aes256Gcm
+                | OpenSslVariants::ChaCha20Poly1305
+        )
+    }
+
+    /// ECB (equal blocks encrypt equally) is insecure, and so are CTR and OFB:
+    /// with their fixed IV, every message under a key is xored with the same
+    /// keystream. CBC with its fixed IV is unauthenticated (and deterministic)
+    pub fn security_level(self) -> SecurityLevel {
+        match self {
+            OpenSslVariants::Aes128Ecb
+            | OpenSslVariants::Aes192Ecb
+            | OpenSslVariants::Aes256Ecb
+            | OpenSslVariants::Aes128Ctr
+            | OpenSslVariants::Aes192Ctr
+            | OpenSslVariants::Aes256Ctr
+            | OpenSslVariants::Aes128Ofb
+            | OpenSslVariants::Aes192Ofb
+            | OpenSslVariants::Aes256Ofb => SecurityLevel::Insecure,
+            v if v.is_authenticated() => SecurityLevel::Authenticated,
+            _ => SecurityLevel::Unauthenticated,
+        }
+    }
+}
+
+impl fmt::Display for OpenSslVariants {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
 }
 
+impl FromStr for OpenSslVariants {
+    type Err = errors::Error;
+
+    fn from_str(s: &str) -> errors::Result<OpenSslVariants> {
+        OpenSslVariants::iterator()
+            .find(|v| v.name().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| errors::Error::unsupported(format!("unknown scheme name '{}'", s)))
+    }
+}
+
+#[cfg(feature = "openssl")]
 pub fn openssl_enum_to_cipher(e: &OpenSslVariants) -> Cipher {
     match e {
         //AES128
@@ -61,10 +285,31 @@ pub fn openssl_enum_to_cipher(e: &OpenSslVariants) -> Cipher {
         OpenSslVariants::Aes256Cbc => Cipher::aes_256_cbc(),
         OpenSslVariants::Aes256Ctr => Cipher::aes_256_ctr(),
         OpenSslVariants::Aes256Ofb => Cipher::aes_256_ofb(),
+        //AEAD
+        OpenSslVariants::Aes128Gcm => Cipher::aes_128_gcm(),
+        OpenSslVariants::Aes192Gcm => Cipher::aes_192_gcm(),
+        OpenSslVariants::Aes256Gcm => Cipher::aes_256_gcm(),
+        OpenSslVariants::ChaCha20Poly1305 => Cipher::chacha20_poly1305(),
+        //ENCRYPT-THEN-MAC, the cipher of the base variant
+        OpenSslVariants::Aes128CbcHmacSha256 => Cipher::aes_128_cbc(),
+        OpenSslVariants::Aes128CtrHmacSha256 => Cipher::aes_128_ctr(),
+        OpenSslVariants::Aes128OfbHmacSha256 => Cipher::aes_128_ofb(),
+        OpenSslVariants::Aes192CbcHmacSha256 => Cipher::aes_192_cbc(),
+        OpenSslVariants::Aes192CtrHmacSha256 => Cipher::aes_192_ctr(),
+        OpenSslVariants::Aes192OfbHmacSha256 => Cipher::aes_192_ofb(),
+        OpenSslVariants::Aes256CbcHmacSha256 => Cipher::aes_256_cbc(),
+        OpenSslVariants::Aes256CtrHmacSha256 => Cipher::aes_256_ctr(),
+        OpenSslVariants::Aes256OfbHmacSha256 => Cipher::aes_256_ofb(),
+        //DETERMINISTIC AEAD, the cipher of the CTR variant
+        OpenSslVariants::Aes128Siv => Cipher::aes_128_ctr(),
+        OpenSslVariants::Aes192Siv => Cipher::aes_192_ctr(),
+        OpenSslVariants::Aes256Siv => Cipher::aes_256_ctr(),
     }
 }
 
-pub fn openssl_index_to_enum<'a>(index: u32) -> Result<OpenSslVariants, errors::Error> {
+/// deprecated: numeric indices are kept for backwards compatibility
+/// with older command lines, use the scheme names instead (see `FromStr`)
+pub fn openssl_index_to_enum(index: u32) -> Result<OpenSslVariants, errors::Error> {
     match index {
         //AES128
         0 => Ok(OpenSslVariants::Aes128Ecb),
@@ -86,3 +331,133 @@ pub fn openssl_index_to_enum<'a>(index: u32) -> Result<OpenSslVariants, errors::
         )),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::OpenSslVariants;
+    use crate::encryption_scheme::SecurityLevel;
+    use crate::openssl_aes::errors::Result;
+
+    #[test]
+    fn name_round_trip() -> Result<()> {
+        for variant in OpenSslVariants::iterator() {
+            assert_eq!(variant.to_string().parse::<OpenSslVariants>()?, *variant);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn names_are_case_insensitive() -> Result<()> {
+        assert_eq!(
+            "AES-256-GCM".parse::<OpenSslVariants>()?,
+            OpenSslVariants::Aes256Gcm
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_name_is_unsupported() {
+        assert!("aes-512-gcm".parse::<OpenSslVariants>().is_err());
+    }
+
+    #[test]
+    fn only_aead_modes_are_aead() {
+        let aead: Vec<_> = OpenSslVariants::iterator()
+            .filter(|v| v.is_aead())
+            .map(|v| v.name())
+            .collect();
+        assert_eq!(
+            aead,
+            [
+                "aes-128-gcm",
+                "aes-192-gcm",
+                "aes-256-gcm",
+                "chacha20-poly1305"
+            ]
+        );
+    }
+
+    #[test]
+    fn aead_etm_and_siv_modes_are_authenticated() {
+        for variant in OpenSslVariants::iterator() {
+            assert_eq!(
+                variant.is_authenticated(),
+                variant.is_aead()
+                    || variant.name().ends_with("-hmac-sha256")
+                    || variant.name().ends_with("-siv"),
+                "{}",
+                variant
+            );
+        }
+    }
+
+    #[test]
+    fn etm_modes_extend_their_base() {
+        for variant in OpenSslVariants::iterator() {
+            if let Some(base) = variant.etm_base() {
+                assert!(variant.name().starts_with(base.name()));
+                assert_eq!(variant.key_length(), 32 + base.key_length());
+                assert_eq!(variant.iv_length(), Some(16));
+                assert_eq!(variant.fixed_iv(), None);
+                assert_eq!(base.etm_base(), None);
+            }
+        }
+    }
+
+    #[test]
+    fn siv_modes_double_their_base() {
+        for variant in OpenSslVariants::iterator() {
+            if let Some(base) = variant.siv_base() {
+                assert_eq!(variant.name().replace("-siv", "-ctr"), base.name());
+                assert_eq!(variant.key_length(), 2 * base.key_length());
+                assert_eq!(variant.fixed_iv(), None);
+                assert!(variant.is_deterministic());
+            }
+        }
+    }
+
+    #[test]
+    fn only_randomized_modes_are_not_deterministic() {
+        let randomized: Vec<_> = OpenSslVariants::iterator()
+            .filter(|v| !v.is_deterministic())
+            .copied()
+            .collect();
+        for variant in OpenSslVariants::iterator() {
+            let random_iv = variant.is_aead() || variant.etm_base().is_some();
+            assert_eq!(randomized.contains(variant), random_iv, "{}", variant);
+        }
+    }
+
+    #[cfg(feature = "openssl")]
+    #[test]
+    fn lengths_match_openssl() {
+        for variant in OpenSslVariants::iterator() {
+            let cipher = super::openssl_enum_to_cipher(variant);
+            let mac_key_len = match (variant.etm_base(), variant.siv_base()) {
+                (Some(_), _) => 32,
+                (_, Some(_)) => cipher.key_len(),
+                _ => 0,
+            };
+            assert_eq!(variant.key_length(), mac_key_len + cipher.key_len());
+            assert_eq!(variant.iv_length(), cipher.iv_len());
+        }
+    }
+
+    #[test]
+    fn ecb_and_fixed_iv_stream_modes_are_insecure() {
+        for variant in OpenSslVariants::iterator() {
+            let name = variant.name();
+            assert_eq!(
+                variant.security_level() == SecurityLevel::Insecure,
+                name.ends_with("-ecb") || name.ends_with("-ctr") || name.ends_with("-ofb"),
+                "{}",
+                name
+            );
+            // the fixed IV of CBC makes it deterministic too
+            if name.ends_with("-cbc") {
+                assert_eq!(variant.security_level(), SecurityLevel::Unauthenticated);
+                assert!(variant.is_deterministic(), "{}", name);
+            }
+        }
+    }
+}
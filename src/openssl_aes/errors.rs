@@ -88,7 +88,7 @@ pub enum ErrorKind {
 
 impl fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match &*self {
+        match self {
             ErrorKind::KeyLengthError { expected, got } => write!(
                 f,
                 "key length mismatch: expected {} \
@@ -0,0 +1,121 @@
+use crate::openssl_aes::errors::{Error, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+// The encrypt-then-MAC variants (e.g. "aes-256-cbc-hmac-sha256") encrypt with
+// an unauthenticated variant and append an HMAC-SHA256 tag, after RFC 7518,
+// section 5.2.2:
+//
+//   key        mac key (32 bytes) || encryption key
+//   tag        HMAC-SHA256(mac key, aad || iv || ciphertext || aad bits as u64)
+//   output     iv || ciphertext || tag
+//
+// with a random 16-byte IV per box, and the full 32-byte tag. The tag is
+// checked in constant time before anything is decrypted, and every failure
+// after that (e.g. bad CBC padding) is the same opaque error as a bad tag, so
+// the errors cannot serve as a padding oracle.
+
+/// the length of the MAC key, which precedes the encryption key
+pub const MAC_KEY_LEN: usize = 32;
+
+/// the length of the tag appended to the ciphertext
+pub const TAG_LEN: usize = 32;
+
+/// the length of the random IV prepended to the ciphertext
+pub const IV_LEN: usize = 16;
+
+/// the one error every failed decryption returns
+pub(crate) fn failed() -> Error {
+    Error::decryption("decryption failed")
+}
+
+/// encrypt with encrypt, called with the encryption key, and append the tag
+/// over aad, iv and its ciphertext. key must have been length checked
+pub(crate) fn seal<F>(key: &[u8], iv: &[u8], aad: &[u8], encrypt: F) -> Result<Vec<u8>>
+where
+    F: FnOnce(&[u8]) -> Result<Vec<u8>>,
+{
+    let (mac_key, enc_key) = key.split_at(MAC_KEY_LEN);
+    let mut out = encrypt(enc_key)?;
+    let tag = mac(mac_key, aad, iv, &out)?.result().code();
+    out.extend_from_slice(&tag);
+    Ok(out)
+}
+
+/// check the tag of ctext, then decrypt it with decrypt, called with the
+/// encryption key and the ciphertext without the tag
+pub(crate) fn open<F>(
+    key: &[u8],
+    iv: &[u8],
+    aad: &[u8],
+    ctext: &[u8],
+    decrypt: F,
+) -> Result<Vec<u8>>
+where
+    F: FnOnce(&[u8], &[u8]) -> Result<Vec<u8>>,
+{
+    if ctext.len() < TAG_LEN {
+        return Err(failed());
+    }
+    let (enc, tag) = ctext.split_at(ctext.len() - TAG_LEN);
+    let (mac_key, enc_key) = key.split_at(MAC_KEY_LEN);
+    // verify compares in constant time
+    mac(mac_key, aad, iv, enc)?
+        .verify(tag)
+        .map_err(|_| failed())?;
+    decrypt(enc_key, enc).map_err(|_| failed())
+}
+
+fn mac(mac_key: &[u8], aad: &[u8], iv: &[u8], enc: &[u8]) -> Result<Hmac<Sha256>> {
+    let mut mac = Hmac::<Sha256>::new_varkey(mac_key)
+        .map_err(|_| Error::keylen(MAC_KEY_LEN, mac_key.len()))?;
+    mac.input(aad);
+    mac.input(iv);
+    mac.input(enc);
+    mac.input(&(aad.len() as u64 * 8).to_be_bytes());
+    Ok(mac)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 48] = [7; 48];
+    const IV: [u8; 16] = [1; 16];
+
+    fn identity(_: &[u8], enc: &[u8]) -> Result<Vec<u8>> {
+        Ok(enc.to_vec())
+    }
+
+    #[test]
+    fn open_checks_the_tag() -> Result<()> {
+        let sealed = seal(&KEY, &IV, b"aad", |_| Ok(b"ctext".to_vec()))?;
+        assert_eq!(sealed.len(), 5 + TAG_LEN);
+        assert_eq!(open(&KEY, &IV, b"aad", &sealed, identity)?, b"ctext");
+        for i in 0..sealed.len() {
+            let mut forged = sealed.clone();
+            forged[i] ^= 0x80;
+            assert!(open(&KEY, &IV, b"aad", &forged, identity).is_err());
+        }
+        assert!(open(&KEY, &[2; 16], b"aad", &sealed, identity).is_err());
+        assert!(open(&KEY, &IV, b"", &sealed, identity).is_err());
+        assert!(open(&KEY, &IV, b"aad", &sealed[..TAG_LEN - 1], identity).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn failures_are_opaque() -> Result<()> {
+        let sealed = seal(&KEY, &IV, &[], |_| Ok(b"ctext".to_vec()))?;
+        let bad_padding = open(&KEY, &IV, &[], &sealed, |_, _| {
+            Err(Error::decryption("bad decrypt: wrong final block length"))
+        });
+        let mut forged = sealed.clone();
+        forged[0] ^= 1;
+        let bad_tag = open(&KEY, &IV, &[], &forged, identity);
+        let short = open(&KEY, &IV, &[], &sealed[..3], identity);
+        for result in &[bad_padding, bad_tag, short] {
+            assert_eq!(result.as_ref().unwrap_err().kind(), failed().kind());
+        }
+        Ok(())
+    }
+}
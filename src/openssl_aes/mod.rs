@@ -1,3 +1,6 @@
 pub mod defs;
 pub mod errors;
+pub mod etm;
+pub mod siv;
+#[cfg(feature = "openssl")]
 pub mod wrapper;
@@ -0,0 +1,202 @@
+use crate::openssl_aes::errors::Result;
+use crate::openssl_aes::etm::failed;
+
+// The deterministic variants (e.g. "aes-256-siv") are AES-SIV (RFC 5297), a
+// deterministic AEAD:
+//
+//   key        mac key || encryption key, AES keys of the same length
+//   iv         V = S2V(mac key, aad, plaintext), built from AES-CMAC
+//   output     V || AES-CTR(encryption key, V with bits 63 and 31 cleared, plaintext)
+//
+// There is no nonce, so equal plaintexts (with equal associated data) under the
+// same key encrypt to equal ciphertexts. That is the point: they can be compared
+// without the key. Nothing else leaks, unlike the fixed IVs of the unauthenticated
+// modes, where equal prefixes show too. Version 2 boxes pass their header as the
+// associated data (see header.rs), so V = S2V(mac key, header, plaintext), and
+// equal fields only encrypt equally in boxes with equal headers. Empty
+// associated data is not a component of S2V, so for version 1 boxes (which
+// have none) V = S2V(mac key, plaintext). V is checked in constant time after
+// decrypting, and every failure is the same opaque error as the
+// encrypt-then-MAC variants' (see etm.rs).
+
+/// the length of the synthetic IV prepended to the ciphertext
+pub const IV_LEN: usize = 16;
+
+type Block = [u8; 16];
+
+/// encrypt msg. cmac is called with the mac key and the parts of a message to
+/// authenticate, ctr with the encryption key, the counter block and msg.
+/// key must have been length checked
+pub(crate) fn seal<M, E>(key: &[u8], aad: &[u8], msg: &[u8], cmac: M, ctr: E) -> Result<Vec<u8>>
+where
+    M: Fn(&[u8], &[&[u8]]) -> Result<Block>,
+    E: FnOnce(&[u8], &[u8], &[u8]) -> Result<Vec<u8>>,
+{
+    let (mac_key, enc_key) = key.split_at(key.len() / 2);
+    let iv = s2v(|parts| cmac(mac_key, parts), aad, msg)?;
+    let enc = ctr(enc_key, &counter(&iv), msg)?;
+    let mut out = Vec::with_capacity(IV_LEN + enc.len());
+    out.extend_from_slice(&iv);
+    out.extend(enc);
+    Ok(out)
+}
+
+/// decrypt ctext (see `seal`), then check its synthetic IV
+pub(crate) fn open<M, E>(key: &[u8], aad: &[u8], ctext: &[u8], cmac: M, ctr: E) -> Result<Vec<u8>>
+where
+    M: Fn(&[u8], &[&[u8]]) -> Result<Block>,
+    E: FnOnce(&[u8], &[u8], &[u8]) -> Result<Vec<u8>>,
+{
+    if ctext.len() < IV_LEN {
+        return Err(failed());
+    }
+    let (mac_key, enc_key) = key.split_at(key.len() / 2);
+    let (iv, enc) = ctext.split_at(IV_LEN);
+    let msg = ctr(enc_key, &counter(iv), enc).map_err(|_| failed())?;
+    let expected = s2v(|parts| cmac(mac_key, parts), aad, &msg).map_err(|_| failed())?;
+    if !equal(&expected, iv) {
+        return Err(failed());
+    }
+    Ok(msg)
+}
+
+// S2V (RFC 5297, section 2.4) of aad, unless it is empty, and msg
+fn s2v<M>(cmac: M, aad: &[u8], msg: &[u8]) -> Result<Block>
+where
+    M: Fn(&[&[u8]]) -> Result<Block>,
+{
+    let mut d = cmac(&[&[0u8; 16]])?;
+    if !aad.is_empty() {
+        d = xor(&dbl(&d), &cmac(&[aad])?);
+    }
+    if msg.len() >= 16 {
+        // xor d onto the last block of msg
+        let (head, tail) = msg.split_at(msg.len() - 16);
+        let mut last = [0u8; 16];
+        last.copy_from_slice(tail);
+        cmac(&[head, &xor(&last, &d)])
+    } else {
+        let mut padded = [0u8; 16];
+        padded[..msg.len()].copy_from_slice(msg);
+        padded[msg.len()] = 0x80;
+        cmac(&[&xor(&dbl(&d), &padded)])
+    }
+}
+
+// multiplication by x in GF(2^128)
+fn dbl(block: &Block) -> Block {
+    let mut out = [0u8; 16];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = block[i] << 1 | block.get(i + 1).map_or(0, |next| next >> 7);
+    }
+    if block[0] & 0x80 != 0 {
+        out[15] ^= 0x87;
+    }
+    out
+}
+
+fn xor(a: &Block, b: &Block) -> Block {
+    let mut out = *a;
+    out.iter_mut().zip(b.iter()).for_each(|(x, y)| *x ^= y);
+    out
+}
+
+// the first CTR block: V with two bits cleared, so that implementations with
+// 32- or 64-bit counters need not handle a carry
+fn counter(iv: &[u8]) -> Block {
+    let mut block = [0u8; 16];
+    block.copy_from_slice(iv);
+    block[8] &= 0x7f;
+    block[12] &= 0x7f;
+    block
+}
+
+// compares every byte, so the time taken does not tell where a difference is
+fn equal(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openssl_aes::errors::Error;
+    use sha2::{Digest, Sha256};
+
+    const KEY: [u8; 32] = [7; 32];
+
+    // stand-ins for AES-CMAC and AES-CTR, the real ones are checked against
+    // the RFC's vectors (see test_vectors.rs)
+    fn cmac(key: &[u8], parts: &[&[u8]]) -> Result<Block> {
+        let mut hasher = Sha256::new();
+        hasher.input(key);
+        parts.iter().for_each(|part| hasher.input(part));
+        let mut block = [0u8; 16];
+        block.copy_from_slice(&hasher.result()[..16]);
+        Ok(block)
+    }
+
+    fn ctr(key: &[u8], counter: &[u8], msg: &[u8]) -> Result<Vec<u8>> {
+        let pad = cmac(key, &[counter])?;
+        Ok(msg
+            .iter()
+            .zip(pad.iter().cycle())
+            .map(|(m, p)| m ^ p)
+            .collect())
+    }
+
+    #[test]
+    fn dbl_known_answer() {
+        // RFC 5297, A.1: dbl(CMAC(K, <zero>))
+        let mut d = [0u8; 16];
+        d.copy_from_slice(&hex::decode("0e04dfafc1efbf040140582859bf073a").unwrap());
+        assert_eq!(hex::encode(dbl(&d)), "1c09bf5f83df7e080280b050b37e0e74");
+        // the reduction, when the top bit is set
+        let mut top = [0u8; 16];
+        top[0] = 0x80;
+        let mut reduced = [0u8; 16];
+        reduced[15] = 0x87;
+        assert_eq!(dbl(&top), reduced);
+    }
+
+    #[test]
+    fn deterministic_and_checked() -> Result<()> {
+        for msg in &[
+            &b""[..],
+            b"short",
+            b"exactly 16 bytes",
+            b"longer than a single block",
+        ] {
+            let sealed = seal(&KEY, b"aad", msg, cmac, ctr)?;
+            assert_eq!(sealed.len(), IV_LEN + msg.len());
+            assert_eq!(seal(&KEY, b"aad", msg, cmac, ctr)?, sealed);
+            assert_eq!(open(&KEY, b"aad", &sealed, cmac, ctr)?, *msg);
+            for i in 0..sealed.len() {
+                let mut forged = sealed.clone();
+                forged[i] ^= 0x80;
+                assert!(open(&KEY, b"aad", &forged, cmac, ctr).is_err());
+            }
+            assert!(open(&KEY, b"", &sealed, cmac, ctr).is_err());
+        }
+        assert_ne!(
+            seal(&KEY, b"", b"one message", cmac, ctr)?,
+            seal(&KEY, b"", b"another message", cmac, ctr)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn failures_are_opaque() -> Result<()> {
+        let sealed = seal(&KEY, &[], b"message", cmac, ctr)?;
+        let mut forged = sealed.clone();
+        forged[0] ^= 1;
+        let bad_iv = open(&KEY, &[], &forged, cmac, ctr);
+        let short = open(&KEY, &[], &sealed[..IV_LEN - 1], cmac, ctr);
+        let broken = open(&KEY, &[], &sealed, cmac, |_, _, _| {
+            Err(Error::decryption("invalid key"))
+        });
+        for result in &[bad_iv, short, broken] {
+            assert_eq!(result.as_ref().unwrap_err().kind(), failed().kind());
+        }
+        Ok(())
+    }
+}
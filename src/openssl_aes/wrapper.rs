@@ -1,29 +1,34 @@
-pub use crate::encryption_scheme::EncryptionScheme;
+pub use crate::encryption_scheme::{EncryptionScheme, SecurityLevel};
 pub use crate::openssl_aes::{
     defs, defs::OpenSslVariants, errors::Error, errors::ErrorKind, errors::Result,
 };
-use openssl::symm::{decrypt, encrypt, Cipher};
+use crate::openssl_aes::{etm, siv};
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use openssl::symm::{decrypt, decrypt_aead, Cipher, Crypter, Mode};
+use rand::RngCore;
 
-const _IV12: &'static [u8] = b"\x00\x01\x02\x03\x04\x05\x06\x07\x00\x01\x02\x03";
-const _IV16: &'static [u8] = b"\x00\x01\x02\x03\x04\x05\x06\x07\x00\x01\x02\x03\x04\x05\x06\x07";
+// all supported AEAD modes use 96-bit nonces and 128-bit tags
+const AEAD_NONCE_LEN: usize = 12;
+const AEAD_TAG_LEN: usize = 16;
 
 #[derive(Copy, Clone, PartialEq)]
 pub struct OpensslAesWrapper {
     cipher: Cipher,
+    variant: OpenSslVariants,
 }
 
 impl OpensslAesWrapper {
+    pub fn get_variant(&self) -> OpenSslVariants {
+        self.variant
+    }
+
     pub fn get_iv_length(&self) -> Option<usize> {
-        self.cipher.iv_len()
+        self.variant.iv_length()
     }
 
-    // currently we only support 12-byte or 16-byte IVs
     fn get_iv(&self) -> Option<&[u8]> {
-        match self.get_iv_length() {
-            Some(12) => Some(&_IV12[..]),
-            Some(16) => Some(&_IV16[..]),
-            _ => None,
-        }
+        self.variant.fixed_iv()
     }
 
     /// check that key length matches the required key length
@@ -34,6 +39,144 @@ impl OpensslAesWrapper {
         };
         Ok(())
     }
+
+    /// encrypt msg with the given IV (or nonce) and, for authenticated modes,
+    /// associated data. Unlike `encrypt` the IV is not part of the ciphertext,
+    /// while the tag of authenticated modes is appended to it. `encrypt` shares
+    /// its code path, which the known-answer tests check (see test_vectors.rs)
+    pub(crate) fn encrypt_with_iv(
+        &self,
+        key: &[u8],
+        iv: Option<&[u8]>,
+        aad: &[u8],
+        msg: &[u8],
+    ) -> Result<Vec<u8>> {
+        self.check_key_len(key.len())?;
+        if let Some(base) = self.variant.etm_base() {
+            let iv = iv.ok_or_else(|| Error::encryption("missing IV"))?;
+            return etm::seal(key, iv, aad, |enc_key| {
+                OpensslAesWrapper::new(&base).encrypt_with_iv(enc_key, Some(iv), &[], msg)
+            });
+        }
+        if let Some(base) = self.variant.siv_base() {
+            if iv.is_some() {
+                return Err(Error::encryption("SIV modes take no IV"));
+            }
+            return siv::seal(key, aad, msg, cmac, |enc_key, counter, msg| {
+                OpensslAesWrapper::new(&base).encrypt_with_iv(enc_key, Some(counter), &[], msg)
+            });
+        }
+        let mut out = Vec::new();
+        self.encrypt_with_iv_into(key, iv, aad, msg, &mut out)?;
+        Ok(out)
+    }
+
+    /// the inverse of `encrypt_with_iv`
+    pub(crate) fn decrypt_with_iv(
+        &self,
+        key: &[u8],
+        iv: Option<&[u8]>,
+        aad: &[u8],
+        ctext: &[u8],
+    ) -> Result<Vec<u8>> {
+        self.check_key_len(key.len())?;
+        if let Some(base) = self.variant.etm_base() {
+            let iv = iv.ok_or_else(etm::failed)?;
+            return etm::open(key, iv, aad, ctext, |enc_key, enc| {
+                OpensslAesWrapper::new(&base).decrypt_with_iv(enc_key, Some(iv), &[], enc)
+            });
+        }
+        if let Some(base) = self.variant.siv_base() {
+            if iv.is_some() {
+                return Err(etm::failed());
+            }
+            return siv::open(key, aad, ctext, cmac, |enc_key, counter, enc| {
+                OpensslAesWrapper::new(&base).decrypt_with_iv(enc_key, Some(counter), &[], enc)
+            });
+        }
+        if !self.variant.is_aead() {
+            return decrypt(self.cipher, key, iv, ctext).map_err(Error::decryption);
+        }
+        if ctext.len() < AEAD_TAG_LEN {
+            return Err(Error::decryption("ciphertext too short"));
+        }
+        let (enc, tag) = ctext.split_at(ctext.len() - AEAD_TAG_LEN);
+        decrypt_aead(self.cipher, key, iv, aad, enc, tag).map_err(Error::decryption)
+    }
+
+    fn encrypt_with_iv_into(
+        &self,
+        key: &[u8],
+        iv: Option<&[u8]>,
+        aad: &[u8],
+        msg: &[u8],
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
+        let begin = out.len();
+        let crypter = self.crypter_into(key, iv, aad, msg, out)?;
+        if !self.variant.is_aead() {
+            return Ok(());
+        }
+        out.resize(out.len() + AEAD_TAG_LEN, 0);
+        let start = out.len() - AEAD_TAG_LEN;
+        let tagged = crypter
+            .get_tag(&mut out[start..])
+            .map_err(Error::encryption);
+        if tagged.is_err() {
+            out.truncate(begin);
+        }
+        tagged
+    }
+
+    // run msg through the cipher, appending the ciphertext to out without
+    // intermediate buffers. The crypter is returned for the AEAD tag
+    fn crypter_into(
+        &self,
+        key: &[u8],
+        iv: Option<&[u8]>,
+        aad: &[u8],
+        msg: &[u8],
+        out: &mut Vec<u8>,
+    ) -> Result<Crypter> {
+        let mut crypter =
+            Crypter::new(self.cipher, Mode::Encrypt, key, iv).map_err(Error::encryption)?;
+        if self.variant.is_aead() {
+            crypter.aad_update(aad).map_err(Error::encryption)?;
+        }
+        let start = out.len();
+        // update and finalize may write up to a block more than they are given
+        out.resize(start + msg.len() + self.cipher.block_size(), 0);
+        let written = crypter
+            .update(msg, &mut out[start..])
+            .and_then(|count| Ok(count + crypter.finalize(&mut out[start + count..])?));
+        match written {
+            Ok(written) => {
+                out.truncate(start + written);
+                Ok(crypter)
+            }
+            Err(e) => {
+                out.truncate(start);
+                Err(Error::encryption(e))
+            }
+        }
+    }
+}
+
+// AES-CMAC (for SIV, see siv.rs) with the AES of key's length
+fn cmac(key: &[u8], parts: &[&[u8]]) -> Result<[u8; 16]> {
+    let cipher = match key.len() {
+        16 => Cipher::aes_128_cbc(),
+        24 => Cipher::aes_192_cbc(),
+        _ => Cipher::aes_256_cbc(),
+    };
+    let pkey = PKey::cmac(&cipher, key).map_err(Error::encryption)?;
+    let mut signer = Signer::new_without_digest(&pkey).map_err(Error::encryption)?;
+    for part in parts {
+        signer.update(part).map_err(Error::encryption)?;
+    }
+    let mut tag = [0u8; 16];
+    signer.sign(&mut tag).map_err(Error::encryption)?;
+    Ok(tag)
 }
 
 /// See encryption_scheme.rs
@@ -47,23 +190,99 @@ impl EncryptionScheme for OpensslAesWrapper {
     fn new(v: &OpenSslVariants) -> OpensslAesWrapper {
         OpensslAesWrapper {
             cipher: defs::openssl_enum_to_cipher(v),
+            variant: *v,
         }
     }
 
+    fn identifier(&self) -> &str {
+        self.variant.name()
+    }
+
     fn get_key_length(&self) -> usize {
-        self.cipher.key_len()
+        self.variant.key_length()
     }
+
+    fn security_level(&self) -> SecurityLevel {
+        self.variant.security_level()
+    }
+
+    fn is_deterministic(&self) -> bool {
+        self.variant.is_deterministic()
+    }
+
     fn encrypt(&self, key: &[u8], msg: &[u8]) -> Result<Vec<u8>> {
-        self.check_key_len(key.len())?;
-        let iv = self.get_iv();
-        let enc = encrypt(self.cipher, key, iv, &msg[..]).map_err(|e| Error::encryption(e))?;
-        Ok(enc)
+        let mut out = Vec::new();
+        self.encrypt_into(key, msg, &mut out)?;
+        Ok(out)
     }
+
+    fn encrypt_into(&self, key: &[u8], msg: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        self.encrypt_with_aad_into(key, &[], msg, out)
+    }
+
     fn decrypt(&self, key: &[u8], ctext: &[u8]) -> Result<Vec<u8>> {
+        self.decrypt_with_aad(key, &[], ctext)
+    }
+
+    // AEAD modes must never reuse a nonce under the same key, so unlike the other
+    // modes they get a fresh random nonce which is prepended to the ciphertext,
+    // and the tag is appended: nonce || ciphertext || tag.
+    // Encrypt-then-MAC modes likewise get a fresh random IV (see etm.rs), while
+    // SIV modes are deterministic on purpose (see siv.rs)
+    fn encrypt_with_aad_into(
+        &self,
+        key: &[u8],
+        aad: &[u8],
+        msg: &[u8],
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
         self.check_key_len(key.len())?;
-        let iv = self.get_iv();
-        let dec = decrypt(self.cipher, key, iv, &ctext[..]).map_err(|e| Error::decryption(e))?;
-        Ok(dec)
+        if self.variant.siv_base().is_some() {
+            out.extend(self.encrypt_with_iv(key, None, aad, msg)?);
+            return Ok(());
+        }
+        if self.variant.etm_base().is_some() {
+            let mut iv = [0u8; etm::IV_LEN];
+            rand::rngs::OsRng.fill_bytes(&mut iv);
+            let enc = self.encrypt_with_iv(key, Some(&iv), aad, msg)?;
+            out.reserve(iv.len() + enc.len());
+            out.extend_from_slice(&iv);
+            out.extend(enc);
+            return Ok(());
+        }
+        out.reserve(AEAD_NONCE_LEN + msg.len() + self.cipher.block_size() + AEAD_TAG_LEN);
+        if !self.variant.is_aead() {
+            return self.encrypt_with_iv_into(key, self.get_iv(), &[], msg, out);
+        }
+        let mut nonce = [0u8; AEAD_NONCE_LEN];
+        rand::rngs::OsRng.fill_bytes(&mut nonce);
+        let begin = out.len();
+        out.extend_from_slice(&nonce);
+        let sealed = self.encrypt_with_iv_into(key, Some(&nonce), aad, msg, out);
+        if sealed.is_err() {
+            out.truncate(begin);
+        }
+        sealed
+    }
+
+    fn decrypt_with_aad(&self, key: &[u8], aad: &[u8], ctext: &[u8]) -> Result<Vec<u8>> {
+        if self.variant.etm_base().is_some() {
+            self.check_key_len(key.len())?;
+            if ctext.len() < etm::IV_LEN {
+                return Err(etm::failed());
+            }
+            let (iv, rest) = ctext.split_at(etm::IV_LEN);
+            return self.decrypt_with_iv(key, Some(iv), aad, rest);
+        }
+        if !self.variant.is_aead() {
+            // SIV modes authenticate aad, the unauthenticated modes drop it
+            return self.decrypt_with_iv(key, self.get_iv(), aad, ctext);
+        }
+        if ctext.len() < AEAD_NONCE_LEN + AEAD_TAG_LEN {
+            return Err(Error::decryption("ciphertext too short"));
+        }
+        let (nonce, rest) = ctext.split_at(AEAD_NONCE_LEN);
+        self.decrypt_with_iv(key, Some(nonce), aad, rest)
     }
 }
 
@@ -72,16 +291,35 @@ mod tests {
     use super::OpensslAesWrapper;
     use crate::encryption_scheme::EncryptionScheme;
     use crate::kdf;
+    use crate::openssl_aes::etm;
     use crate::openssl_aes::{defs::OpenSslVariants, errors::Result};
 
     const MSG: [u8; 17] = [
         84, 104, 105, 115, 32, 105, 115, 32, 97, 32, 109, 101, 115, 115, 97, 103, 101,
     ];
-    const PASSWORD: &'static str = "password";
+    const PASSWORD: &str = "password";
+
+    const FIELDS: &[u8; 16] = b"Some Crypto Text";
+    const KEY: &[u8; 16] = b"\x00\x01\x02\x03\x04\x05\x06\x07\x08\x09\x0A\x0B\x0C\x0D\x0E\x0F";
+
+    #[test]
+    fn encrypt_with_vector() -> Result<()> {
+        let wrapper = OpensslAesWrapper::new(&OpenSslVariants::Aes128Cbc);
+        let enc = wrapper.encrypt(&KEY[..], &FIELDS[..])?;
+
+        // note: we use constant IVs tailored for this test case.
+        // If IVs change then so should this test (since it will fail).
+        assert_eq!(
+            b"\xB4\xB9\xE7\x30\xD6\xD6\xF7\xDE\x77\x3F\x1C\xFF\xB3\x3E\x44\x5A\x91\xD7\x27\x62\x87\x4D\
+              \xFB\x3C\x5E\xC4\x59\x72\x4A\xF4\x7C\xA1",
+            &enc[..]);
+        Ok(())
+    }
+
     #[test]
     fn encrypt_decrypt_all_schemes() -> Result<()> {
         for variant in OpenSslVariants::iterator() {
-            let wrapper = OpensslAesWrapper::new(&variant);
+            let wrapper = OpensslAesWrapper::new(variant);
             let key =
                 kdf::derive_key_from_password(&String::from(PASSWORD), wrapper.get_key_length());
             let enc = wrapper.encrypt(&key[..], &MSG)?;
@@ -113,10 +351,7 @@ mod tests {
         let key =
             kdf::derive_key_from_password(&String::from(PASSWORD), wrapper.get_key_length() - 1);
         let enc = wrapper.encrypt(&key[..], &MSG);
-        match enc {
-            Ok(_) => assert!(false),
-            Err(_) => assert!(true),
-        }
+        assert!(enc.is_err());
     }
 
     #[test]
@@ -125,10 +360,7 @@ mod tests {
         let key =
             kdf::derive_key_from_password(&String::from(PASSWORD), wrapper.get_key_length() + 1);
         let enc = wrapper.encrypt(&key[..], &MSG);
-        match enc {
-            Ok(_) => assert!(false),
-            Err(_) => assert!(true),
-        }
+        assert!(enc.is_err());
     }
     #[test]
     fn error_on_decrypt_key_length_too_short() -> Result<()> {
@@ -137,10 +369,7 @@ mod tests {
         let enc = wrapper.encrypt(&key[..], &MSG)?;
         let short_key_len = wrapper.get_key_length() - 1;
         let dec = wrapper.decrypt(&key[..short_key_len], &enc[..]);
-        match dec {
-            Ok(_) => assert!(false),
-            Err(_) => assert!(true),
-        }
+        assert!(dec.is_err());
         Ok(())
     }
 
@@ -152,10 +381,85 @@ mod tests {
         let enc = wrapper.encrypt(&key[..], &MSG)?;
         key.push(10);
         let dec = wrapper.decrypt(&key[..], &enc[..]);
-        match dec {
-            Ok(_) => assert!(false),
-            Err(_) => assert!(true),
+        assert!(dec.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn aead_uses_fresh_nonces() -> Result<()> {
+        let wrapper = OpensslAesWrapper::new(&OpenSslVariants::Aes256Gcm);
+        let key = kdf::derive_key_from_password(&String::from(PASSWORD), wrapper.get_key_length());
+        let enc1 = wrapper.encrypt(&key[..], &MSG)?;
+        let enc2 = wrapper.encrypt(&key[..], &MSG)?;
+        assert_ne!(enc1, enc2);
+        assert_eq!(enc1.len(), MSG.len() + 12 + 16);
+        Ok(())
+    }
+
+    #[test]
+    fn aead_detects_tampering() -> Result<()> {
+        for variant in OpenSslVariants::iterator().filter(|v| v.is_authenticated()) {
+            let wrapper = OpensslAesWrapper::new(variant);
+            let key =
+                kdf::derive_key_from_password(&String::from(PASSWORD), wrapper.get_key_length());
+            let mut enc = wrapper.encrypt(&key[..], &MSG)?;
+            enc[14] ^= 1;
+            assert!(wrapper.decrypt(&key[..], &enc[..]).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn aead_rejects_truncated_ciphertext() {
+        let wrapper = OpensslAesWrapper::new(&OpenSslVariants::ChaCha20Poly1305);
+        let key = kdf::derive_key_from_password(&String::from(PASSWORD), wrapper.get_key_length());
+        assert!(wrapper.decrypt(&key[..], &[0u8; 27]).is_err());
+    }
+
+    #[test]
+    fn encrypt_into_appends() -> Result<()> {
+        for variant in OpenSslVariants::iterator() {
+            let wrapper = OpensslAesWrapper::new(variant);
+            let key =
+                kdf::derive_key_from_password(&String::from(PASSWORD), wrapper.get_key_length());
+            let mut out = b"prefix".to_vec();
+            wrapper.encrypt_into(&key[..], &MSG[..], &mut out)?;
+            assert_eq!(&out[..6], b"prefix");
+            assert_eq!(wrapper.decrypt(&key[..], &out[6..])?, &MSG[..]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn encrypt_into_leaves_buffer_on_error() {
+        let wrapper = OpensslAesWrapper::new(&OpenSslVariants::Aes256Gcm);
+        let mut out = b"prefix".to_vec();
+        assert!(wrapper.encrypt_into(&KEY[..], &MSG[..], &mut out).is_err());
+        assert_eq!(out, b"prefix");
+    }
+
+    #[test]
+    fn etm_hides_padding_errors() -> Result<()> {
+        let wrapper = OpensslAesWrapper::new(&OpenSslVariants::Aes128CbcHmacSha256);
+        let key = kdf::derive_key_from_password(&String::from(PASSWORD), wrapper.get_key_length());
+        let iv = [0u8; 16];
+        // a block without valid padding, under a valid tag
+        let garbage = [0x55u8; 16];
+        let base = OpensslAesWrapper::new(&OpenSslVariants::Aes128Cbc);
+        let padding_error = base.decrypt_with_iv(&key[32..], Some(&iv), &[], &garbage);
+        assert!(padding_error.is_err());
+        let bad_padding = etm::seal(&key, &iv, &[], |_| Ok(garbage.to_vec()))?;
+        let mut bad_tag = wrapper.encrypt_with_iv(&key, Some(&iv), &[], &MSG)?;
+        bad_tag[0] ^= 1;
+        let errors = [
+            wrapper.decrypt_with_iv(&key, Some(&iv), &[], &bad_padding),
+            wrapper.decrypt_with_iv(&key, Some(&iv), &[], &bad_tag),
+            wrapper.decrypt(&key, &[0u8; 15]),
+        ];
+        for error in errors.iter() {
+            assert_eq!(error.as_ref().unwrap_err().kind(), etm::failed().kind());
         }
+        assert_ne!(padding_error.unwrap_err().kind(), etm::failed().kind());
         Ok(())
     }
 }
@@ -0,0 +1,158 @@
+use crate::errors::{Error, Result};
+use std::fmt;
+
+// A ciphertext is as long as its plaintext (plus a fixed overhead), so a box
+// of "yes" tells itself apart from a box of "no". Padding the framed fields
+// (see framing.rs) with zeros before encryption hides lengths within a size
+// class, at the cost of larger boxes:
+//
+//   bucket(n)      a multiple of n bytes: lengths hide among n of them
+//   power of two   the next power of two: at most 2x larger, reveals only
+//                  log2 of the length
+//   padme          Padmé (Nikitin et al., "Reducing Metadata Leakage from
+//                  Encrypted Files and Communication with PURBs"): at most
+//                  12% larger, reveals O(log log) bits of the length
+//
+// The padding is part of the plaintext, so authenticated schemes protect it.
+// The policy is recorded in the header (see header.rs), which they authenticate
+// as well in version 2 boxes, and the fields must be followed by exactly the
+// zeros it calls for.
+
+const PADDING_BUCKET: u8 = 1;
+const PADDING_POWER_OF_TWO: u8 = 2;
+const PADDING_PADME: u8 = 3;
+
+/// How the plaintext of a box is padded
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Padding {
+    /// no padding, the default
+    #[default]
+    None,
+    /// to a multiple of the given number of bytes
+    Bucket(u32),
+    /// to the next power of two
+    PowerOfTwo,
+    /// to the next Padmé length
+    Padme,
+}
+
+impl Padding {
+    /// the length a plaintext of len bytes is padded to
+    pub fn padded_len(self, len: usize) -> usize {
+        match self {
+            Padding::None => len,
+            Padding::Bucket(size) => {
+                let size = size.max(1) as usize;
+                len.div_ceil(size).max(1) * size
+            }
+            Padding::PowerOfTwo => len.next_power_of_two(),
+            Padding::Padme => padme(len),
+        }
+    }
+
+    /// append the padding of plaintext
+    pub fn pad(self, plaintext: &mut Vec<u8>) {
+        plaintext.resize(self.padded_len(plaintext.len()), 0);
+    }
+
+    /// the padding's value in a header extension: u8 kind, then for a bucket
+    /// the u32 size. None is not recorded
+    pub(crate) fn to_bytes(self) -> Vec<u8> {
+        match self {
+            Padding::None => Vec::new(),
+            Padding::Bucket(size) => {
+                let mut out = vec![PADDING_BUCKET];
+                out.extend_from_slice(&size.to_be_bytes());
+                out
+            }
+            Padding::PowerOfTwo => vec![PADDING_POWER_OF_TWO],
+            Padding::Padme => vec![PADDING_PADME],
+        }
+    }
+
+    /// parse the value of a padding header extension
+    pub(crate) fn from_bytes(value: &[u8]) -> Result<Padding> {
+        match value {
+            [PADDING_BUCKET, a, b, c, d] => match u32::from_be_bytes([*a, *b, *c, *d]) {
+                0 => Err(Error::malformed("padding to buckets of 0 bytes")),
+                size => Ok(Padding::Bucket(size)),
+            },
+            [PADDING_POWER_OF_TWO] => Ok(Padding::PowerOfTwo),
+            [PADDING_PADME] => Ok(Padding::Padme),
+            _ => Err(Error::malformed("unknown padding")),
+        }
+    }
+}
+
+impl fmt::Display for Padding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Padding::None => write!(f, "none"),
+            Padding::Bucket(size) => write!(f, "bucket ({} bytes)", size),
+            Padding::PowerOfTwo => write!(f, "power of two"),
+            Padding::Padme => write!(f, "padme"),
+        }
+    }
+}
+
+// keep the top log2(log2(len)) + 1 bits of len, rounding up
+fn padme(len: usize) -> usize {
+    if len < 2 {
+        return len;
+    }
+    let exponent = log2(len);
+    let kept = log2(exponent) + 1;
+    let mask = (1 << (exponent - kept)) - 1;
+    (len + mask) & !mask
+}
+
+fn log2(x: usize) -> usize {
+    (usize::BITS - 1 - x.leading_zeros()) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn padded_lengths() {
+        let cases = [
+            (Padding::None, [0, 1, 9, 100, 1000]),
+            (Padding::Bucket(64), [64, 64, 64, 128, 1024]),
+            (Padding::PowerOfTwo, [1, 1, 16, 128, 1024]),
+            (Padding::Padme, [0, 1, 10, 104, 1024]),
+        ];
+        for (padding, expected) in &cases {
+            for (len, padded) in [0, 1, 9, 100, 1000].iter().zip(expected) {
+                assert_eq!(padding.padded_len(*len), *padded, "{} of {}", padding, len);
+            }
+        }
+    }
+
+    #[test]
+    fn padme_overhead_is_bounded() {
+        for len in 1..100_000 {
+            let padded = padme(len);
+            assert!(padded >= len);
+            assert!(padded - len <= len * 12 / 100 + 1, "{}", len);
+        }
+    }
+
+    #[test]
+    fn pad_appends_zeros() {
+        let mut plaintext = b"yes".to_vec();
+        Padding::Bucket(16).pad(&mut plaintext);
+        assert_eq!(plaintext, b"yes\0\0\0\0\0\0\0\0\0\0\0\0\0");
+    }
+
+    #[test]
+    fn header_value_round_trip() -> Result<()> {
+        for padding in &[Padding::Bucket(256), Padding::PowerOfTwo, Padding::Padme] {
+            assert_eq!(Padding::from_bytes(&padding.to_bytes())?, *padding);
+        }
+        assert!(Padding::from_bytes(b"\x01\x00\x00\x00\x00").is_err());
+        assert!(Padding::from_bytes(b"\x02\x00").is_err());
+        assert!(Padding::from_bytes(b"\x04").is_err());
+        Ok(())
+    }
+}
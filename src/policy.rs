@@ -0,0 +1,298 @@
+use crate::encryption_scheme::SecurityLevel;
+use crate::errors::{Error, Result};
+use crate::header::Header;
+use crate::kdf::{Kdf, DEFAULT_PBKDF2_ITERATIONS};
+use crate::validity::{Clock, Validity};
+use serde::Deserialize;
+
+/// the most bytes a compressed box may decompress to by default (64 MiB)
+pub const DEFAULT_MAX_DECOMPRESSED_LEN: u64 = 64 << 20;
+
+/// Restricts which boxes `EncryptedBox::decrypt` is willing to open.
+///
+/// Since a box describes its own scheme and KDF, whoever crafts the box
+/// picks them. The policy makes sure that choice is one we accept before
+/// any key is derived. The defaults are strict: authenticated schemes only,
+/// PBKDF2 with at least the default number of iterations.
+///
+/// A policy can also be read from TOML, e.g.
+/// ```toml
+/// allowed_schemes = ["aes-256-gcm", "chacha20-poly1305"]
+/// allowed_kdfs = ["pbkdf2-sha256"]
+/// min_kdf_cost = 100000
+/// max_decompressed_len = 1048576
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DecryptPolicy {
+    /// schemes that may be decrypted. If empty, any scheme at or above
+    /// min_security_level is accepted, otherwise exactly these are
+    allowed_schemes: Vec<String>,
+    /// the weakest scheme accepted when allowed_schemes is empty
+    min_security_level: SecurityLevel,
+    /// names of the KDFs that may be used (see `Kdf::name`)
+    allowed_kdfs: Vec<String>,
+    /// minimal KDF cost (e.g. PBKDF2 iterations)
+    min_kdf_cost: u64,
+    /// maximal KDF cost, so a crafted box cannot make us derive keys forever
+    max_kdf_cost: u64,
+    /// the most bytes a compressed box may decompress to (see compression.rs)
+    max_decompressed_len: u64,
+    /// tells the time validity windows are checked at (see validity.rs)
+    #[serde(skip)]
+    clock: Clock,
+    /// open boxes outside their validity window, e.g. for forensics
+    #[serde(skip)]
+    ignore_expiry: bool,
+}
+
+impl Default for DecryptPolicy {
+    fn default() -> DecryptPolicy {
+        DecryptPolicy {
+            allowed_schemes: Vec::new(),
+            min_security_level: SecurityLevel::Authenticated,
+            allowed_kdfs: vec![String::from("pbkdf2-sha256")],
+            min_kdf_cost: u64::from(DEFAULT_PBKDF2_ITERATIONS),
+            max_kdf_cost: 100 * u64::from(DEFAULT_PBKDF2_ITERATIONS),
+            max_decompressed_len: DEFAULT_MAX_DECOMPRESSED_LEN,
+            clock: Clock::System,
+            ignore_expiry: false,
+        }
+    }
+}
+
+impl DecryptPolicy {
+    /// the default (strict) policy
+    pub fn new() -> DecryptPolicy {
+        DecryptPolicy::default()
+    }
+
+    /// read a policy from TOML, missing keys keep their defaults
+    pub fn from_toml(toml: &str) -> Result<DecryptPolicy> {
+        toml::from_str(toml).map_err(|e| Error::policy(format!("invalid policy: {}", e)))
+    }
+
+    /// accept the given scheme. Once any scheme is explicitly allowed,
+    /// only explicitly allowed schemes are accepted
+    pub fn allow_scheme(&mut self, scheme: &str) -> &mut DecryptPolicy {
+        if !self.allowed_schemes.iter().any(|s| s == scheme) {
+            self.allowed_schemes.push(scheme.to_string());
+        }
+        self
+    }
+
+    /// set the weakest scheme accepted when no scheme is explicitly allowed
+    pub fn min_security_level(&mut self, level: SecurityLevel) -> &mut DecryptPolicy {
+        self.min_security_level = level;
+        self
+    }
+
+    /// accept the given KDF (by name, see `Kdf::name`)
+    pub fn allow_kdf(&mut self, kdf: &str) -> &mut DecryptPolicy {
+        if !self.allowed_kdfs.iter().any(|k| k == kdf) {
+            self.allowed_kdfs.push(kdf.to_string());
+        }
+        self
+    }
+
+    /// set the minimal KDF cost
+    pub fn min_kdf_cost(&mut self, cost: u64) -> &mut DecryptPolicy {
+        self.min_kdf_cost = cost;
+        self
+    }
+
+    /// set the maximal KDF cost
+    pub fn max_kdf_cost(&mut self, cost: u64) -> &mut DecryptPolicy {
+        self.max_kdf_cost = cost;
+        self
+    }
+
+    /// set the most bytes a compressed box may decompress to, so a crafted
+    /// box cannot exhaust our memory
+    pub fn max_decompressed_len(&mut self, len: u64) -> &mut DecryptPolicy {
+        self.max_decompressed_len = len;
+        self
+    }
+
+    /// the most bytes a compressed box may decompress to
+    pub fn decompression_limit(&self) -> u64 {
+        self.max_decompressed_len
+    }
+
+    /// check validity windows at the time clock tells (the system clock by
+    /// default), e.g. `Clock::Fixed` in tests
+    pub fn set_clock(&mut self, clock: Clock) -> &mut DecryptPolicy {
+        self.clock = clock;
+        self
+    }
+
+    /// open boxes even outside their validity window. Their headers are still
+    /// authenticated. Meant for forensics, never for tokens
+    pub fn ignore_expiry(&mut self) -> &mut DecryptPolicy {
+        self.ignore_expiry = true;
+        self
+    }
+
+    /// check that the (authenticated) validity window of a box holds now
+    pub fn check_validity(&self, validity: &Validity) -> Result<()> {
+        if self.ignore_expiry {
+            return Ok(());
+        }
+        validity.check(self.clock.now())
+    }
+
+    /// check everything the header alone tells about a box
+    pub fn check_header(&self, header: &Header) -> Result<()> {
+        if !self.allowed_schemes.is_empty() && !self.allowed_schemes.contains(&header.scheme) {
+            return Err(Error::policy(format!(
+                "scheme '{}' is not allowed",
+                header.scheme
+            )));
+        }
+        self.check_kdf(&header.kdf.kdf)
+    }
+
+    /// check a box's KDF, so that its key is neither too cheap nor too
+    /// expensive to derive
+    pub fn check_kdf(&self, kdf: &Kdf) -> Result<()> {
+        if !self.allowed_kdfs.iter().any(|k| k == kdf.name()) {
+            return Err(Error::policy(format!(
+                "kdf '{}' is not allowed",
+                kdf.name()
+            )));
+        }
+        if kdf.cost() < self.min_kdf_cost {
+            return Err(Error::policy(format!(
+                "kdf cost {} is below the minimum of {}",
+                kdf.cost(),
+                self.min_kdf_cost
+            )));
+        }
+        if kdf.cost() > self.max_kdf_cost {
+            return Err(Error::policy(format!(
+                "kdf cost {} is above the maximum of {}",
+                kdf.cost(),
+                self.max_kdf_cost
+            )));
+        }
+        Ok(())
+    }
+
+    /// check the security level of the scheme the header named
+    pub fn check_security_level(&self, scheme: &str, level: SecurityLevel) -> Result<()> {
+        if self.allowed_schemes.is_empty() && level < self.min_security_level {
+            return Err(Error::policy(format!(
+                "scheme '{}' is {:?}, below the minimum of {:?}",
+                scheme, level, self.min_security_level
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ErrorKind;
+    use crate::kdf::KdfParams;
+    use crate::validity::from_unix_seconds;
+
+    fn header(scheme: &str, kdf: Kdf) -> Header {
+        Header::new(scheme, KdfParams::new(kdf))
+    }
+
+    fn is_violation(result: Result<()>) -> bool {
+        match result {
+            Err(e) => matches!(e.kind(), ErrorKind::PolicyViolation(_)),
+            Ok(_) => false,
+        }
+    }
+
+    #[test]
+    fn default_accepts_default_kdf() -> Result<()> {
+        DecryptPolicy::default().check_header(&header("aes-256-gcm", Kdf::default()))
+    }
+
+    #[test]
+    fn default_rejects_legacy_kdf() {
+        let policy = DecryptPolicy::default();
+        assert!(is_violation(
+            policy.check_header(&header("aes-256-gcm", Kdf::Sha512))
+        ));
+    }
+
+    #[test]
+    fn default_rejects_cheap_and_expensive_kdf() {
+        let policy = DecryptPolicy::default();
+        for iterations in &[1000, u32::MAX] {
+            let kdf = Kdf::Pbkdf2Sha256 {
+                iterations: *iterations,
+            };
+            assert!(is_violation(
+                policy.check_header(&header("aes-256-gcm", kdf))
+            ));
+        }
+    }
+
+    #[test]
+    fn default_rejects_unauthenticated_schemes() -> Result<()> {
+        let policy = DecryptPolicy::default();
+        assert!(is_violation(policy.check_security_level(
+            "aes-256-cbc",
+            SecurityLevel::Unauthenticated
+        )));
+        policy.check_security_level("aes-256-gcm", SecurityLevel::Authenticated)
+    }
+
+    #[test]
+    fn allow_list_is_exclusive() -> Result<()> {
+        let mut policy = DecryptPolicy::default();
+        policy.allow_scheme("aes-256-cbc");
+        policy.check_header(&header("aes-256-cbc", Kdf::default()))?;
+        policy.check_security_level("aes-256-cbc", SecurityLevel::Unauthenticated)?;
+        assert!(is_violation(
+            policy.check_header(&header("aes-256-gcm", Kdf::default()))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn validity_at_the_clock() -> Result<()> {
+        let window = Validity {
+            not_before: None,
+            expires_at: Some(1_000),
+        };
+        let mut policy = DecryptPolicy::default();
+        policy.set_clock(Clock::Fixed(from_unix_seconds(999)));
+        policy.check_validity(&window)?;
+        policy.set_clock(Clock::Fixed(from_unix_seconds(1_000)));
+        match policy.check_validity(&window).unwrap_err().kind() {
+            ErrorKind::ExpiryError(_) => (),
+            kind => panic!("unexpected error {:?}", kind),
+        }
+        policy.ignore_expiry().check_validity(&window)
+    }
+
+    #[test]
+    fn from_toml() -> Result<()> {
+        let policy = DecryptPolicy::from_toml(
+            r#"
+            allowed_schemes = ["chacha20-poly1305"]
+            allowed_kdfs = ["pbkdf2-sha256", "sha512"]
+            min_kdf_cost = 1
+            "#,
+        )?;
+        let mut expected = DecryptPolicy::default();
+        expected
+            .allow_scheme("chacha20-poly1305")
+            .allow_kdf("sha512")
+            .min_kdf_cost(1);
+        assert_eq!(policy, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn from_toml_rejects_unknown_keys() {
+        assert!(DecryptPolicy::from_toml("allow_everything = true").is_err());
+    }
+}
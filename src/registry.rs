@@ -0,0 +1,169 @@
+use crate::encryption_scheme::{DefaultScheme, EncryptionScheme, SchemeRef};
+use crate::errors::{Error, Result};
+use crate::openssl_aes::defs::OpenSslVariants;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::sync::Arc;
+
+/// Builds a scheme, see `SchemeRegistry::register`
+pub type SchemeFactory = Box<dyn Fn() -> SchemeRef + Send + Sync>;
+
+/// Maps scheme identifiers, as recorded in the header of every box, to
+/// factories of those schemes. This is how a box is decrypted without
+/// knowing its scheme at compile time (see `EncryptedBox::decrypt_with_registry`),
+/// and how other crates plug in schemes of their own, e.g.
+/// ```ignore
+/// let mut registry = SchemeRegistry::default();
+/// registry.register_scheme(MyScheme::new(&MyVariant::Fast));
+/// ```
+/// Identifiers are case-insensitive. The default registry holds the built-in
+/// schemes (see `DefaultScheme`), `new` an empty one.
+pub struct SchemeRegistry {
+    factories: BTreeMap<String, SchemeFactory>,
+}
+
+impl SchemeRegistry {
+    /// an empty registry
+    pub fn new() -> SchemeRegistry {
+        SchemeRegistry {
+            factories: BTreeMap::new(),
+        }
+    }
+
+    /// register factory under identifier, replacing the scheme registered
+    /// under it before. The schemes factory builds should be identified by
+    /// identifier, as that is what the boxes they seal record
+    pub fn register<F>(&mut self, identifier: &str, factory: F) -> &mut SchemeRegistry
+    where
+        F: Fn() -> SchemeRef + Send + Sync + 'static,
+    {
+        self.factories
+            .insert(identifier.to_ascii_lowercase(), Box::new(factory));
+        self
+    }
+
+    /// register scheme under its identifier
+    pub fn register_scheme<T>(&mut self, scheme: T) -> &mut SchemeRegistry
+    where
+        T: EncryptionScheme + Clone + Send + Sync + 'static,
+        T::Error: Display,
+    {
+        let identifier = scheme.identifier().to_string();
+        self.register(&identifier, move || Arc::new(scheme.clone()))
+    }
+
+    /// build the scheme registered under identifier
+    pub fn get(&self, identifier: &str) -> Result<SchemeRef> {
+        self.factories
+            .get(&identifier.to_ascii_lowercase())
+            .map(|factory| factory())
+            .ok_or_else(|| Error::scheme(format!("scheme '{}' is unsupported", identifier)))
+    }
+
+    /// whether a scheme is registered under identifier
+    pub fn contains(&self, identifier: &str) -> bool {
+        self.factories
+            .contains_key(&identifier.to_ascii_lowercase())
+    }
+
+    /// the registered identifiers, in order
+    pub fn identifiers(&self) -> impl Iterator<Item = &str> {
+        self.factories.keys().map(|identifier| &identifier[..])
+    }
+}
+
+impl Default for SchemeRegistry {
+    /// a registry of the built-in schemes
+    fn default() -> SchemeRegistry {
+        let mut registry = SchemeRegistry::new();
+        for variant in OpenSslVariants::iterator() {
+            registry.register_scheme(DefaultScheme::new(variant));
+        }
+        registry
+    }
+}
+
+impl fmt::Debug for SchemeRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.identifiers()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption_scheme::SecurityLevel;
+
+    /// a scheme of another crate, reversing the message (so not secure at all)
+    #[derive(Clone)]
+    struct Reverse;
+
+    impl EncryptionScheme for Reverse {
+        type Error = String;
+        type Variant = ();
+
+        fn new(_: &()) -> Reverse {
+            Reverse
+        }
+
+        fn identifier(&self) -> &str {
+            "x-reverse"
+        }
+
+        fn get_key_length(&self) -> usize {
+            4
+        }
+
+        fn security_level(&self) -> SecurityLevel {
+            SecurityLevel::Insecure
+        }
+
+        fn encrypt(&self, _: &[u8], msg: &[u8]) -> std::result::Result<Vec<u8>, String> {
+            Ok(msg.iter().rev().copied().collect())
+        }
+
+        fn decrypt(&self, key: &[u8], ctext: &[u8]) -> std::result::Result<Vec<u8>, String> {
+            EncryptionScheme::encrypt(self, key, ctext)
+        }
+    }
+
+    #[test]
+    fn default_holds_builtin_schemes() -> Result<()> {
+        let registry = SchemeRegistry::default();
+        assert_eq!(
+            registry.identifiers().count(),
+            OpenSslVariants::iterator().count()
+        );
+        for variant in OpenSslVariants::iterator() {
+            let scheme = registry.get(variant.name())?;
+            assert_eq!(scheme.identifier(), variant.name());
+            assert_eq!(scheme.get_key_length(), variant.key_length());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn identifiers_are_case_insensitive() -> Result<()> {
+        let registry = SchemeRegistry::default();
+        assert!(registry.contains("AES-256-GCM"));
+        assert_eq!(registry.get("AES-256-GCM")?.identifier(), "aes-256-gcm");
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_scheme_is_unsupported() {
+        assert!(SchemeRegistry::new().get("aes-256-gcm").is_err());
+        assert!(SchemeRegistry::default().get("aes-512-gcm").is_err());
+    }
+
+    #[test]
+    fn register_custom_scheme() -> Result<()> {
+        let mut registry = SchemeRegistry::default();
+        registry.register_scheme(Reverse);
+        let scheme = registry.get("x-reverse")?;
+        assert_eq!(scheme.security_level(), SecurityLevel::Insecure);
+        assert_eq!(scheme.encrypt(b"key!", b"abc").unwrap(), b"cba");
+        assert!(registry.contains("aes-256-gcm"));
+        Ok(())
+    }
+}
@@ -0,0 +1,458 @@
+pub use crate::encryption_scheme::{EncryptionScheme, SecurityLevel};
+pub use crate::openssl_aes::{
+    defs, defs::OpenSslVariants, errors::Error, errors::ErrorKind, errors::Result,
+};
+use crate::openssl_aes::{etm, siv};
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::consts::{U12, U16};
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{
+    BlockCipher, BlockDecrypt, BlockDecryptMut, BlockEncrypt, BlockEncryptMut, BlockSizeUser,
+    KeyInit, KeyIvInit, StreamCipher,
+};
+use aes::{Aes128, Aes192, Aes256};
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::AesGcm;
+use chacha20poly1305::ChaCha20Poly1305;
+use cmac::{Cmac, Mac};
+use rand::RngCore;
+
+// all supported AEAD modes use 96-bit nonces and 128-bit tags
+const AEAD_NONCE_LEN: usize = 12;
+const AEAD_TAG_LEN: usize = 16;
+
+/// The RustCrypto backend, a pure Rust implementation of the same variants
+/// as `OpensslAesWrapper`, producing the same ciphertexts (see rustcrypto_aes/)
+#[derive(Copy, Clone, PartialEq)]
+pub struct RustCryptoAesWrapper {
+    variant: OpenSslVariants,
+}
+
+#[derive(Clone, Copy)]
+enum Mode {
+    Ecb,
+    Cbc,
+    Ctr,
+    Ofb,
+    Gcm,
+}
+
+impl RustCryptoAesWrapper {
+    pub fn get_variant(&self) -> OpenSslVariants {
+        self.variant
+    }
+
+    pub fn get_iv_length(&self) -> Option<usize> {
+        self.variant.iv_length()
+    }
+
+    fn mode(&self) -> Mode {
+        match self.variant {
+            OpenSslVariants::Aes128Ecb
+            | OpenSslVariants::Aes192Ecb
+            | OpenSslVariants::Aes256Ecb => Mode::Ecb,
+            OpenSslVariants::Aes128Cbc
+            | OpenSslVariants::Aes192Cbc
+            | OpenSslVariants::Aes256Cbc => Mode::Cbc,
+            OpenSslVariants::Aes128Ctr
+            | OpenSslVariants::Aes192Ctr
+            | OpenSslVariants::Aes256Ctr => Mode::Ctr,
+            OpenSslVariants::Aes128Ofb
+            | OpenSslVariants::Aes192Ofb
+            | OpenSslVariants::Aes256Ofb => Mode::Ofb,
+            _ => Mode::Gcm,
+        }
+    }
+
+    /// check that key length matches the required key length
+    fn check_key_len(&self, key_len: usize) -> Result<()> {
+        let expected_key_length = self.get_key_length();
+        if expected_key_length != key_len {
+            return Result::Err(Error::keylen(expected_key_length, key_len));
+        };
+        Ok(())
+    }
+
+    /// encrypt msg with the given IV (or nonce) and, for authenticated modes,
+    /// associated data. Unlike `encrypt` the IV is not part of the ciphertext,
+    /// while the tag of authenticated modes is appended to it. Used by `encrypt`
+    /// and by the known-answer tests (see test_vectors.rs)
+    pub(crate) fn encrypt_with_iv(
+        &self,
+        key: &[u8],
+        iv: Option<&[u8]>,
+        aad: &[u8],
+        msg: &[u8],
+    ) -> Result<Vec<u8>> {
+        self.check_key_len(key.len())?;
+        if let Some(base) = self.variant.etm_base() {
+            let iv = iv.ok_or_else(|| Error::encryption("missing IV"))?;
+            return etm::seal(key, iv, aad, |enc_key| {
+                RustCryptoAesWrapper::new(&base).encrypt_with_iv(enc_key, Some(iv), &[], msg)
+            });
+        }
+        if let Some(base) = self.variant.siv_base() {
+            if iv.is_some() {
+                return Err(Error::encryption("SIV modes take no IV"));
+            }
+            return siv::seal(key, aad, msg, cmac, |enc_key, counter, msg| {
+                RustCryptoAesWrapper::new(&base).encrypt_with_iv(enc_key, Some(counter), &[], msg)
+            });
+        }
+        let iv = iv.unwrap_or(&[]);
+        match (self.variant, key.len()) {
+            (OpenSslVariants::ChaCha20Poly1305, _) => {
+                let cipher = ChaCha20Poly1305::new_from_slice(key).map_err(Error::encryption)?;
+                seal(&cipher, iv, aad, msg)
+            }
+            (_, 16) => self.encrypt_aes::<Aes128>(key, iv, aad, msg),
+            (_, 24) => self.encrypt_aes::<Aes192>(key, iv, aad, msg),
+            _ => self.encrypt_aes::<Aes256>(key, iv, aad, msg),
+        }
+    }
+
+    /// the inverse of `encrypt_with_iv`
+    pub(crate) fn decrypt_with_iv(
+        &self,
+        key: &[u8],
+        iv: Option<&[u8]>,
+        aad: &[u8],
+        ctext: &[u8],
+    ) -> Result<Vec<u8>> {
+        self.check_key_len(key.len())?;
+        if let Some(base) = self.variant.etm_base() {
+            let iv = iv.ok_or_else(etm::failed)?;
+            return etm::open(key, iv, aad, ctext, |enc_key, enc| {
+                RustCryptoAesWrapper::new(&base).decrypt_with_iv(enc_key, Some(iv), &[], enc)
+            });
+        }
+        if let Some(base) = self.variant.siv_base() {
+            if iv.is_some() {
+                return Err(etm::failed());
+            }
+            return siv::open(key, aad, ctext, cmac, |enc_key, counter, enc| {
+                RustCryptoAesWrapper::new(&base).decrypt_with_iv(enc_key, Some(counter), &[], enc)
+            });
+        }
+        let iv = iv.unwrap_or(&[]);
+        match (self.variant, key.len()) {
+            (OpenSslVariants::ChaCha20Poly1305, _) => {
+                let cipher = ChaCha20Poly1305::new_from_slice(key).map_err(Error::decryption)?;
+                open(&cipher, iv, aad, ctext)
+            }
+            (_, 16) => self.decrypt_aes::<Aes128>(key, iv, aad, ctext),
+            (_, 24) => self.decrypt_aes::<Aes192>(key, iv, aad, ctext),
+            _ => self.decrypt_aes::<Aes256>(key, iv, aad, ctext),
+        }
+    }
+
+    fn encrypt_aes<C>(&self, key: &[u8], iv: &[u8], aad: &[u8], msg: &[u8]) -> Result<Vec<u8>>
+    where
+        C: BlockCipher + BlockSizeUser<BlockSize = U16> + BlockEncrypt + BlockDecrypt + KeyInit,
+    {
+        match self.mode() {
+            Mode::Ecb => Ok(ecb::Encryptor::<C>::new_from_slice(key)
+                .map_err(Error::encryption)?
+                .encrypt_padded_vec_mut::<Pkcs7>(msg)),
+            Mode::Cbc => Ok(cbc::Encryptor::<C>::new_from_slices(key, iv)
+                .map_err(Error::encryption)?
+                .encrypt_padded_vec_mut::<Pkcs7>(msg)),
+            Mode::Ctr => {
+                let mut out = msg.to_vec();
+                ctr::Ctr128BE::<C>::new_from_slices(key, iv)
+                    .map_err(Error::encryption)?
+                    .apply_keystream(&mut out);
+                Ok(out)
+            }
+            Mode::Ofb => {
+                let cipher = C::new_from_slice(key).map_err(Error::encryption)?;
+                ofb(&cipher, iv, msg).map_err(Error::encryption)
+            }
+            Mode::Gcm => {
+                let cipher = AesGcm::<C, U12>::new_from_slice(key).map_err(Error::encryption)?;
+                seal(&cipher, iv, aad, msg)
+            }
+        }
+    }
+
+    fn decrypt_aes<C>(&self, key: &[u8], iv: &[u8], aad: &[u8], ctext: &[u8]) -> Result<Vec<u8>>
+    where
+        C: BlockCipher + BlockSizeUser<BlockSize = U16> + BlockEncrypt + BlockDecrypt + KeyInit,
+    {
+        match self.mode() {
+            Mode::Ecb => ecb::Decryptor::<C>::new_from_slice(key)
+                .map_err(Error::decryption)?
+                .decrypt_padded_vec_mut::<Pkcs7>(ctext)
+                .map_err(Error::decryption),
+            Mode::Cbc => cbc::Decryptor::<C>::new_from_slices(key, iv)
+                .map_err(Error::decryption)?
+                .decrypt_padded_vec_mut::<Pkcs7>(ctext)
+                .map_err(Error::decryption),
+            Mode::Ctr => {
+                let mut out = ctext.to_vec();
+                ctr::Ctr128BE::<C>::new_from_slices(key, iv)
+                    .map_err(Error::decryption)?
+                    .apply_keystream(&mut out);
+                Ok(out)
+            }
+            Mode::Ofb => {
+                let cipher = C::new_from_slice(key).map_err(Error::decryption)?;
+                ofb(&cipher, iv, ctext).map_err(Error::decryption)
+            }
+            Mode::Gcm => {
+                let cipher = AesGcm::<C, U12>::new_from_slice(key).map_err(Error::decryption)?;
+                open(&cipher, iv, aad, ctext)
+            }
+        }
+    }
+}
+
+// OFB xors the message with the keystream E(IV), E(E(IV)), ...
+// and is its own inverse
+fn ofb<C>(cipher: &C, iv: &[u8], msg: &[u8]) -> std::result::Result<Vec<u8>, &'static str>
+where
+    C: BlockSizeUser<BlockSize = U16> + BlockEncrypt,
+{
+    if iv.len() != 16 {
+        return Err("invalid IV length");
+    }
+    let mut block = GenericArray::clone_from_slice(iv);
+    let mut out = Vec::with_capacity(msg.len());
+    for chunk in msg.chunks(16) {
+        cipher.encrypt_block(&mut block);
+        out.extend(chunk.iter().zip(block.iter()).map(|(m, k)| m ^ k));
+    }
+    Ok(out)
+}
+
+// AES-CMAC (for SIV, see siv.rs) with the AES of key's length
+fn cmac(key: &[u8], parts: &[&[u8]]) -> Result<[u8; 16]> {
+    match key.len() {
+        16 => cmac_with::<Aes128>(key, parts),
+        24 => cmac_with::<Aes192>(key, parts),
+        _ => cmac_with::<Aes256>(key, parts),
+    }
+}
+
+fn cmac_with<C>(key: &[u8], parts: &[&[u8]]) -> Result<[u8; 16]>
+where
+    C: BlockCipher + BlockSizeUser<BlockSize = U16> + BlockEncrypt + KeyInit + Clone,
+    Cmac<C>: Mac + KeyInit,
+{
+    let mut mac = <Cmac<C> as KeyInit>::new_from_slice(key).map_err(Error::encryption)?;
+    for part in parts {
+        mac.update(part);
+    }
+    let mut tag = [0u8; 16];
+    tag.copy_from_slice(&mac.finalize().into_bytes());
+    Ok(tag)
+}
+
+fn seal<A: Aead>(cipher: &A, nonce: &[u8], aad: &[u8], msg: &[u8]) -> Result<Vec<u8>> {
+    if nonce.len() != AEAD_NONCE_LEN {
+        return Err(Error::encryption("invalid nonce length"));
+    }
+    cipher
+        .encrypt(GenericArray::from_slice(nonce), Payload { msg, aad })
+        .map_err(Error::encryption)
+}
+
+fn open<A: Aead>(cipher: &A, nonce: &[u8], aad: &[u8], ctext: &[u8]) -> Result<Vec<u8>> {
+    if nonce.len() != AEAD_NONCE_LEN {
+        return Err(Error::decryption("invalid nonce length"));
+    }
+    cipher
+        .decrypt(GenericArray::from_slice(nonce), Payload { msg: ctext, aad })
+        .map_err(Error::decryption)
+}
+
+/// See encryption_scheme.rs
+impl EncryptionScheme for RustCryptoAesWrapper {
+    /// error type returned (openssl_aes::errors::Error, shared by the backends)
+    type Error = Error;
+
+    /// variant type is OpenSslVariants
+    type Variant = OpenSslVariants;
+
+    fn new(v: &OpenSslVariants) -> RustCryptoAesWrapper {
+        RustCryptoAesWrapper { variant: *v }
+    }
+
+    fn identifier(&self) -> &str {
+        self.variant.name()
+    }
+
+    fn get_key_length(&self) -> usize {
+        self.variant.key_length()
+    }
+
+    fn security_level(&self) -> SecurityLevel {
+        self.variant.security_level()
+    }
+
+    fn is_deterministic(&self) -> bool {
+        self.variant.is_deterministic()
+    }
+
+    fn encrypt(&self, key: &[u8], msg: &[u8]) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        self.encrypt_with_aad_into(key, &[], msg, &mut out)?;
+        Ok(out)
+    }
+
+    fn decrypt(&self, key: &[u8], ctext: &[u8]) -> Result<Vec<u8>> {
+        self.decrypt_with_aad(key, &[], ctext)
+    }
+
+    // AEAD modes must never reuse a nonce under the same key, so like the openssl
+    // backend they get a fresh random nonce which is prepended to the ciphertext,
+    // and the tag is appended: nonce || ciphertext || tag. Encrypt-then-MAC
+    // modes likewise get a fresh random IV (see etm.rs), SIV modes a synthetic
+    // one (see siv.rs). The other modes use the fixed IV shared with the openssl backend
+    fn encrypt_with_aad_into(
+        &self,
+        key: &[u8],
+        aad: &[u8],
+        msg: &[u8],
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
+        if self.variant.etm_base().is_some() {
+            let mut iv = [0u8; etm::IV_LEN];
+            rand::rngs::OsRng.fill_bytes(&mut iv);
+            let enc = self.encrypt_with_iv(key, Some(&iv), aad, msg)?;
+            out.reserve(iv.len() + enc.len());
+            out.extend_from_slice(&iv);
+            out.extend(enc);
+            return Ok(());
+        }
+        if !self.variant.is_aead() {
+            // SIV modes authenticate aad, the unauthenticated modes drop it
+            out.extend(self.encrypt_with_iv(key, self.variant.fixed_iv(), aad, msg)?);
+            return Ok(());
+        }
+        let mut nonce = [0u8; AEAD_NONCE_LEN];
+        rand::rngs::OsRng.fill_bytes(&mut nonce);
+        let enc = self.encrypt_with_iv(key, Some(&nonce), aad, msg)?;
+        out.reserve(AEAD_NONCE_LEN + enc.len());
+        out.extend_from_slice(&nonce);
+        out.extend(enc);
+        Ok(())
+    }
+
+    fn decrypt_with_aad(&self, key: &[u8], aad: &[u8], ctext: &[u8]) -> Result<Vec<u8>> {
+        if self.variant.etm_base().is_some() {
+            self.check_key_len(key.len())?;
+            if ctext.len() < etm::IV_LEN {
+                return Err(etm::failed());
+            }
+            let (iv, rest) = ctext.split_at(etm::IV_LEN);
+            return self.decrypt_with_iv(key, Some(iv), aad, rest);
+        }
+        if !self.variant.is_aead() {
+            // SIV modes authenticate aad, the unauthenticated modes drop it
+            return self.decrypt_with_iv(key, self.variant.fixed_iv(), aad, ctext);
+        }
+        if ctext.len() < AEAD_NONCE_LEN + AEAD_TAG_LEN {
+            return Err(Error::decryption("ciphertext too short"));
+        }
+        let (nonce, rest) = ctext.split_at(AEAD_NONCE_LEN);
+        self.decrypt_with_iv(key, Some(nonce), aad, rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RustCryptoAesWrapper;
+    use crate::encryption_scheme::EncryptionScheme;
+    use crate::kdf;
+    use crate::openssl_aes::etm;
+    use crate::openssl_aes::{defs::OpenSslVariants, errors::Result};
+
+    const MSG: &[u8] = b"This is a message that spans more than two AES blocks";
+    const PASSWORD: &str = "password";
+
+    const FIELDS: &[u8; 16] = b"Some Crypto Text";
+    const KEY: &[u8; 16] = b"\x00\x01\x02\x03\x04\x05\x06\x07\x08\x09\x0A\x0B\x0C\x0D\x0E\x0F";
+
+    #[test]
+    fn encrypt_with_vector() -> Result<()> {
+        // the same vector as the openssl backend's, with the same fixed IV
+        let wrapper = RustCryptoAesWrapper::new(&OpenSslVariants::Aes128Cbc);
+        let enc = wrapper.encrypt(&KEY[..], &FIELDS[..])?;
+        assert_eq!(
+            b"\xB4\xB9\xE7\x30\xD6\xD6\xF7\xDE\x77\x3F\x1C\xFF\xB3\x3E\x44\x5A\x91\xD7\x27\x62\x87\x4D\
+              \xFB\x3C\x5E\xC4\x59\x72\x4A\xF4\x7C\xA1",
+            &enc[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn encrypt_decrypt_all_variants() -> Result<()> {
+        for variant in OpenSslVariants::iterator() {
+            let wrapper = RustCryptoAesWrapper::new(variant);
+            let key =
+                kdf::derive_key_from_password(&String::from(PASSWORD), wrapper.get_key_length());
+            for len in &[0, 1, 16, MSG.len()] {
+                let enc = wrapper.encrypt(&key[..], &MSG[..*len])?;
+                assert_eq!(wrapper.decrypt(&key[..], &enc[..])?, &MSG[..*len]);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn error_on_key_length_mismatch() {
+        for variant in OpenSslVariants::iterator() {
+            let wrapper = RustCryptoAesWrapper::new(variant);
+            let key = vec![0u8; wrapper.get_key_length() + 1];
+            assert!(wrapper.encrypt(&key[..], MSG).is_err());
+            assert!(wrapper.decrypt(&key[..], MSG).is_err());
+        }
+    }
+
+    #[test]
+    fn aead_detects_tampering() -> Result<()> {
+        for variant in OpenSslVariants::iterator().filter(|v| v.is_authenticated()) {
+            let wrapper = RustCryptoAesWrapper::new(variant);
+            let key =
+                kdf::derive_key_from_password(&String::from(PASSWORD), wrapper.get_key_length());
+            let mut enc = wrapper.encrypt(&key[..], MSG)?;
+            let last = enc.len() - 1;
+            enc[last] ^= 1;
+            assert!(wrapper.decrypt(&key[..], &enc[..]).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn aead_rejects_truncated_ciphertext() {
+        let wrapper = RustCryptoAesWrapper::new(&OpenSslVariants::ChaCha20Poly1305);
+        let key = kdf::derive_key_from_password(&String::from(PASSWORD), wrapper.get_key_length());
+        assert!(wrapper.decrypt(&key[..], &[0u8; 27]).is_err());
+    }
+
+    #[test]
+    fn etm_hides_padding_errors() -> Result<()> {
+        let wrapper = RustCryptoAesWrapper::new(&OpenSslVariants::Aes128CbcHmacSha256);
+        let key = kdf::derive_key_from_password(&String::from(PASSWORD), wrapper.get_key_length());
+        let iv = [0u8; 16];
+        // a block without valid padding, under a valid tag
+        let garbage = [0x55u8; 16];
+        let base = RustCryptoAesWrapper::new(&OpenSslVariants::Aes128Cbc);
+        let padding_error = base.decrypt_with_iv(&key[32..], Some(&iv), &[], &garbage);
+        assert!(padding_error.is_err());
+        let bad_padding = etm::seal(&key, &iv, &[], |_| Ok(garbage.to_vec()))?;
+        let mut bad_tag = wrapper.encrypt_with_iv(&key, Some(&iv), &[], MSG)?;
+        bad_tag[0] ^= 1;
+        let errors = [
+            wrapper.decrypt_with_iv(&key, Some(&iv), &[], &bad_padding),
+            wrapper.decrypt_with_iv(&key, Some(&iv), &[], &bad_tag),
+            wrapper.decrypt(&key, &[0u8; 15]),
+        ];
+        for error in errors.iter() {
+            assert_eq!(error.as_ref().unwrap_err().kind(), etm::failed().kind());
+        }
+        assert_ne!(padding_error.unwrap_err().kind(), etm::failed().kind());
+        Ok(())
+    }
+}
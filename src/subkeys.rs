@@ -0,0 +1,176 @@
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::fmt;
+
+// Every key a box uses is derived from one master key with HKDF-SHA256
+// (RFC 5869), so no two purposes or schemes ever share key material:
+//
+//   master key   the KDF's output (MASTER_KEY_LEN bytes, see kdf.rs)
+//   subkey       HKDF-Expand(HKDF-Extract(salt, master key),
+//                            "encrypted-box " || purpose || " " || scheme
+//                            || " " || len, len)
+//
+// e.g. "encrypted-box encryption key aes-256-gcm 32". Keys of the same purpose
+// for different schemes are independent, unlike truncations of a single
+// digest, where the AES-128 key is a prefix of the AES-256 key. HKDF alone
+// would still make a shorter key a prefix of a longer one with the same info,
+// hence the length in the info.
+
+/// the length of the master key the KDF derives for a box
+pub const MASTER_KEY_LEN: usize = 32;
+
+/// What a subkey is used for. Each purpose has its own label in the HKDF info
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Purpose {
+    /// encrypting the content of a box
+    ContentEncryption,
+    /// authenticating ciphertexts
+    Mac,
+    /// wrapping (encrypting) other keys
+    KeyWrapping,
+    /// authenticating the header of a box
+    HeaderAuthentication,
+    /// computing blind indexes (see blind_index.rs)
+    BlindIndex,
+    /// format-preserving encryption (see fpe.rs)
+    FormatPreserving,
+}
+
+impl Purpose {
+    /// the label of this purpose in the HKDF info
+    pub fn label(self) -> &'static str {
+        match self {
+            Purpose::ContentEncryption => "encryption key",
+            Purpose::Mac => "mac key",
+            Purpose::KeyWrapping => "key wrapping key",
+            Purpose::HeaderAuthentication => "header authentication key",
+            Purpose::BlindIndex => "blind index key",
+            Purpose::FormatPreserving => "format preserving key",
+        }
+    }
+}
+
+impl fmt::Display for Purpose {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.label())
+    }
+}
+
+/// A secret subkeys are derived from (an HKDF pseudorandom key)
+pub struct MasterKey {
+    prk: Hkdf<Sha256>,
+}
+
+impl MasterKey {
+    /// use key, which must already be uniformly random (e.g. the output of a
+    /// KDF), as the master key
+    pub fn new(key: &[u8]) -> MasterKey {
+        MasterKey::extract(None, key)
+    }
+
+    /// extract a master key from input keying material that is not uniformly
+    /// random, e.g. a shared secret, with an optional (non-secret) salt
+    pub fn extract(salt: Option<&[u8]>, ikm: &[u8]) -> MasterKey {
+        MasterKey {
+            prk: Hkdf::new(salt, ikm),
+        }
+    }
+
+    /// expand the master key into len bytes bound to info
+    pub fn expand(&self, info: &[u8], len: usize) -> Vec<u8> {
+        let mut okm = vec![0u8; len];
+        self.prk
+            .expand(info, &mut okm)
+            .expect("subkeys are far shorter than HKDF's limit");
+        okm
+    }
+
+    /// the subkey of len bytes for purpose with the scheme called scheme
+    pub fn subkey(&self, purpose: Purpose, scheme: &str, len: usize) -> Vec<u8> {
+        let info = format!("encrypted-box {} {} {}", purpose, scheme, len);
+        self.expand(info.as_bytes(), len)
+    }
+}
+
+impl fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("MasterKey(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PURPOSES: [Purpose; 6] = [
+        Purpose::ContentEncryption,
+        Purpose::Mac,
+        Purpose::KeyWrapping,
+        Purpose::HeaderAuthentication,
+        Purpose::BlindIndex,
+        Purpose::FormatPreserving,
+    ];
+
+    #[test]
+    fn rfc5869_known_answer() {
+        // RFC 5869, A.1 (Test Case 1)
+        let ikm = [0x0bu8; 22];
+        let salt = hex::decode("000102030405060708090a0b0c").unwrap();
+        let info = hex::decode("f0f1f2f3f4f5f6f7f8f9").unwrap();
+        let okm = MasterKey::extract(Some(&salt), &ikm).expand(&info, 42);
+        assert_eq!(
+            hex::encode(okm),
+            "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf\
+             34007208d5b887185865"
+        );
+    }
+
+    #[test]
+    fn purposes_have_independent_keys() {
+        let master = MasterKey::new(&[7; MASTER_KEY_LEN]);
+        for (i, a) in PURPOSES.iter().enumerate() {
+            for b in &PURPOSES[i + 1..] {
+                assert_ne!(
+                    master.subkey(*a, "aes-256-gcm", 32),
+                    master.subkey(*b, "aes-256-gcm", 32),
+                    "{} and {}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn schemes_have_independent_keys() {
+        let master = MasterKey::new(&[7; MASTER_KEY_LEN]);
+        let purpose = Purpose::ContentEncryption;
+        let aes128 = master.subkey(purpose, "aes-128-gcm", 16);
+        let aes256 = master.subkey(purpose, "aes-256-gcm", 32);
+        assert_ne!(&aes128[..], &aes256[..16]);
+        assert_ne!(master.subkey(purpose, "aes-256-cbc", 32), aes256);
+        // derivation is deterministic
+        assert_eq!(master.subkey(purpose, "aes-256-gcm", 32), aes256);
+    }
+
+    #[test]
+    fn lengths_have_independent_keys() {
+        let master = MasterKey::new(&[7; MASTER_KEY_LEN]);
+        let purpose = Purpose::ContentEncryption;
+        let short = master.subkey(purpose, "aes-256-gcm", 16);
+        let long = master.subkey(purpose, "aes-256-gcm", 32);
+        assert_ne!(&short[..], &long[..16]);
+        // unlike plain HKDF-Expand with the same info
+        let info = b"encrypted-box encryption key aes-256-gcm";
+        assert_eq!(&master.expand(info, 16)[..], &master.expand(info, 32)[..16]);
+    }
+
+    #[test]
+    fn subkeys_depend_on_the_master_key() {
+        let purpose = Purpose::ContentEncryption;
+        let one = MasterKey::new(&[1; MASTER_KEY_LEN]).subkey(purpose, "aes-256-gcm", 32);
+        let two = MasterKey::new(&[2; MASTER_KEY_LEN]).subkey(purpose, "aes-256-gcm", 32);
+        assert_ne!(one, two);
+        assert_ne!(&one[..], &[1; MASTER_KEY_LEN][..]);
+    }
+}
@@ -0,0 +1,355 @@
+// Known-answer tests of every scheme, and golden vectors of the container format.
+//
+// The vectors live in tests/vectors/, one file per source:
+//
+//   sp800-38a.txt   NIST SP 800-38A (ECB, CBC, CTR, OFB)
+//   gcm.txt         the GCM test cases of McGrew and Viega (AES-GCM)
+//   rfc8439.txt     RFC 8439 (ChaCha20-Poly1305)
+//   etm.txt         the encrypt-then-MAC modes, computed independently
+//   siv.txt         RFC 5297 (AES-SIV), and more computed independently
+//   containers.txt  encrypted boxes that must keep opening
+//
+// Every vector is a `[scheme identifier]` line followed by `name = hex value`
+// lines, optionally preceded by comment lines naming it, e.g.
+//
+//   # F.2.1 CBC-AES128.Encrypt
+//   [aes-128-cbc]
+//   key = 2b7e151628aed2a6abf7158809cf4f3c
+//   ...
+//
+// Round trips would pass even if a key or IV were wired up wrong, so the
+// cipher vectors are checked through the same code that `encrypt` and `decrypt`
+// use, with the vector's IV in place of the fixed IV (or random nonce).
+// Every backend is checked, and every variant must have vectors.
+
+use crate::encrypted_box::EncryptedBox;
+use crate::encryption_scheme::EncryptionScheme;
+use crate::header::Header;
+use crate::kdf::KeyCache;
+use crate::openssl_aes::defs::OpenSslVariants;
+use crate::openssl_aes::errors::Error;
+use crate::policy::DecryptPolicy;
+use crate::registry::SchemeRegistry;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+const CIPHER_VECTORS: &[&str] = &[
+    "sp800-38a.txt",
+    "gcm.txt",
+    "rfc8439.txt",
+    "etm.txt",
+    "siv.txt",
+];
+const CONTAINER_VECTORS: &str = "containers.txt";
+
+/// A scheme whose IV (or nonce) and associated data can be chosen, so it can
+/// be checked against published vectors. Schemes added later implement it too
+trait KnownAnswer: EncryptionScheme<Variant = OpenSslVariants, Error = Error> {
+    fn encrypt_with_iv(
+        &self,
+        key: &[u8],
+        iv: Option<&[u8]>,
+        aad: &[u8],
+        msg: &[u8],
+    ) -> Result<Vec<u8>, Error>;
+
+    fn decrypt_with_iv(
+        &self,
+        key: &[u8],
+        iv: Option<&[u8]>,
+        aad: &[u8],
+        ctext: &[u8],
+    ) -> Result<Vec<u8>, Error>;
+}
+
+#[cfg(feature = "openssl")]
+impl KnownAnswer for crate::openssl_aes::wrapper::OpensslAesWrapper {
+    fn encrypt_with_iv(
+        &self,
+        key: &[u8],
+        iv: Option<&[u8]>,
+        aad: &[u8],
+        msg: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        Self::encrypt_with_iv(self, key, iv, aad, msg)
+    }
+
+    fn decrypt_with_iv(
+        &self,
+        key: &[u8],
+        iv: Option<&[u8]>,
+        aad: &[u8],
+        ctext: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        Self::decrypt_with_iv(self, key, iv, aad, ctext)
+    }
+}
+
+#[cfg(feature = "rustcrypto")]
+impl KnownAnswer for crate::rustcrypto_aes::wrapper::RustCryptoAesWrapper {
+    fn encrypt_with_iv(
+        &self,
+        key: &[u8],
+        iv: Option<&[u8]>,
+        aad: &[u8],
+        msg: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        Self::encrypt_with_iv(self, key, iv, aad, msg)
+    }
+
+    fn decrypt_with_iv(
+        &self,
+        key: &[u8],
+        iv: Option<&[u8]>,
+        aad: &[u8],
+        ctext: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        Self::decrypt_with_iv(self, key, iv, aad, ctext)
+    }
+}
+
+/// a vector of a file in tests/vectors/
+struct Vector {
+    /// where the vector is from, e.g. "gcm.txt:31 # Test Case 3"
+    name: String,
+    scheme: String,
+    values: Vec<(String, String)>,
+}
+
+impl Vector {
+    /// the first value called name
+    fn get(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| &value[..])
+    }
+
+    /// the first value called name, which the vector must have
+    fn text(&self, name: &str) -> &str {
+        self.get(name)
+            .unwrap_or_else(|| panic!("{}: missing '{}'", self.name, name))
+    }
+
+    /// the first value called name, decoded from hex
+    fn bytes(&self, name: &str) -> Vec<u8> {
+        self.hex(self.text(name))
+    }
+
+    /// every value called name, decoded from hex
+    fn all_bytes(&self, name: &str) -> Vec<Vec<u8>> {
+        self.values
+            .iter()
+            .filter(|(n, _)| n == name)
+            .map(|(_, value)| self.hex(value))
+            .collect()
+    }
+
+    fn hex(&self, value: &str) -> Vec<u8> {
+        hex::decode(value).unwrap_or_else(|e| panic!("{}: bad hex: {}", self.name, e))
+    }
+}
+
+/// read the vectors of file in tests/vectors/
+fn load(file: &str) -> Vec<Vector> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("vectors")
+        .join(file);
+    let text = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("could not read {}: {}", path.display(), e));
+    let mut vectors: Vec<Vector> = Vec::new();
+    let mut comment = String::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            comment.clear();
+        } else if line.starts_with('#') {
+            comment = line.to_string();
+        } else if line.starts_with('[') && line.ends_with(']') {
+            vectors.push(Vector {
+                name: format!("{}:{} {}", file, number + 1, comment),
+                scheme: line[1..line.len() - 1].to_string(),
+                values: Vec::new(),
+            });
+        } else {
+            match (line.find('='), vectors.last_mut()) {
+                (Some(eq), Some(vector)) => vector.values.push((
+                    line[..eq].trim().to_string(),
+                    line[eq + 1..].trim().to_string(),
+                )),
+                _ => panic!(
+                    "{}:{}: expected '[scheme]' or 'name = value'",
+                    file,
+                    number + 1
+                ),
+            }
+        }
+    }
+    vectors
+}
+
+// ECB and CBC pad with PKCS#7, so their output holds a padding block
+// (a whole one if the message fills its last block) after the vector's ciphertext.
+// The encrypt-then-MAC vectors include the padding
+fn pads(variant: OpenSslVariants) -> bool {
+    variant.name().ends_with("-ecb") || variant.name().ends_with("-cbc")
+}
+
+/// check every cipher vector with scheme T, and that every variant has some
+fn check_ciphers<T: KnownAnswer>() {
+    let vectors: Vec<Vector> = CIPHER_VECTORS.iter().flat_map(|file| load(file)).collect();
+    for variant in OpenSslVariants::iterator() {
+        assert!(
+            vectors.iter().any(|v| v.scheme == variant.name()),
+            "no known-answer vectors for {}",
+            variant
+        );
+    }
+    for vector in &vectors {
+        let variant = OpenSslVariants::from_str(&vector.scheme)
+            .unwrap_or_else(|_| panic!("{}: unknown scheme", vector.name));
+        check_cipher(&T::new(&variant), variant, vector);
+    }
+}
+
+fn check_cipher<T: KnownAnswer>(scheme: &T, variant: OpenSslVariants, vector: &Vector) {
+    let key = vector.bytes("key");
+    let iv = vector.get("iv").map(|_| vector.bytes("iv"));
+    let aad = vector
+        .get("aad")
+        .map_or_else(Vec::new, |_| vector.bytes("aad"));
+    let plaintext = vector.bytes("plaintext");
+    let mut expected = vector.bytes("ciphertext");
+    if vector.get("tag").is_some() {
+        expected.extend(vector.bytes("tag"));
+    }
+    let iv = iv.as_deref();
+
+    let enc = scheme
+        .encrypt_with_iv(&key, iv, &aad, &plaintext)
+        .unwrap_or_else(|e| panic!("{}: encryption failed: {}", vector.name, e));
+    if pads(variant) {
+        assert_eq!(enc[..expected.len()], expected[..], "{}", vector.name);
+        assert_eq!(
+            enc.len(),
+            (plaintext.len() / 16 + 1) * 16,
+            "{}",
+            vector.name
+        );
+    } else {
+        assert_eq!(enc, expected, "{}", vector.name);
+    }
+
+    let dec = scheme
+        .decrypt_with_iv(&key, iv, &aad, &enc)
+        .unwrap_or_else(|e| panic!("{}: decryption failed: {}", vector.name, e));
+    assert_eq!(dec, plaintext, "{}", vector.name);
+
+    if variant.is_authenticated() {
+        let mut forged = enc.clone();
+        let last = forged.len() - 1;
+        forged[last] ^= 1;
+        assert!(
+            scheme.decrypt_with_iv(&key, iv, &aad, &forged).is_err(),
+            "{}: accepted a forged tag",
+            vector.name
+        );
+        if !aad.is_empty() {
+            assert!(
+                scheme.decrypt_with_iv(&key, iv, &[], &enc).is_err(),
+                "{}: ignored the associated data",
+                vector.name
+            );
+        }
+    }
+}
+
+/// the default policy, but accepting every scheme and the cheap KDFs of the
+/// golden boxes
+fn permissive() -> DecryptPolicy {
+    let mut policy = DecryptPolicy::new();
+    for variant in OpenSslVariants::iterator() {
+        policy.allow_scheme(variant.name());
+    }
+    policy.allow_kdf("sha512").min_kdf_cost(1);
+    policy
+}
+
+/// open every golden box with scheme T, and seal the deterministic ones again
+fn check_containers<T: KnownAnswer + Clone>() {
+    let vectors = load(CONTAINER_VECTORS);
+    for variant in OpenSslVariants::iterator() {
+        assert!(
+            vectors.iter().any(|v| v.scheme == variant.name()),
+            "no golden box of {}",
+            variant
+        );
+    }
+    for vector in &vectors {
+        let sealed = vector.bytes("box");
+        let fields = vector.all_bytes("field");
+        let mut keys = KeyCache::new(vector.text("password").to_string());
+
+        let (header, _) =
+            Header::parse(&sealed).unwrap_or_else(|e| panic!("{}: bad header: {}", vector.name, e));
+        assert_eq!(header.scheme, vector.scheme, "{}", vector.name);
+
+        let eb = EncryptedBox::<T>::decrypt_with_keys(&sealed, &permissive(), &mut keys)
+            .unwrap_or_else(|e| panic!("{}: could not open: {}", vector.name, e));
+        assert_eq!(eb.fields(), &fields[..], "{}", vector.name);
+
+        if matches!(OpenSslVariants::from_str(&header.scheme), Ok(v) if v.is_deterministic()) {
+            let resealed = eb
+                .encrypt()
+                .unwrap_or_else(|e| panic!("{}: could not seal: {}", vector.name, e));
+            assert_eq!(resealed, sealed, "{}: the format changed", vector.name);
+        }
+    }
+}
+
+#[cfg(feature = "openssl")]
+#[test]
+fn openssl_known_answers() {
+    check_ciphers::<crate::openssl_aes::wrapper::OpensslAesWrapper>();
+}
+
+#[cfg(feature = "rustcrypto")]
+#[test]
+fn rustcrypto_known_answers() {
+    check_ciphers::<crate::rustcrypto_aes::wrapper::RustCryptoAesWrapper>();
+}
+
+#[cfg(feature = "openssl")]
+#[test]
+fn openssl_golden_boxes() {
+    check_containers::<crate::openssl_aes::wrapper::OpensslAesWrapper>();
+}
+
+#[cfg(feature = "rustcrypto")]
+#[test]
+fn rustcrypto_golden_boxes() {
+    check_containers::<crate::rustcrypto_aes::wrapper::RustCryptoAesWrapper>();
+}
+
+#[test]
+fn golden_boxes_open_through_the_registry() {
+    let registry = SchemeRegistry::default();
+    for vector in load(CONTAINER_VECTORS) {
+        let mut keys = KeyCache::new(vector.text("password").to_string());
+        let eb = EncryptedBox::decrypt_with_registry(
+            &vector.bytes("box"),
+            &permissive(),
+            &mut keys,
+            &registry,
+        )
+        .unwrap_or_else(|e| panic!("{}: could not open: {}", vector.name, e));
+        assert_eq!(
+            eb.fields(),
+            &vector.all_bytes("field")[..],
+            "{}",
+            vector.name
+        );
+    }
+}
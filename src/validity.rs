@@ -0,0 +1,175 @@
+use crate::errors::{Error, Result};
+use crate::openssl_aes::etm;
+use crate::subkeys::{MasterKey, Purpose};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// A box may carry a validity window, e.g. to serve as a short-lived token:
+//
+//   not before   u64 seconds since the Unix epoch, before which it does not open
+//   expires at   u64 seconds since the Unix epoch, from which on it does not open
+//
+// Both are header extensions (see header.rs), readable without the password.
+// A box with either also carries a header tag, as its last extension:
+//
+//   tag          HMAC-SHA256(header key, the header up to the tag extension)
+//   header key   the header authentication subkey of the master key for the
+//                scheme (see subkeys.rs)
+//
+// The tag alone would not stop anyone from removing the window (along with
+// the tag), so the content key of a box with a tag is bound to it: its label
+// is the scheme identifier followed by the hex tag. Moving the window, or
+// removing it, leaves a box nobody can decrypt.
+
+/// the length of a header tag (HMAC-SHA256)
+pub const HEADER_TAG_LEN: usize = 32;
+
+/// When a box may be opened
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Validity {
+    /// seconds since the Unix epoch before which the box does not open
+    pub not_before: Option<u64>,
+    /// seconds since the Unix epoch from which on the box does not open
+    pub expires_at: Option<u64>,
+}
+
+impl Validity {
+    /// whether the box opens at any time
+    pub fn is_unbounded(&self) -> bool {
+        self.not_before.is_none() && self.expires_at.is_none()
+    }
+
+    /// check that now (in seconds since the Unix epoch) is inside the window
+    pub fn check(&self, now: u64) -> Result<()> {
+        if let Some(not_before) = self.not_before {
+            if now < not_before {
+                return Err(Error::expiry(format!(
+                    "the box is not valid before {} (it is {})",
+                    not_before, now
+                )));
+            }
+        }
+        if let Some(expires_at) = self.expires_at {
+            if now >= expires_at {
+                return Err(Error::expiry(format!(
+                    "the box expired at {} (it is {})",
+                    expires_at, now
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Tells the time validity windows are checked at
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Clock {
+    /// the system clock, the default
+    #[default]
+    System,
+    /// always the given time, e.g. for tests
+    Fixed(SystemTime),
+}
+
+impl Clock {
+    /// the current time in seconds since the Unix epoch
+    pub fn now(self) -> u64 {
+        match self {
+            Clock::System => unix_seconds(SystemTime::now()),
+            Clock::Fixed(time) => unix_seconds(time),
+        }
+    }
+}
+
+/// time in whole seconds since the Unix epoch (0 for earlier times)
+pub fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+/// the time secs seconds after the Unix epoch
+pub fn from_unix_seconds(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// the header tag of the authenticated bytes of a header (see
+/// `Header::authenticated_bytes`) sealed with scheme
+pub(crate) fn header_tag(master_key: &[u8], scheme: &str, authenticated: &[u8]) -> Vec<u8> {
+    header_mac(master_key, scheme, authenticated)
+        .result()
+        .code()
+        .to_vec()
+}
+
+/// check the header tag of a header, in constant time. A wrong password
+/// fails here, so the error is the opaque one of a failed decryption (see
+/// etm.rs) rather than that of a malformed box
+pub(crate) fn verify_header_tag(
+    master_key: &[u8],
+    scheme: &str,
+    authenticated: &[u8],
+    tag: &[u8],
+) -> Result<()> {
+    header_mac(master_key, scheme, authenticated)
+        .verify(tag)
+        .map_err(|_| Error::scheme(etm::failed()))
+}
+
+fn header_mac(master_key: &[u8], scheme: &str, authenticated: &[u8]) -> Hmac<Sha256> {
+    let key =
+        MasterKey::new(master_key).subkey(Purpose::HeaderAuthentication, scheme, HEADER_TAG_LEN);
+    let mut mac = Hmac::<Sha256>::new_varkey(&key).expect("HMAC takes keys of any length");
+    mac.input(authenticated);
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ErrorKind;
+
+    fn is_expiry(result: Result<()>) -> bool {
+        match result {
+            Err(e) => matches!(e.kind(), ErrorKind::ExpiryError(_)),
+            Ok(_) => false,
+        }
+    }
+
+    #[test]
+    fn window_bounds() -> Result<()> {
+        let window = Validity {
+            not_before: Some(100),
+            expires_at: Some(200),
+        };
+        assert!(is_expiry(window.check(99)));
+        window.check(100)?;
+        window.check(199)?;
+        assert!(is_expiry(window.check(200)));
+
+        Validity::default().check(0)?;
+        assert!(Validity::default().is_unbounded());
+        assert!(!window.is_unbounded());
+        Ok(())
+    }
+
+    #[test]
+    fn fixed_clock() {
+        let clock = Clock::Fixed(from_unix_seconds(1_600_000_000));
+        assert_eq!(clock.now(), 1_600_000_000);
+        assert!(Clock::System.now() > 1_600_000_000);
+        assert_eq!(unix_seconds(UNIX_EPOCH - Duration::from_secs(1)), 0);
+    }
+
+    #[test]
+    fn header_tags() -> Result<()> {
+        let master = [7u8; 32];
+        let tag = header_tag(&master, "aes-256-gcm", b"header");
+        assert_eq!(tag.len(), HEADER_TAG_LEN);
+        verify_header_tag(&master, "aes-256-gcm", b"header", &tag)?;
+        assert!(verify_header_tag(&master, "aes-256-gcm", b"headex", &tag).is_err());
+        assert!(verify_header_tag(&master, "aes-128-gcm", b"header", &tag).is_err());
+        assert!(verify_header_tag(&[8u8; 32], "aes-256-gcm", b"header", &tag).is_err());
+        Ok(())
+    }
+}
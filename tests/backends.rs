@@ -0,0 +1,95 @@
+// ciphertexts of one backend must decrypt with the other
+#![cfg(all(feature = "openssl", feature = "rustcrypto"))]
+
+use encrypted_box::encrypted_box::EncryptedBox;
+use encrypted_box::encrypted_box_builder::EncryptedBoxBuilder;
+use encrypted_box::encryption_scheme::EncryptionScheme;
+use encrypted_box::kdf::{self, Kdf};
+use encrypted_box::openssl_aes::defs::OpenSslVariants;
+use encrypted_box::openssl_aes::wrapper::OpensslAesWrapper;
+use encrypted_box::policy::DecryptPolicy;
+use encrypted_box::rustcrypto_aes::wrapper::RustCryptoAesWrapper;
+use exitfailure::ExitFailure;
+
+const PASSWORD: &str = "password";
+const MSG: &[u8] = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit. Donec posuere.";
+
+fn key(variant: &OpenSslVariants) -> Vec<u8> {
+    kdf::derive_key_from_password(&String::from(PASSWORD), variant.key_length())
+}
+
+#[test]
+fn openssl_to_rustcrypto() -> Result<(), ExitFailure> {
+    for variant in OpenSslVariants::iterator() {
+        let (openssl, rustcrypto) = (
+            OpensslAesWrapper::new(variant),
+            RustCryptoAesWrapper::new(variant),
+        );
+        for len in 0..=MSG.len() {
+            let enc = openssl.encrypt(&key(variant), &MSG[..len])?;
+            assert_eq!(
+                rustcrypto.decrypt(&key(variant), &enc)?,
+                &MSG[..len],
+                "{}",
+                variant
+            );
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn rustcrypto_to_openssl() -> Result<(), ExitFailure> {
+    for variant in OpenSslVariants::iterator() {
+        let (openssl, rustcrypto) = (
+            OpensslAesWrapper::new(variant),
+            RustCryptoAesWrapper::new(variant),
+        );
+        for len in 0..=MSG.len() {
+            let enc = rustcrypto.encrypt(&key(variant), &MSG[..len])?;
+            assert_eq!(
+                openssl.decrypt(&key(variant), &enc)?,
+                &MSG[..len],
+                "{}",
+                variant
+            );
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn non_aead_ciphertexts_are_identical() -> Result<(), ExitFailure> {
+    for variant in OpenSslVariants::iterator().filter(|v| !v.is_authenticated()) {
+        let openssl = OpensslAesWrapper::new(variant).encrypt(&key(variant), MSG)?;
+        let rustcrypto = RustCryptoAesWrapper::new(variant).encrypt(&key(variant), MSG)?;
+        assert_eq!(openssl, rustcrypto, "{}", variant);
+    }
+    Ok(())
+}
+
+#[test]
+fn boxes_open_with_either_backend() -> Result<(), ExitFailure> {
+    let mut policy = DecryptPolicy::default();
+    policy.min_kdf_cost(1000);
+    let kdf = Kdf::Pbkdf2Sha256 { iterations: 1000 };
+    for variant in OpenSslVariants::iterator().filter(|v| v.is_authenticated()) {
+        let mut ebb = EncryptedBoxBuilder::new(OpensslAesWrapper::new(variant));
+        ebb.set_kdf(kdf)
+            .set_password(String::from(PASSWORD))
+            .add_fields(&["a", "b"]);
+        let sealed = ebb.into_box()?.encrypt()?;
+        let eb = EncryptedBox::<RustCryptoAesWrapper>::decrypt(
+            String::from(PASSWORD),
+            &sealed,
+            &policy,
+        )?;
+        assert_eq!(eb.fields(), &[b"a".to_vec(), b"b".to_vec()][..]);
+
+        let sealed = eb.encrypt()?;
+        let eb =
+            EncryptedBox::<OpensslAesWrapper>::decrypt(String::from(PASSWORD), &sealed, &policy)?;
+        assert_eq!(eb.fields(), &[b"a".to_vec(), b"b".to_vec()][..]);
+    }
+    Ok(())
+}
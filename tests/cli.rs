@@ -1,9 +1,16 @@
 use assert_cmd::prelude::*;
+use encrypted_box::blind_index::BlindIndex;
+use encrypted_box::encoding::{self, Encoding};
+use encrypted_box::encrypted_box_builder::EncryptedBoxBuilder;
+use encrypted_box::encryption_scheme::{DefaultScheme, EncryptionScheme};
+use encrypted_box::kdf::Kdf;
+use encrypted_box::openssl_aes::defs::OpenSslVariants;
 use predicates::prelude::*;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use std::io::Write;
 use std::process::Command;
+use std::time::{Duration, UNIX_EPOCH};
 use tempfile::NamedTempFile;
 
 #[test]
@@ -64,23 +71,20 @@ fn compare_use_password_to_password_file() -> Result<(), Box<dyn std::error::Err
         .arg("--password-file")
         .arg(file.path())
         .arg("-f")
-        .arg("field")
-        .arg("-s")
-        .arg("1");
+        .arg("field");
     let c_file = cmd_file.output()?;
+    assert!(c_file.status.success());
 
     let mut cmd_line = Command::cargo_bin("encrypted-box")?;
     cmd_line
+        .arg("-d")
         .arg("-p")
         .arg("password")
-        .arg("-f")
-        .arg("field")
-        .arg("-s")
-        .arg("1");
-
-    let c_line = cmd_line.output()?;
-
-    assert_eq!(c_line, c_file);
+        .with_stdin()
+        .buffer(c_file.stdout)
+        .assert()
+        .success()
+        .stdout("field\n");
 
     Ok(())
 }
@@ -142,10 +146,844 @@ fn happy_flow() -> Result<(), Box<dyn std::error::Error>> {
         .arg("-f")
         .arg("field")
         .arg("-s")
-        .arg("1");
+        .arg("1")
+        .arg("--insecure");
+
+    // the box starts with the "EBOX" magic
+    cmd_line
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("RUJPWA"));
+
+    Ok(())
+}
+
+fn encrypt_then_decrypt(encoding: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let boxed = dir.path().join("box");
+
+    let mut cmd_enc = Command::cargo_bin("encrypted-box")?;
+    cmd_enc
+        .arg("-p")
+        .arg("password")
+        .arg("-f")
+        .arg("field")
+        .arg("-s")
+        .arg("1")
+        .arg("--insecure")
+        .arg("-e")
+        .arg(encoding)
+        .arg("-o")
+        .arg(&boxed);
+    cmd_enc.assert().success();
+
+    let mut cmd_dec = Command::cargo_bin("encrypted-box")?;
+    cmd_dec
+        .arg("-d")
+        .arg("-p")
+        .arg("password")
+        .arg("--insecure")
+        .arg("-i")
+        .arg(&boxed);
+    cmd_dec.assert().success().stdout("field\n");
+
+    Ok(())
+}
+
+#[test]
+fn decrypt_detects_every_encoding() -> Result<(), Box<dyn std::error::Error>> {
+    for encoding in &["base64", "base64url", "hex", "binary", "armor"] {
+        encrypt_then_decrypt(encoding)?;
+    }
+    Ok(())
+}
+
+/// encrypt fields with the given extra arguments, returning the base64 box
+fn encrypt(fields: &[&str], args: &[&str]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-p")
+        .arg("password")
+        .arg("-f")
+        .args(fields)
+        .args(args);
+    let output = cmd.output()?;
+    assert!(output.status.success());
+    Ok(output.stdout)
+}
+
+#[test]
+fn decrypt_from_stdin() -> Result<(), Box<dyn std::error::Error>> {
+    let boxed = encrypt(&["first", "second field"], &[])?;
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-d")
+        .arg("-p")
+        .arg("password")
+        .with_stdin()
+        .buffer(boxed)
+        .assert()
+        .success()
+        .stdout("first\nsecond field\n");
+
+    Ok(())
+}
+
+#[test]
+fn decrypt_with_wrong_password() -> Result<(), Box<dyn std::error::Error>> {
+    let boxed = encrypt(&["field"], &[])?;
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-d")
+        .arg("-p")
+        .arg("not the password")
+        .with_stdin()
+        .buffer(boxed)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("decryption failed!"));
+
+    Ok(())
+}
+
+#[test]
+fn decrypt_refuses_unauthenticated_box() -> Result<(), Box<dyn std::error::Error>> {
+    let boxed = encrypt(&["field"], &["-s", "aes-128-cbc", "--insecure"])?;
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-d")
+        .arg("-p")
+        .arg("password")
+        .with_stdin()
+        .buffer(boxed)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "policy violation: 'scheme 'aes-128-cbc' is Unauthenticated",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn decrypt_allow_scheme() -> Result<(), Box<dyn std::error::Error>> {
+    let boxed = encrypt(&["field"], &["-s", "aes-128-cbc", "--insecure"])?;
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-d")
+        .arg("-p")
+        .arg("password")
+        .arg("--allow-scheme")
+        .arg("aes-128-cbc")
+        .with_stdin()
+        .buffer(boxed.clone())
+        .assert()
+        .success()
+        .stdout("field\n");
+
+    // once schemes are listed, nothing else is accepted
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-d")
+        .arg("-p")
+        .arg("password")
+        .arg("--allow-scheme")
+        .arg("aes-256-gcm")
+        .with_stdin()
+        .buffer(boxed)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "scheme 'aes-128-cbc' is not allowed",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn decrypt_policy_file() -> Result<(), Box<dyn std::error::Error>> {
+    let boxed = encrypt(&["field"], &["-s", "chacha20-poly1305"])?;
+    let mut policy = NamedTempFile::new()?;
+    write!(policy, "allowed_schemes = [\"aes-256-gcm\"]")?;
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-d")
+        .arg("-p")
+        .arg("password")
+        .arg("--policy-file")
+        .arg(policy.path())
+        .with_stdin()
+        .buffer(boxed.clone())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "scheme 'chacha20-poly1305' is not allowed",
+        ));
+
+    // --allow-scheme extends the policy file
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-d")
+        .arg("-p")
+        .arg("password")
+        .arg("--policy-file")
+        .arg(policy.path())
+        .arg("--allow-scheme")
+        .arg("chacha20-poly1305")
+        .with_stdin()
+        .buffer(boxed)
+        .assert()
+        .success()
+        .stdout("field\n");
+
+    Ok(())
+}
+
+#[test]
+fn invalid_policy_file() -> Result<(), Box<dyn std::error::Error>> {
+    let mut policy = NamedTempFile::new()?;
+    write!(policy, "allow_everything = true")?;
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-d")
+        .arg("-p")
+        .arg("password")
+        .arg("--policy-file")
+        .arg(policy.path())
+        .with_stdin()
+        .buffer("")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid policy"));
+
+    Ok(())
+}
+
+#[test]
+fn armor_output() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-p")
+        .arg("password")
+        .arg("-f")
+        .arg("field")
+        .arg("-e")
+        .arg("armor");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with(
+            "-----BEGIN ENCRYPTED BOX-----\n",
+        ))
+        .stdout(predicate::str::ends_with("-----END ENCRYPTED BOX-----\n"));
+
+    Ok(())
+}
+
+#[test]
+fn binary_output_requires_file() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-p")
+        .arg("password")
+        .arg("-f")
+        .arg("field")
+        .arg("-e")
+        .arg("binary");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "binary output must be written to a file",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn unknown_encoding() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-p")
+        .arg("password")
+        .arg("-f")
+        .arg("field")
+        .arg("-e")
+        .arg("base32");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("unknown encoding"));
+
+    Ok(())
+}
+
+#[test]
+fn list_schemes() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("list-schemes");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("aes-128-ecb"))
+        .stdout(predicate::str::is_match(
+            r"aes-256-gcm\s+32\s+12\s+yes\s+no",
+        )?)
+        .stdout(predicate::str::is_match(
+            r"aes-256-siv\s+64\s+16\s+yes\s+yes",
+        )?);
+
+    Ok(())
+}
+
+#[test]
+fn bench_recommends_kdf() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("bench").arg("--target-ms").arg("20");
+    cmd.assert().success().stdout(predicate::str::is_match(
+        r#"kdf = \{ algorithm = "pbkdf2-sha256", iterations = \d+ \}"#,
+    )?);
+
+    Ok(())
+}
+
+#[test]
+fn scheme_by_name_matches_index() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-p")
+        .arg("password")
+        .arg("-f")
+        .arg("field")
+        .arg("-s")
+        .arg("aes-128-cbc")
+        .arg("--insecure");
+    let by_name = cmd.output()?;
+    assert!(!String::from_utf8(by_name.stderr)?.contains("deprecated"));
+    let by_index = encrypt(&["field"], &["-s", "1", "--insecure"])?;
+
+    // magic, version and scheme identifier (the first 15 bytes) match
+    assert_eq!(by_name.stdout[..20], by_index[..20]);
+
+    Ok(())
+}
+
+#[test]
+fn unauthenticated_scheme_requires_insecure() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-p")
+        .arg("password")
+        .arg("-f")
+        .arg("field")
+        .arg("-s")
+        .arg("aes-128-cbc");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "scheme 'aes-128-cbc' is unauthenticated, so tampering goes undetected; pass --insecure",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn ecb_requires_insecure() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-p")
+        .arg("password")
+        .arg("-f")
+        .arg("field")
+        .arg("-s")
+        .arg("aes-256-ecb");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("scheme 'aes-256-ecb' is insecure"));
+
+    Ok(())
+}
+
+#[test]
+fn insecure_scheme_warns() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-p")
+        .arg("password")
+        .arg("-f")
+        .arg("field")
+        .arg("-s")
+        .arg("aes-256-ecb")
+        .arg("--insecure");
+    cmd.assert().success().stderr(predicate::str::contains(
+        "warning: scheme 'aes-256-ecb' is insecure",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn scheme_index_is_deprecated() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-p")
+        .arg("password")
+        .arg("-f")
+        .arg("field")
+        .arg("-s")
+        .arg("1")
+        .arg("--insecure");
+    cmd.assert().success().stderr(predicate::str::contains(
+        "numeric scheme indices are deprecated, use '--scheme aes-128-cbc' instead",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn unknown_scheme_name() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-p")
+        .arg("password")
+        .arg("-f")
+        .arg("field")
+        .arg("-s")
+        .arg("aes-512-gcm");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "unknown scheme name 'aes-512-gcm'",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn default_scheme_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd_enc = Command::cargo_bin("encrypted-box")?;
+    cmd_enc.arg("-p").arg("password").arg("-f").arg("field");
+    let enc = cmd_enc.output()?;
+    assert!(enc.status.success());
+
+    let mut cmd_dec = Command::cargo_bin("encrypted-box")?;
+    cmd_dec
+        .arg("-d")
+        .arg("-p")
+        .arg("password")
+        .with_stdin()
+        .buffer(enc.stdout)
+        .assert()
+        .success()
+        .stdout("field\n");
+
+    Ok(())
+}
+
+/// write a config (and a password file named pass next to it) into dir
+fn write_config(
+    dir: &std::path::Path,
+    config: &str,
+) -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(dir.join("pass"), "password")?;
+    let path = dir.join("config.toml");
+    std::fs::write(&path, config)?;
+    Ok(path)
+}
+
+#[test]
+fn config_sets_defaults() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let config = write_config(
+        dir.path(),
+        r#"
+        scheme = "chacha20-poly1305"
+        encoding = "hex"
+        keyring = "pass"
+        kdf = { algorithm = "pbkdf2-sha256", iterations = 100001 }
+        "#,
+    )?;
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("--config").arg(&config).arg("-f").arg("field");
+    let output = cmd.output()?;
+    assert!(output.status.success());
+    let boxed = String::from_utf8(output.stdout)?;
+    // magic, version and the configured scheme, then the configured iterations
+    assert!(boxed.starts_with(&format!(
+        "45424f580211{}01000186a1",
+        hex::encode("chacha20-poly1305")
+    )));
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-d")
+        .arg("-p")
+        .arg("password")
+        .with_stdin()
+        .buffer(boxed)
+        .assert()
+        .success()
+        .stdout("field\n");
+
+    Ok(())
+}
+
+#[test]
+fn flags_override_config() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let config = write_config(
+        dir.path(),
+        "scheme = \"aes-128-cbc\"\nencoding = \"hex\"\nkeyring = \"no-such-file\"\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("--config")
+        .arg(&config)
+        .arg("-f")
+        .arg("field")
+        .arg("-s")
+        .arg("aes-256-gcm")
+        .arg("-e")
+        .arg("base64")
+        .arg("--password-file")
+        .arg(dir.path().join("pass"));
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with("RUJPWAILYWVzLTI1Ni1nY20"));
+
+    Ok(())
+}
+
+#[test]
+fn config_from_xdg_config_home() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    write_config(
+        &dir.path().join("encrypted-box"),
+        "encoding = \"hex\"\nkeyring = \"pass\"\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.env("XDG_CONFIG_HOME", dir.path())
+        .arg("-f")
+        .arg("field");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with("45424f5802"));
+
+    Ok(())
+}
+
+#[test]
+fn config_policy() -> Result<(), Box<dyn std::error::Error>> {
+    let boxed = encrypt(&["field"], &["-s", "chacha20-poly1305"])?;
+    let dir = tempfile::tempdir()?;
+    let config = write_config(
+        dir.path(),
+        "[policy]\nallowed_schemes = [\"aes-256-gcm\"]\n",
+    )?;
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("--config")
+        .arg(&config)
+        .arg("-d")
+        .arg("-p")
+        .arg("password")
+        .with_stdin()
+        .buffer(boxed)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "scheme 'chacha20-poly1305' is not allowed",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn invalid_config() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let config = write_config(dir.path(), "schema = \"aes-256-gcm\"\n")?;
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("--config")
+        .arg(&config)
+        .arg("-p")
+        .arg("password")
+        .arg("-f")
+        .arg("field");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("config error: 'invalid config"));
+
+    Ok(())
+}
+
+#[test]
+fn batch_csv_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let plain = dir.path().join("plain.csv");
+    let sealed = dir.path().join("sealed.csv");
+    let report = dir.path().join("errors.jsonl");
+    std::fs::write(&plain, "id,email\n1,a@example.com\n2\n3,c@example.com\n")?;
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("batch")
+        .arg("encrypt")
+        .arg("-c")
+        .arg("email")
+        .arg("-p")
+        .arg("password")
+        .arg("-i")
+        .arg(&plain)
+        .arg("-o")
+        .arg(&sealed)
+        .arg("--error-report")
+        .arg(&report);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("1 of 3 records failed"));
+    let errors = std::fs::read_to_string(&report)?;
+    assert!(errors.starts_with("{\"error\":"));
+    assert!(errors.trim_end().ends_with("\"record\":2}"));
+    assert!(!std::fs::read_to_string(&sealed)?.contains("example.com"));
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("batch")
+        .arg("decrypt")
+        .arg("-c")
+        .arg("email")
+        .arg("-p")
+        .arg("password")
+        .arg("-i")
+        .arg(&sealed);
+    cmd.assert()
+        .success()
+        .stdout("id,email\n1,a@example.com\n3,c@example.com\n")
+        .stderr("2 records processed\n");
 
-    let output = cmd_line.output()?;
-    assert_eq!(output.stdout, b"QDRFizR/JYrq4Ns6mfyKgg==\n");
+    Ok(())
+}
+
+#[test]
+fn batch_jsonl_per_row() -> Result<(), Box<dyn std::error::Error>> {
+    let plain = "{\"email\":\"a@example.com\",\"id\":1,\"tags\":[\"x\"]}\n";
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-p")
+        .arg("password")
+        .arg("batch")
+        .arg("encrypt")
+        .arg("--format")
+        .arg("jsonl")
+        .arg("--per-row")
+        .arg("-c")
+        .arg("email")
+        .arg("-c")
+        .arg("tags")
+        .arg("--jobs")
+        .arg("2");
+    let output = cmd.with_stdin().buffer(plain).output()?;
+    assert!(output.status.success());
+    let sealed = String::from_utf8(output.stdout)?;
+    assert!(sealed.starts_with("{\"encrypted_box\":\"RUJPWA"));
+    assert!(sealed.trim_end().ends_with("\"id\":1}"));
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-p")
+        .arg("password")
+        .arg("batch")
+        .arg("decrypt")
+        .arg("--format")
+        .arg("jsonl")
+        .arg("--per-row")
+        .with_stdin()
+        .buffer(sealed)
+        .assert()
+        .success()
+        .stdout(plain);
+
+    Ok(())
+}
+
+#[test]
+fn batch_requires_format() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-p")
+        .arg("password")
+        .arg("batch")
+        .arg("encrypt")
+        .arg("-c")
+        .arg("email")
+        .with_stdin()
+        .buffer("id,email\n");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "could not detect the record format, use --format",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn index_values() -> Result<(), Box<dyn std::error::Error>> {
+    let index = BlindIndex::derive("email", "password", Kdf::default());
+    let expected = format!(
+        "{}\n{}\n",
+        hex::encode(index.index(b"alice@example.com")),
+        hex::encode(index.index(b"bob@example.com"))
+    );
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-p")
+        .arg("password")
+        .arg("index")
+        .arg("-n")
+        .arg("email")
+        .arg("alice@example.com")
+        .arg("bob@example.com");
+    cmd.assert().success().stdout(expected);
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-p")
+        .arg("password")
+        .arg("index")
+        .arg("-n")
+        .arg("email")
+        .arg("-l")
+        .arg("33")
+        .arg("alice@example.com");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "the index length must be between 1 and 32",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn compress_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+    let roles = vec![r#""admin""#; 50].join(", ");
+    let json = &format!(r#"{{"name": "alice", "roles": [{}]}}"#, roles)[..];
+    let plain = encrypt(&[json], &[])?;
+    for algorithm in &["zstd", "deflate"] {
+        let mut cmd = Command::cargo_bin("encrypted-box")?;
+        cmd.arg("-p")
+            .arg("password")
+            .arg("-f")
+            .arg(json)
+            .arg("--compress")
+            .arg(algorithm);
+        let output = cmd.output()?;
+        assert!(output.status.success());
+        assert!(String::from_utf8(output.stderr)?.contains("warning: compressed boxes leak"));
+        assert!(output.stdout.len() < plain.len());
+
+        let mut cmd = Command::cargo_bin("encrypted-box")?;
+        cmd.arg("-d")
+            .arg("-p")
+            .arg("password")
+            .with_stdin()
+            .buffer(output.stdout)
+            .assert()
+            .success()
+            .stdout(format!("{}\n", json));
+    }
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-p")
+        .arg("password")
+        .arg("-f")
+        .arg(json)
+        .arg("--compress")
+        .arg("gzip");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("unknown compression 'gzip'"));
+
+    Ok(())
+}
+
+#[test]
+fn ignore_expiry() -> Result<(), Box<dyn std::error::Error>> {
+    let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&OpenSslVariants::Aes256Gcm));
+    ebb.set_password(String::from("password"))
+        .add_field("token")
+        .expires_at(UNIX_EPOCH + Duration::from_secs(1_000_000_000));
+    let sealed = ebb
+        .build()
+        .expect("could not build box")
+        .encrypt()
+        .expect("could not seal box");
+    let mut file = NamedTempFile::new()?;
+    file.write_all(&sealed)?;
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-d")
+        .arg("-p")
+        .arg("password")
+        .arg("-i")
+        .arg(file.path());
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("the box expired at 1000000000"));
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-d")
+        .arg("-p")
+        .arg("password")
+        .arg("-i")
+        .arg(file.path())
+        .arg("--ignore-expiry");
+    cmd.assert()
+        .success()
+        .stdout("token\n")
+        .stderr(predicate::str::contains("warning: opening boxes outside"));
+
+    Ok(())
+}
+
+#[test]
+fn migrate_v0_box() -> Result<(), Box<dyn std::error::Error>> {
+    // a version 0 box, as older versions printed it: the bare ciphertext
+    let variant = OpenSslVariants::Aes256Cbc;
+    let key = Kdf::Sha512.derive("password", &[], variant.key_length());
+    let v0 = DefaultScheme::new(&variant)
+        .encrypt(&key, b"legacy field")
+        .expect("could not seal box");
+    let mut file = NamedTempFile::new()?;
+    file.write_all(&encoding::encode(&v0, Encoding::Base64))?;
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-d")
+        .arg("-p")
+        .arg("password")
+        .arg("-i")
+        .arg(file.path())
+        .arg("--legacy-scheme")
+        .arg("aes-256-cbc");
+    cmd.assert()
+        .success()
+        .stdout("legacy field\n")
+        .stderr(predicate::str::contains("warning: opening a version 0 box"));
+
+    let migrated = NamedTempFile::new()?;
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("migrate")
+        .arg("-p")
+        .arg("password")
+        .arg("-i")
+        .arg(file.path())
+        .arg("-o")
+        .arg(migrated.path())
+        .arg("--legacy-scheme")
+        .arg("9");
+    cmd.assert().success();
+
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("-d")
+        .arg("-p")
+        .arg("password")
+        .arg("-i")
+        .arg(migrated.path());
+    cmd.assert().success().stdout("legacy field\n");
+
+    // the scheme of a version 0 box must be named, and be one of the originals
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("migrate")
+        .arg("-p")
+        .arg("password")
+        .arg("-i")
+        .arg(file.path());
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("--legacy-scheme"));
+    let mut cmd = Command::cargo_bin("encrypted-box")?;
+    cmd.arg("migrate")
+        .arg("-p")
+        .arg("password")
+        .arg("-i")
+        .arg(file.path())
+        .arg("--legacy-scheme")
+        .arg("aes-256-gcm");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "never sealed with scheme 'aes-256-gcm'",
+    ));
 
     Ok(())
 }
@@ -0,0 +1,257 @@
+// property-based round trips of the builder and of every scheme of every
+// enabled backend, over arbitrary fields, passwords and variants
+use encrypted_box::encrypted_box::EncryptedBox;
+use encrypted_box::encrypted_box_builder::EncryptedBoxBuilder;
+use encrypted_box::encryption_scheme::{DefaultScheme, EncryptionScheme, SecurityLevel};
+use encrypted_box::framing;
+use encrypted_box::header::Header;
+use encrypted_box::kdf::{Kdf, KdfParams};
+use encrypted_box::openssl_aes::defs::OpenSslVariants;
+use encrypted_box::policy::DecryptPolicy;
+use encrypted_box::subkeys::MASTER_KEY_LEN;
+use proptest::prelude::*;
+use proptest::sample::Index;
+use std::fmt::Debug;
+
+// the KDF is not under test, keep it cheap
+const TEST_KDF: Kdf = Kdf::Pbkdf2Sha256 { iterations: 1 };
+
+fn variant() -> impl Strategy<Value = OpenSslVariants> {
+    prop::sample::select(OpenSslVariants::iterator().copied().collect::<Vec<_>>())
+}
+
+fn authenticated_variant() -> impl Strategy<Value = OpenSslVariants> {
+    prop::sample::select(
+        OpenSslVariants::iterator()
+            .copied()
+            .filter(|v| v.is_authenticated())
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn field() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        4 => Just(Vec::new()),
+        8 => prop::collection::vec(any::<u8>(), 0..64),
+        8 => any::<String>().prop_map(String::into_bytes),
+        1 => prop::collection::vec(any::<u8>(), 64 * 1024..1024 * 1024),
+    ]
+}
+
+fn fields() -> impl Strategy<Value = Vec<Vec<u8>>> {
+    prop::collection::vec(field(), 0..8)
+}
+
+// the builder takes fields as strings
+fn text_fields() -> impl Strategy<Value = Vec<String>> {
+    let text = prop_oneof![
+        1 => Just(String::new()),
+        8 => any::<String>(),
+        1 => "[a-z]{65536,262144}",
+    ];
+    prop::collection::vec(text, 0..8)
+}
+
+fn key(variant: OpenSslVariants) -> impl Strategy<Value = Vec<u8>> {
+    prop::collection::vec(any::<u8>(), variant.key_length())
+}
+
+fn variant_and_key() -> impl Strategy<Value = (OpenSslVariants, Vec<u8>)> {
+    variant().prop_flat_map(|variant| (Just(variant), key(variant)))
+}
+
+// the length of the ciphertext of a plaintext of len bytes: the unauthenticated
+// modes use a fixed IV which is not part of it, ECB and CBC pad to whole
+// blocks, AEAD modes add a 12-byte nonce and a 16-byte tag, and
+// encrypt-then-MAC modes a 16-byte IV and a 32-byte tag
+fn ciphertext_len(variant: OpenSslVariants, len: usize) -> usize {
+    if let Some(base) = variant.etm_base() {
+        return 16 + ciphertext_len(base, len) + 32;
+    }
+    if variant.siv_base().is_some() {
+        return 16 + len;
+    }
+    match variant {
+        OpenSslVariants::Aes128Ecb
+        | OpenSslVariants::Aes192Ecb
+        | OpenSslVariants::Aes256Ecb
+        | OpenSslVariants::Aes128Cbc
+        | OpenSslVariants::Aes192Cbc
+        | OpenSslVariants::Aes256Cbc => (len / 16 + 1) * 16,
+        v if v.is_authenticated() => 12 + len + 16,
+        _ => len,
+    }
+}
+
+fn policy() -> DecryptPolicy {
+    let mut policy = DecryptPolicy::new();
+    policy
+        .min_security_level(SecurityLevel::Insecure)
+        .min_kdf_cost(1);
+    policy
+}
+
+fn seal(variant: OpenSslVariants, password: &str, fields: &[Vec<u8>]) -> Vec<u8> {
+    let kdf = KdfParams::new(TEST_KDF);
+    let key = kdf.derive(password, MASTER_KEY_LEN);
+    EncryptedBox::new(fields.to_vec(), key, kdf, DefaultScheme::new(&variant))
+        .encrypt()
+        .unwrap()
+}
+
+fn open(password: &str, sealed: &[u8]) -> encrypted_box::errors::Result<Vec<Vec<u8>>> {
+    let eb = EncryptedBox::<DefaultScheme>::decrypt(String::from(password), sealed, &policy())?;
+    Ok(eb.fields().to_vec())
+}
+
+fn scheme_round_trip<T>(
+    variant: OpenSslVariants,
+    key: &[u8],
+    msg: &[u8],
+) -> Result<(), TestCaseError>
+where
+    T: EncryptionScheme<Variant = OpenSslVariants>,
+    T::Error: Debug,
+{
+    let scheme = T::new(&variant);
+    let enc = scheme.encrypt(key, msg).unwrap();
+    prop_assert_eq!(enc.len(), ciphertext_len(variant, msg.len()));
+    prop_assert_eq!(scheme.decrypt(key, &enc).unwrap(), msg);
+    Ok(())
+}
+
+fn scheme_detects_bit_flip<T>(
+    variant: OpenSslVariants,
+    key: &[u8],
+    msg: &[u8],
+    bit: Index,
+) -> Result<(), TestCaseError>
+where
+    T: EncryptionScheme<Variant = OpenSslVariants>,
+    T::Error: Debug,
+{
+    let scheme = T::new(&variant);
+    let mut enc = scheme.encrypt(key, msg).unwrap();
+    let bit = bit.index(enc.len() * 8);
+    enc[bit / 8] ^= 1 << (bit % 8);
+    prop_assert!(scheme.decrypt(key, &enc).is_err());
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn box_round_trip(variant in variant(), password in any::<String>(), fields in fields()) {
+        let sealed = seal(variant, &password, &fields);
+        let opened = open(&password, &sealed).unwrap();
+        // every field keeps its boundaries, not just the concatenation
+        let lengths: Vec<_> = opened.iter().map(Vec::len).collect();
+        prop_assert_eq!(lengths, fields.iter().map(Vec::len).collect::<Vec<_>>());
+        prop_assert_eq!(opened, fields);
+    }
+
+    #[test]
+    fn builder_round_trip(
+        variant in variant(),
+        password in any::<String>(),
+        fields in text_fields(),
+    ) {
+        let mut ebb = EncryptedBoxBuilder::new(DefaultScheme::new(&variant));
+        let eb = ebb
+            .set_kdf(TEST_KDF)
+            .set_password(password.clone())
+            .allow_insecure()
+            .add_fields(&fields)
+            .build()
+            .unwrap();
+        let expected: Vec<_> = fields.iter().map(|f| f.as_bytes().to_vec()).collect();
+        prop_assert_eq!(eb.fields(), &expected[..]);
+        let opened = open(&password, &eb.encrypt().unwrap()).unwrap();
+        prop_assert_eq!(opened, expected);
+    }
+
+    #[test]
+    fn box_length(variant in variant(), fields in fields()) {
+        let sealed = seal(variant, "password", &fields);
+        let (header, ctext) = Header::parse(&sealed).unwrap();
+        prop_assert_eq!(header.encoded_len(), sealed.len() - ctext.len());
+        prop_assert_eq!(
+            ctext.len(),
+            ciphertext_len(variant, framing::encoded_len(&fields))
+        );
+    }
+
+    #[test]
+    fn box_wrong_password_fails_authenticated(
+        variant in authenticated_variant(),
+        password in any::<String>(),
+        other in any::<String>(),
+        fields in fields(),
+    ) {
+        prop_assume!(password != other);
+        let sealed = seal(variant, &password, &fields);
+        prop_assert!(open(&other, &sealed).is_err());
+    }
+
+    #[test]
+    fn box_bit_flip_detected_authenticated(
+        variant in authenticated_variant(),
+        fields in fields(),
+        bit in any::<Index>(),
+    ) {
+        // the header is not authenticated (the scheme identifier is case
+        // insensitive, and the salt only matters to salted KDFs), so flip a
+        // bit of the scheme's ciphertext
+        let mut sealed = seal(variant, "password", &fields);
+        let (_, ctext) = Header::parse(&sealed).unwrap();
+        let begin = sealed.len() - ctext.len();
+        let bit = bit.index(ctext.len() * 8);
+        sealed[begin + bit / 8] ^= 1 << (bit % 8);
+        prop_assert!(open("password", &sealed).is_err());
+    }
+}
+
+#[cfg(feature = "openssl")]
+mod openssl {
+    use super::*;
+    use encrypted_box::openssl_aes::wrapper::OpensslAesWrapper;
+
+    proptest! {
+        #[test]
+        fn scheme_round_trip((variant, key) in variant_and_key(), msg in field()) {
+            super::scheme_round_trip::<OpensslAesWrapper>(variant, &key, &msg)?;
+        }
+
+        #[test]
+        fn scheme_bit_flip_detected_authenticated(
+            (variant, key) in authenticated_variant().prop_flat_map(|v| (Just(v), key(v))),
+            msg in field(),
+            bit in any::<Index>(),
+        ) {
+            scheme_detects_bit_flip::<OpensslAesWrapper>(variant, &key, &msg, bit)?;
+        }
+    }
+}
+
+#[cfg(feature = "rustcrypto")]
+mod rustcrypto {
+    use super::*;
+    use encrypted_box::rustcrypto_aes::wrapper::RustCryptoAesWrapper;
+
+    proptest! {
+        #[test]
+        fn scheme_round_trip((variant, key) in variant_and_key(), msg in field()) {
+            super::scheme_round_trip::<RustCryptoAesWrapper>(variant, &key, &msg)?;
+        }
+
+        #[test]
+        fn scheme_bit_flip_detected_authenticated(
+            (variant, key) in authenticated_variant().prop_flat_map(|v| (Just(v), key(v))),
+            msg in field(),
+            bit in any::<Index>(),
+        ) {
+            scheme_detects_bit_flip::<RustCryptoAesWrapper>(variant, &key, &msg, bit)?;
+        }
+    }
+}